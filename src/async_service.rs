@@ -0,0 +1,112 @@
+//! Adapters of the sync services to [`AsyncCommandHandler`] and
+//! [`AsyncQueryHandler`], e.g.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use intl_svc_test_task::commands::AsyncCommandHandler;
+//! use intl_svc_test_task::cqrs::future::{block_on, Offload, Spawner};
+//! use intl_svc_test_task::clicks::Clicks;
+//! use intl_svc_test_task::cqrs::mem_store::MemEventStore;
+//! use intl_svc_test_task::gen::SimplestSlugGenerator;
+//! use intl_svc_test_task::sync::SyncUrlShortenerService;
+//! use intl_svc_test_task::{Stats, Url};
+//!
+//! let service = Arc::new(SyncUrlShortenerService::new(
+//!     Box::new(MemEventStore::<Stats>::new()),
+//!     Box::new(MemEventStore::<Clicks>::new()),
+//!     Box::new(SimplestSlugGenerator),
+//! ));
+//! let service = Offload::shared(service, Spawner::default());
+//! let link = block_on(service.handle_create_short_link(Url::from("https://example.com"), None)).unwrap();
+//! ```
+
+use std::sync::Arc;
+
+use crate::commands::{AsyncCommandHandler, SyncCommandHandler};
+use crate::cqrs::future::{BoxFuture, Inline, Offload};
+use crate::queries::{AsyncQueryHandler, QueryHandler};
+use crate::{ShortLink, ShortenerError, Slug, Stats, Url};
+
+impl<S: SyncCommandHandler + Sync> AsyncCommandHandler for Inline<S> {
+    fn handle_create_short_link(&self, url: Url, slug: Option<Slug>) -> BoxFuture<'_, Result<ShortLink, ShortenerError>> {
+        Box::pin(async move { self.0.handle_create_short_link(url, slug) })
+    }
+
+    fn handle_redirect(&self, slug: Slug) -> BoxFuture<'_, Result<ShortLink, ShortenerError>> {
+        Box::pin(async move { self.0.handle_redirect(slug) })
+    }
+}
+
+impl<S: QueryHandler + Sync> AsyncQueryHandler for Inline<S> {
+    fn get_stats(&self, slug: Slug) -> BoxFuture<'_, Result<Stats, ShortenerError>> {
+        Box::pin(async move { self.0.get_stats(slug) })
+    }
+}
+
+impl<S: SyncCommandHandler + Send + Sync + 'static> AsyncCommandHandler for Offload<S> {
+    fn handle_create_short_link(&self, url: Url, slug: Option<Slug>) -> BoxFuture<'_, Result<ShortLink, ShortenerError>> {
+        let service = Arc::clone(&self.inner);
+        Box::pin(self.spawner.run(move || service.handle_create_short_link(url, slug)))
+    }
+
+    fn handle_redirect(&self, slug: Slug) -> BoxFuture<'_, Result<ShortLink, ShortenerError>> {
+        let service = Arc::clone(&self.inner);
+        Box::pin(self.spawner.run(move || service.handle_redirect(slug)))
+    }
+}
+
+impl<S: QueryHandler + Send + Sync + 'static> AsyncQueryHandler for Offload<S> {
+    fn get_stats(&self, slug: Slug) -> BoxFuture<'_, Result<Stats, ShortenerError>> {
+        let service = Arc::clone(&self.inner);
+        Box::pin(self.spawner.run(move || service.get_stats(slug)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cqrs::future::{block_on, Spawner};
+    use crate::cqrs::mem_store::MemEventStore;
+    use crate::gen::SimplestSlugGenerator;
+    use crate::sync::SyncUrlShortenerService;
+
+    fn create_service() -> SyncUrlShortenerService {
+        SyncUrlShortenerService::new(Box::new(MemEventStore::<Stats>::new()), Box::new(MemEventStore::new()), Box::new(SimplestSlugGenerator))
+    }
+
+    async fn create_and_redirect<S: AsyncCommandHandler + AsyncQueryHandler>(service: &S) -> u64 {
+        let link = service.handle_create_short_link(Url::from("https://example.com"), None).await.unwrap();
+        for _ in 0..3 {
+            service.handle_redirect(link.slug.clone()).await.unwrap();
+        }
+        assert_eq!(
+            service.handle_create_short_link(Url::from("https://example.com"), Some(link.slug.clone())).await,
+            Err(ShortenerError::SlugAlreadyInUse),
+        );
+        assert_eq!(service.get_stats(Slug::from("missing")).await, Err(ShortenerError::SlugNotFound));
+        service.get_stats(link.slug).await.unwrap().redirects
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(block_on(create_and_redirect(&Inline(create_service()))), 3);
+    }
+
+    #[test]
+    fn test_offload() {
+        let service = Offload::new(create_service(), Spawner::default());
+        assert_eq!(block_on(create_and_redirect(&service)), 3);
+    }
+
+    #[test]
+    fn test_offload_concurrent_futures() {
+        let service = Offload::new(create_service(), Spawner::default());
+        let link = block_on(service.handle_create_short_link(Url::from("https://example.com"), None)).unwrap();
+        // all the redirects are in flight at once
+        let redirects = (0..50).map(|_| service.handle_redirect(link.slug.clone())).collect::<Vec<_>>();
+        for redirect in redirects {
+            block_on(redirect).unwrap();
+        }
+        assert_eq!(block_on(service.get_stats(link.slug)).unwrap().redirects, 50);
+    }
+}
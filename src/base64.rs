@@ -5,28 +5,30 @@ const UPPERCASEOFFSET: i8 = 65;
 const LOWERCASEOFFSET: i8 = 71;
 const DIGITOFFSET: i8 = -4;
 
+/// Whether the encoded data is padded with `=` up to a multiple of 4 symbols
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    Padded,
+    Unpadded,
+}
+
 pub trait Alphabet {
     const SIXTY_SECOND_SYMBOL: i8;
     const SIXTY_THIRD_SYMBOL: i8;
 
-    fn get_char_for_index(index: u8) -> Option<char> {
-        let index = index as i8;
-        
-        let ascii_index = match index {
-            0..=25 => index + UPPERCASEOFFSET,  // A-Z
-            26..=51 => index + LOWERCASEOFFSET, // a-z
-            52..=61 => index + DIGITOFFSET,     // 0-9
-            62 => Self::SIXTY_SECOND_SYMBOL,    // + or -
-            63 => Self::SIXTY_THIRD_SYMBOL,     // / or _
-
-            _ => return None,
-        } as u8;
+    /// Lookup table of the symbols (ascii) by their index
+    const ENCODE_TABLE: [u8; 64] = encode_table(Self::SIXTY_SECOND_SYMBOL as u8, Self::SIXTY_THIRD_SYMBOL as u8);
 
-        Some(ascii_index as char)
+    #[allow(dead_code)]
+    fn get_char_for_index(index: u8) -> Option<char> {
+        Self::ENCODE_TABLE.get(index as usize).map(|&symbol| symbol as char)
     }
 
-    #[allow(dead_code)]
     fn get_index_for_char(character: char) -> Option<u8> {
+        if !character.is_ascii() {
+            return None;
+        }
         let character = character as i8;
         let base64_index = match character {
             65..=90 => character - UPPERCASEOFFSET,  // A-Z
@@ -46,6 +48,7 @@ pub trait Alphabet {
 }
 
 pub struct Std;
+#[allow(dead_code)]
 pub struct Url;
 
 impl Alphabet for Std {
@@ -58,6 +61,14 @@ impl Std {
     pub fn encode(data: &[u8]) -> String {
         encode::<Self>(data)
     }
+
+    pub fn encode_unpadded(data: &[u8]) -> String {
+        encode_with::<Self>(data, Padding::Unpadded)
+    }
+
+    pub fn decode(data: &str) -> Result<Vec<u8>, DecodeError> {
+        decode::<Self>(data)
+    }
 }
 
 impl Alphabet for Url {
@@ -65,52 +76,204 @@ impl Alphabet for Url {
     const SIXTY_THIRD_SYMBOL: i8 = '_' as i8;
 }
 
+#[allow(dead_code)]
 impl Url {
     pub fn encode(data: &[u8]) -> String {
         encode::<Self>(data)
     }
+
+    pub fn encode_unpadded(data: &[u8]) -> String {
+        encode_with::<Self>(data, Padding::Unpadded)
+    }
+
+    pub fn decode(data: &str) -> Result<Vec<u8>, DecodeError> {
+        decode::<Self>(data)
+    }
+}
+
+/// Errors of the strict base64 decoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The symbol at the position (in chars) is not a part of the alphabet
+    InvalidSymbol { position: usize, symbol: char },
+    /// The length of the data can't be produced by the encoder
+    InvalidLength,
+    /// Padding is misplaced or has a wrong length
+    InvalidPadding,
+    /// Unused bits of the last symbol are not zeroes, i.e. the same data has
+    /// another (canonical) encoding
+    NonCanonicalTrailingBits,
+}
+
+const fn encode_table(sixty_second: u8, sixty_third: u8) -> [u8; 64] {
+    let mut table = [0u8; 64];
+    let mut index = 0;
+    while index < 64 {
+        table[index] = match index as u8 {
+            i @ 0..=25 => i + UPPERCASEOFFSET as u8,            // A-Z
+            i @ 26..=51 => i + LOWERCASEOFFSET as u8,           // a-z
+            i @ 52..=61 => i - DIGITOFFSET.unsigned_abs(),      // 0-9
+            62 => sixty_second,                                 // + or -
+            _ => sixty_third,                                   // / or _
+        };
+        index += 1;
+    }
+    table
 }
 
+/// Length of the encoded data in symbols
+pub const fn encoded_len(data_len: usize, padding: Padding) -> usize {
+    match padding {
+        Padding::Padded => data_len.div_ceil(3) * 4,
+        Padding::Unpadded => (data_len * 4).div_ceil(3),
+    }
+}
+
+/// Encodes the data with padding
 pub fn encode<A: Alphabet + ?Sized>(data: &[u8]) -> String {
-    let encoded = data
-        .chunks(3)
-        .map(split)
-        .flat_map(|chunk| encode_chunk::<A>(chunk));
-    String::from_iter(encoded)
-}
-
-fn split(chunk: &[u8]) -> Vec<u8> {
-    match chunk.len() {
-        1 => vec![
-            &chunk[0] >> 2,
-            (&chunk[0] & 0b00000011) << 4
-        ],
-
-        2 => vec![
-            &chunk[0] >> 2,
-            (&chunk[0] & 0b00000011) << 4 | &chunk[1] >> 4,
-            (&chunk[1] & 0b00001111) << 2,
-        ],
-
-        3 => vec![
-            &chunk[0] >> 2,
-            (&chunk[0] & 0b00000011) << 4 | &chunk[1] >> 4,
-            (&chunk[1] & 0b00001111) << 2 | &chunk[2] >> 6,
-            &chunk[2] & 0b00111111
-        ],
+    encode_with::<A>(data, Padding::Padded)
+}
+
+pub fn encode_with<A: Alphabet + ?Sized>(data: &[u8], padding: Padding) -> String {
+    let mut out = String::with_capacity(encoded_len(data.len(), padding));
+    // unwrap: writing to a String never fails
+    encode_to_fmt::<A, _>(data, padding, &mut out).unwrap();
+    out
+}
+
+/// Encodes the data into the provided buffer and returns the encoded part of
+/// it, or `None` if the buffer is shorter than [`encoded_len`].
+pub fn encode_to_slice<'a, A: Alphabet + ?Sized>(
+    data: &[u8],
+    padding: Padding,
+    out: &'a mut [u8],
+) -> Option<&'a str> {
+    let out = out.get_mut(..encoded_len(data.len(), padding))?;
+    let mut written = 0;
+    for chunk in data.chunks(3) {
+        let (symbols, count) = encode_chunk::<A>(chunk, padding);
+        out[written..written + count].copy_from_slice(&symbols[..count]);
+        written += count;
+    }
+    // SAFETY: all the symbols of the alphabet and the padding are ascii
+    Some(unsafe { core::str::from_utf8_unchecked(out) })
+}
+
+/// Encodes the data into the writer, using a fixed size buffer on the stack
+pub fn encode_to_fmt<A: Alphabet + ?Sized, W: core::fmt::Write + ?Sized>(
+    data: &[u8],
+    padding: Padding,
+    out: &mut W,
+) -> core::fmt::Result {
+    const BLOCK_LEN: usize = 3 * 256; // a multiple of 3, so only the last block can be padded
+    let mut buf = [0u8; encoded_len(BLOCK_LEN, Padding::Padded)];
+    for block in data.chunks(BLOCK_LEN) {
+        // unwrap: the buffer fits any block
+        out.write_str(encode_to_slice::<A>(block, padding, &mut buf).unwrap())?;
+    }
+    Ok(())
+}
+
+fn encode_chunk<A: Alphabet + ?Sized>(chunk: &[u8], padding: Padding) -> ([u8; 4], usize) {
+    let table = &A::ENCODE_TABLE;
+    let pad = A::get_padding_char() as u8;
+    let (symbols, count) = match *chunk {
+        [a] => ([
+            table[(a >> 2) as usize],
+            table[((a & 0b00000011) << 4) as usize],
+            pad,
+            pad,
+        ], 2),
+
+        [a, b] => ([
+            table[(a >> 2) as usize],
+            table[((a & 0b00000011) << 4 | b >> 4) as usize],
+            table[((b & 0b00001111) << 2) as usize],
+            pad,
+        ], 3),
+
+        [a, b, c] => ([
+            table[(a >> 2) as usize],
+            table[((a & 0b00000011) << 4 | b >> 4) as usize],
+            table[((b & 0b00001111) << 2 | c >> 6) as usize],
+            table[(c & 0b00111111) as usize],
+        ], 4),
 
         _ => unreachable!()
+    };
+    match padding {
+        Padding::Padded => (symbols, 4),
+        Padding::Unpadded => (symbols, count),
     }
 }
 
-fn encode_chunk<A: Alphabet + ?Sized>(chunk: Vec<u8>) -> Vec<char> {
-    let mut out = vec![A::get_padding_char(); 4];
-    for i in 0..chunk.len() {
-        if let Some(chr) = A::get_char_for_index(chunk[i]) {
-            out[i] = chr;
+/// Decodes both padded and unpadded data. Padding, if present, must be complete.
+pub fn decode<A: Alphabet + ?Sized>(data: &str) -> Result<Vec<u8>, DecodeError> {
+    let padding_char = A::get_padding_char();
+    let symbols = data.trim_end_matches(padding_char);
+    let symbols_count = symbols.chars().count();
+    let padding_count = data.len() - symbols.len();
+
+    match (symbols_count % 4, padding_count) {
+        (1, _) => return Err(DecodeError::InvalidLength),
+        (_, 0) | (2, 2) | (3, 1) => {}
+        _ => return Err(DecodeError::InvalidPadding),
+    }
+
+    let mut indices = Vec::with_capacity(symbols_count);
+    for (position, symbol) in symbols.chars().enumerate() {
+        match A::get_index_for_char(symbol) {
+            Some(index) => indices.push(index),
+            None if symbol == padding_char => return Err(DecodeError::InvalidPadding),
+            None => return Err(DecodeError::InvalidSymbol { position, symbol }),
+        }
+    }
+
+    let mut out = Vec::with_capacity(symbols_count * 3 / 4);
+    for chunk in indices.chunks(4) {
+        join(chunk, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn join(chunk: &[u8], out: &mut Vec<u8>) -> Result<(), DecodeError> {
+    match *chunk {
+        [a, b] => {
+            if b & 0b00001111 != 0 {
+                return Err(DecodeError::NonCanonicalTrailingBits);
+            }
+            out.push(a << 2 | b >> 4);
+        }
+
+        [a, b, c] => {
+            if c & 0b00000011 != 0 {
+                return Err(DecodeError::NonCanonicalTrailingBits);
+            }
+            out.push(a << 2 | b >> 4);
+            out.push(b << 4 | c >> 2);
+        }
+
+        [a, b, c, d] => {
+            out.push(a << 2 | b >> 4);
+            out.push(b << 4 | c >> 2);
+            out.push(c << 6 | d);
+        }
+
+        _ => unreachable!()
+    }
+    Ok(())
+}
+
+impl core::error::Error for DecodeError {}
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSymbol { position, symbol } => write!(f, "invalid symbol {symbol:?} at position {position}"),
+            Self::InvalidLength => write!(f, "invalid length"),
+            Self::InvalidPadding => write!(f, "invalid padding"),
+            Self::NonCanonicalTrailingBits => write!(f, "non-canonical trailing bits"),
         }
     }
-    out
 }
 
 #[cfg(test)]
@@ -124,4 +287,170 @@ mod test {
         assert_eq!(Url::encode("eightsym".as_bytes()), "ZWlnaHRzeW0=");
         assert_eq!(Std::encode("eightsym".as_bytes()), "ZWlnaHRzeW0=");
     }
+
+    #[test]
+    fn test_encode_unpadded() {
+        assert_eq!(Url::encode_unpadded("eightsym".as_bytes()), "ZWlnaHRzeW0");
+        assert_eq!(Std::encode_unpadded("y".as_bytes()), "eQ");
+        assert_eq!(Std::encode_unpadded(&[0xfb, 0xff]), "+/8");
+        assert_eq!(Url::encode_unpadded(&[0xfb, 0xff]), "-_8");
+        assert_eq!(Url::encode_unpadded(&[]), "");
+    }
+
+    #[test]
+    fn test_encode_to_slice() {
+        let mut buf = [0u8; 8];
+        assert_eq!(encode_to_slice::<Url>(b"sixsym", Padding::Unpadded, &mut buf), Some("c2l4c3lt"));
+        assert_eq!(encode_to_slice::<Url>(b"five5", Padding::Padded, &mut buf), Some("Zml2ZTU="));
+        assert_eq!(encode_to_slice::<Url>(b"five5", Padding::Unpadded, &mut buf), Some("Zml2ZTU"));
+        assert_eq!(encode_to_slice::<Url>(b"sevensy", Padding::Unpadded, &mut buf), None);
+    }
+
+    #[test]
+    fn test_encode_matches_legacy() {
+        let data = (0..=255).cycle().take(3000).collect::<Vec<u8>>();
+        for len in (0..16).chain([767, 768, 769, 1536, 3000]) {
+            assert_eq!(Std::encode(&data[..len]), legacy::encode::<Std>(&data[..len]));
+            assert_eq!(Url::encode(&data[..len]), legacy::encode::<Url>(&data[..len]));
+            assert_eq!(
+                Url::encode_unpadded(&data[..len]),
+                legacy::encode::<Url>(&data[..len]).trim_end_matches('='),
+            );
+        }
+    }
+
+    /// Compares the encoder with the previous implementation:
+    /// `cargo test --release bench_encode -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_encode() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        fn bench(name: &str, iterations: u32, f: impl Fn() -> usize) {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                black_box(f());
+            }
+            println!("{name:>32}: {:>10.1} ns/iter", start.elapsed().as_nanos() as f64 / iterations as f64);
+        }
+
+        let slug_bytes = [0x12, 0x34, 0x56, 0x78, 0x00, 0x01];
+        let kilobyte = (0..=255).cycle().take(1024).collect::<Vec<u8>>();
+        for (data, iterations) in [(&slug_bytes[..], 1_000_000), (&kilobyte[..], 20_000)] {
+            println!("{} bytes:", data.len());
+            bench("legacy::encode", iterations, || legacy::encode::<Url>(black_box(data)).len());
+            bench("encode", iterations, || encode::<Url>(black_box(data)).len());
+            bench("encode_to_fmt", iterations, || {
+                let mut out = String::with_capacity(2048);
+                encode_to_fmt::<Url, _>(black_box(data), Padding::Unpadded, &mut out).unwrap();
+                out.len()
+            });
+            bench("encode_to_slice", iterations, || {
+                let mut buf = [0u8; 2048];
+                encode_to_slice::<Url>(black_box(data), Padding::Unpadded, &mut buf).unwrap().len()
+            });
+        }
+    }
+
+    /// The previous implementation, allocating on every chunk
+    mod legacy {
+        use super::super::{Alphabet, DIGITOFFSET, LOWERCASEOFFSET, UPPERCASEOFFSET};
+
+        pub fn encode<A: Alphabet + ?Sized>(data: &[u8]) -> String {
+            let encoded = data
+                .chunks(3)
+                .map(split)
+                .flat_map(|chunk| encode_chunk::<A>(chunk));
+            String::from_iter(encoded)
+        }
+
+        fn split(chunk: &[u8]) -> Vec<u8> {
+            match chunk.len() {
+                1 => vec![
+                    chunk[0] >> 2,
+                    (chunk[0] & 0b00000011) << 4
+                ],
+
+                2 => vec![
+                    chunk[0] >> 2,
+                    (chunk[0] & 0b00000011) << 4 | chunk[1] >> 4,
+                    (chunk[1] & 0b00001111) << 2,
+                ],
+
+                3 => vec![
+                    chunk[0] >> 2,
+                    (chunk[0] & 0b00000011) << 4 | chunk[1] >> 4,
+                    (chunk[1] & 0b00001111) << 2 | chunk[2] >> 6,
+                    chunk[2] & 0b00111111
+                ],
+
+                _ => unreachable!()
+            }
+        }
+
+        fn encode_chunk<A: Alphabet + ?Sized>(chunk: Vec<u8>) -> Vec<char> {
+            let mut out = vec![A::get_padding_char(); 4];
+            for i in 0..chunk.len() {
+                if let Some(chr) = get_char_for_index::<A>(chunk[i]) {
+                    out[i] = chr;
+                }
+            }
+            out
+        }
+
+        fn get_char_for_index<A: Alphabet + ?Sized>(index: u8) -> Option<char> {
+            let index = index as i8;
+            
+            let ascii_index = match index {
+                0..=25 => index + UPPERCASEOFFSET,  // A-Z
+                26..=51 => index + LOWERCASEOFFSET, // a-z
+                52..=61 => index + DIGITOFFSET,     // 0-9
+                62 => A::SIXTY_SECOND_SYMBOL,       // + or -
+                63 => A::SIXTY_THIRD_SYMBOL,        // / or _
+
+                _ => return None,
+            } as u8;
+
+            Some(ascii_index as char)
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Url::decode("Zmx1ZmZ5IHBhbmNha2Vz").unwrap(), b"fluffy pancakes");
+        assert_eq!(Std::decode("ZWlnaHRzeW0=").unwrap(), b"eightsym");
+        assert_eq!(Std::decode("ZWlnaHRzeW0").unwrap(), b"eightsym");
+        assert_eq!(Std::decode("c2l4c3k=").unwrap(), b"sixsy");
+        assert_eq!(Std::decode("c2l4c3k").unwrap(), b"sixsy");
+        assert_eq!(Url::decode("eQ==").unwrap(), b"y");
+        assert_eq!(Url::decode("eQ").unwrap(), b"y");
+        assert_eq!(Url::decode("").unwrap(), b"");
+        assert_eq!(Url::decode("-_8").unwrap(), [0xfb, 0xff]);
+        assert_eq!(Std::decode("+/8").unwrap(), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let data = (0..=255).collect::<Vec<u8>>();
+        for len in 0..data.len() {
+            assert_eq!(Url::decode(&Url::encode(&data[..len])).unwrap(), &data[..len]);
+            assert_eq!(Std::decode(&Std::encode(&data[len..])).unwrap(), &data[len..]);
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Url::decode("+/8"), Err(DecodeError::InvalidSymbol { position: 0, symbol: '+' }));
+        assert_eq!(Std::decode("ab_8"), Err(DecodeError::InvalidSymbol { position: 2, symbol: '_' }));
+        assert_eq!(Std::decode("abЁ8"), Err(DecodeError::InvalidSymbol { position: 2, symbol: 'Ё' }));
+        assert_eq!(Std::decode("eQ="), Err(DecodeError::InvalidPadding));
+        assert_eq!(Std::decode("eQ==="), Err(DecodeError::InvalidPadding));
+        assert_eq!(Std::decode("eQ=Q"), Err(DecodeError::InvalidPadding));
+        assert_eq!(Std::decode("ZWln="), Err(DecodeError::InvalidPadding));
+        assert_eq!(Std::decode("ZWlna"), Err(DecodeError::InvalidLength));
+        assert_eq!(Std::decode("ZWlna==="), Err(DecodeError::InvalidLength));
+        assert_eq!(Std::decode("eR=="), Err(DecodeError::NonCanonicalTrailingBits));
+        assert_eq!(Std::decode("c2l4c3l="), Err(DecodeError::NonCanonicalTrailingBits));
+    }
 }
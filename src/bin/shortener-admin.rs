@@ -0,0 +1,269 @@
+//! Admin tool of the shortener, works over a store file of [`FileEventStore`]
+//! with the streams of both the links and their clicks.
+//!
+//! ```text
+//! shortener-admin --store links.jsonl create <url> [slug]
+//! shortener-admin --store links.jsonl stats <slug>
+//! shortener-admin --store links.jsonl stats-at <time|#position>
+//! shortener-admin --store links.jsonl events <slug>
+//! shortener-admin --store links.jsonl replay <slug> <index>
+//! shortener-admin --store links.jsonl check
+//! shortener-admin --store links.jsonl compact <threshold> <keep>
+//! shortener-admin --store links.jsonl export <file> [from to]
+//! shortener-admin --store links.jsonl import <file> [--dry-run]
+//! ```
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::ops::Bound;
+use std::process::ExitCode;
+
+use intl_svc_test_task::clicks::Clicks;
+use intl_svc_test_task::commands::CommandHandler;
+use intl_svc_test_task::cqrs::export::{Export, ExportFile, ImportMode, ImportReport};
+use intl_svc_test_task::cqrs::file_store::{Compaction, FileBackend, FileEventStore};
+use intl_svc_test_task::cqrs::history::AsOf;
+use intl_svc_test_task::cqrs::store::{EventIndex, EventStore, EventStoreError, KeyMode};
+use intl_svc_test_task::cqrs::{Aggregate, DomainEvent};
+use intl_svc_test_task::gen::SimplestSlugGenerator;
+use intl_svc_test_task::json::ToJson;
+use intl_svc_test_task::queries::{HistoryQueryHandler, QueryHandler};
+use intl_svc_test_task::{Slug, SlugRef, Stats, Url, UrlShortenerService};
+
+const USAGE: &str = "\
+usage: shortener-admin --store FILE <command>
+
+commands:
+    create <url> [slug]     creates a short link
+    stats <slug>            prints the stats of the link
+    stats-at <time|#position>
+                            prints the stats of all the links as of the UTC time (e.g.
+                            2026-01-01T12:00) or before the position in the store log
+    events <slug>           prints the event streams of the link and its clicks with the indices
+    replay <slug> <index>   prints the state of the link after the event at the index
+    check                   checks the consistency of all the streams of the store
+    compact <threshold> <keep>
+                            moves the redirects of the links with more than `threshold` of
+                            them in the store to its archive file, except the last `keep`
+    export <file> [from to] exports the streams of the links with the slugs in [from, to)
+    import <file>           imports the streams which don't exist, unless some of them conflict
+                            or are inconsistent; only reports them with --dry-run";
+
+enum Command {
+    Create { url: String, slug: Option<String> },
+    Stats { slug: String },
+    StatsAt { as_of: AsOf },
+    Events { slug: String },
+    Replay { slug: String, index: EventIndex },
+    Check,
+    Compact { compaction: Compaction },
+    Export { path: String, from: Option<String>, to: Option<String> },
+    Import { path: String, mode: ImportMode },
+}
+
+fn parse_args() -> Result<(String, Command), String> {
+    let mut store = None;
+    let mut dry_run = false;
+    let mut positional = Vec::new();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--store" => store = Some(iter.next().ok_or("missing value of --store")?),
+            "--dry-run" => dry_run = true,
+            "--help" | "-h" => return Err(USAGE.into()),
+            _ => positional.push(arg),
+        }
+    }
+    let store = store.ok_or(format!("missing --store\n{USAGE}"))?;
+
+    let command = match positional.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["create", url] => Command::Create { url: url.to_string(), slug: None },
+        ["create", url, slug] => Command::Create { url: url.to_string(), slug: Some(slug.to_string()) },
+        ["stats", slug] => Command::Stats { slug: slug.to_string() },
+        ["stats-at", as_of] => Command::StatsAt { as_of: as_of.parse()? },
+        ["events", slug] => Command::Events { slug: slug.to_string() },
+        ["replay", slug, index] => Command::Replay {
+            slug: slug.to_string(),
+            index: index.parse().map_err(|e| format!("invalid index: {e}"))?,
+        },
+        ["check"] => Command::Check,
+        ["compact", threshold, keep] => Command::Compact {
+            compaction: Compaction {
+                threshold: threshold.parse().map_err(|e| format!("invalid threshold: {e}"))?,
+                keep: keep.parse().map_err(|e| format!("invalid keep: {e}"))?,
+            },
+        },
+        ["export", path] => Command::Export { path: path.to_string(), from: None, to: None },
+        ["export", path, from, to] => Command::Export {
+            path: path.to_string(),
+            from: Some(from.to_string()),
+            to: Some(to.to_string()),
+        },
+        ["import", path] => Command::Import {
+            path: path.to_string(),
+            mode: match dry_run {
+                true => ImportMode::DryRun,
+                false => ImportMode::Commit,
+            },
+        },
+        _ => return Err(USAGE.into()),
+    };
+    Ok((store, command))
+}
+
+struct Stores {
+    links: FileEventStore<Stats>,
+    clicks: FileEventStore<Clicks>,
+}
+
+impl Stores {
+    fn open(path: &str) -> Result<Self, String> {
+        let open_err = |e: EventStoreError| format!("failed to open {path}: {e}");
+        let backend = FileBackend::open(path).map_err(open_err)?;
+        let links = FileEventStore::with_backend(backend.clone(), KeyMode::Exact).map_err(open_err)?;
+        let clicks = FileEventStore::with_backend(backend, KeyMode::Exact).map_err(open_err)?;
+        Ok(Self { links, clicks })
+    }
+
+    fn into_service(self) -> UrlShortenerService {
+        UrlShortenerService::new(Box::new(self.links), Box::new(self.clicks), Box::new(SimplestSlugGenerator))
+    }
+}
+
+fn run(stores: Stores, command: Command) -> Result<(), String> {
+    match command {
+        Command::Create { url, slug } => {
+            let mut service = stores.into_service();
+            let link = service
+                .handle_create_short_link(Url(url), slug.map(Slug))
+                .map_err(|e| format!("{e:?}"))?;
+            println!("{}", link.to_json());
+        }
+        Command::Stats { slug } => {
+            let service = stores.into_service();
+            let stats = service.get_stats(Slug(slug)).map_err(|e| format!("{e:?}"))?;
+            println!("{}", stats.to_json());
+        }
+        Command::StatsAt { as_of } => {
+            let service = stores.into_service();
+            for stats in service.get_stats_as_of(as_of).map_err(|e| e.to_string())? {
+                println!("{}", stats.to_json());
+            }
+        }
+        Command::Events { slug } => {
+            let events = stores.links.fetch_from(SlugRef::new(&slug), 0).map_err(|e| e.to_string())?;
+            for event in events.events() {
+                println!("{}\t{}\t{}", event.index(), event.event().event_name(), event.event().to_json());
+            }
+            let events = match stores.clicks.fetch_from(&events.snapshot().aggregate().link.slug, 0) {
+                Ok(events) => events,
+                Err(EventStoreError::AggregateIsNotExist) => return Ok(()),
+                Err(e) => return Err(e.to_string()),
+            };
+            for event in events.events() {
+                println!("{}\t{}\t{}", event.index(), event.event().event_name(), event.event().to_json());
+            }
+        }
+        Command::Replay { slug, index } => {
+            let events = stores.links.fetch_from(SlugRef::new(&slug), index).map_err(|e| e.to_string())?;
+            let snapshot = events.snapshot_at(index).ok_or(format!("no event at index {index}"))?;
+            println!("{}", snapshot.aggregate().to_json());
+        }
+        Command::Check => {
+            let mut inconsistent = stores.links.check_consistency().map_err(|e| e.to_string())?;
+            inconsistent.extend(stores.clicks.check_consistency().map_err(|e| e.to_string())?);
+            for (slug, e) in &inconsistent {
+                println!("{slug}\t{e}");
+            }
+            if !inconsistent.is_empty() {
+                return Err(format!("{} inconsistent stream(s)", inconsistent.len()));
+            }
+            let streams = stores.links.aggregate_ids().map_err(|e| e.to_string())?.len()
+                + stores.clicks.aggregate_ids().map_err(|e| e.to_string())?.len();
+            eprintln!("{streams} stream(s) are consistent");
+        }
+        Command::Compact { compaction } => {
+            // the links keep their redirect settings in their own streams, only the clicks are compacted
+            let compacted = stores.clicks.compact(compaction).map_err(|e| e.to_string())?;
+            eprintln!("{} stream(s) compacted\t{} event(s) archived", compacted.streams, compacted.events);
+        }
+        Command::Export { path, from, to } => {
+            let from = from.as_deref().map_or(Bound::Unbounded, Bound::Included);
+            let to = to.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+            let mut export = Export::new();
+            export
+                .add(&stores.links, (from, to))
+                .and_then(|export| export.add(&stores.clicks, (from, to)))
+                .map_err(|e| e.to_string())?;
+            let file = File::create(&path).map_err(|e| format!("failed to create {path}: {e}"))?;
+            export.write_to(BufWriter::new(file)).map_err(|e| format!("failed to write {path}: {e}"))?;
+            for summary in export.aggregates() {
+                eprintln!("{}\t{} stream(s)\t{} event(s)", summary.aggregate_type, summary.streams, summary.events);
+            }
+        }
+        Command::Import { path, mode } => {
+            let file = File::open(&path).map_err(|e| format!("failed to open {path}: {e}"))?;
+            let file = ExportFile::read(BufReader::new(file)).map_err(|e| format!("{path}: {e}"))?;
+            // both are checked before anything is committed
+            let mut links = file.import(&stores.links, ImportMode::DryRun).map_err(|e| e.to_string())?;
+            let mut clicks = file.import(&stores.clicks, ImportMode::DryRun).map_err(|e| e.to_string())?;
+            if mode == ImportMode::DryRun || !links.is_clean() || !clicks.is_clean() {
+                print_report(&links);
+                print_report(&clicks);
+                return match links.is_clean() && clicks.is_clean() {
+                    true => Ok(()),
+                    false => Err("nothing is imported".into()),
+                };
+            }
+            // the stores share the file, the links and their clicks are committed in one line
+            let import_err = |e: EventStoreError| format!("nothing is imported: {e}");
+            let mut transaction = stores.links.backend().transaction().map_err(import_err)?;
+            file.import_in(&mut transaction, &stores.links, &links).map_err(import_err)?;
+            file.import_in(&mut transaction, &stores.clicks, &clicks).map_err(import_err)?;
+            transaction.commit().map_err(import_err)?;
+            (links.committed, clicks.committed) = (true, true);
+            print_report(&links);
+            print_report(&clicks);
+        }
+    }
+    Ok(())
+}
+
+fn print_report<A: Aggregate<Id = Slug, IdRef = SlugRef>>(report: &ImportReport<A>) {
+    let aggregate_type = A::aggregate_type();
+    for aggregate_id in &report.conflicts {
+        println!("{aggregate_type}\t{aggregate_id}\tconflicts with the stored stream");
+    }
+    for (aggregate_id, e) in &report.inconsistent {
+        println!("{aggregate_type}\t{aggregate_id}\t{e}");
+    }
+    let new = match report.committed {
+        true => "imported",
+        false => "new",
+    };
+    eprintln!("{aggregate_type}\t{} {new}\t{} unchanged", report.new.len(), report.unchanged.len());
+}
+
+fn main() -> ExitCode {
+    let (path, command) = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let stores = match Stores::open(&path) {
+        Ok(stores) => stores,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(stores, command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! HTTP redirect server of the shortener, keeps the links in memory.
+//!
+//! ```text
+//! shortener-server [--addr 127.0.0.1:8080] [--threads 4] [--redirect-status 302] [--base-url http://127.0.0.1:8080]
+//! ```
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use intl_svc_test_task::cqrs::mem_store::{KeyMode, MemBackend, MemEventStore};
+use intl_svc_test_task::gen::SimplestSlugGenerator;
+use intl_svc_test_task::http::{Config, RedirectStatus, Router, Server, ThreadPool};
+use intl_svc_test_task::clicks::Clicks;
+use intl_svc_test_task::sync::SyncUrlShortenerService;
+use intl_svc_test_task::Stats;
+
+const USAGE: &str = "usage: shortener-server [--addr ADDR] [--threads N] [--redirect-status 301|302|307|308] [--base-url URL]";
+
+struct Args {
+    addr: String,
+    threads: usize,
+    redirect_status: RedirectStatus,
+    base_url: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        addr: "127.0.0.1:8080".into(),
+        threads: 4,
+        redirect_status: RedirectStatus::default(),
+        base_url: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("missing value of {arg}"));
+        match arg.as_str() {
+            "--addr" => args.addr = value()?,
+            "--threads" => args.threads = value()?.parse().map_err(|e| format!("invalid --threads: {e}"))?,
+            "--redirect-status" => {
+                args.redirect_status = value()?
+                    .parse()
+                    .ok()
+                    .and_then(RedirectStatus::from_code)
+                    .ok_or("invalid --redirect-status, expected one of 301, 302, 307, 308")?
+            }
+            "--base-url" => args.base_url = Some(value()?),
+            "--help" | "-h" => return Err(USAGE.into()),
+            _ => return Err(format!("unexpected argument {arg}\n{USAGE}")),
+        }
+    }
+    Ok(args)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let server = match Server::bind(&args.addr) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("failed to bind {}: {e}", args.addr);
+            return ExitCode::FAILURE;
+        }
+    };
+    let base_url = args.base_url.unwrap_or_else(|| format!("http://{}", args.addr));
+
+    // links and their clicks side by side, the workers share the service
+    let backend = MemBackend::new();
+    let service = SyncUrlShortenerService::new(
+        Box::new(MemEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact)),
+        Box::new(MemEventStore::<Clicks>::with_backend(backend, KeyMode::Exact)),
+        Box::new(SimplestSlugGenerator),
+    );
+    let router = Router::new(Arc::new(service), Config { redirect_status: args.redirect_status, base_url });
+
+    eprintln!("listening on {}", args.addr);
+    server.run(router, ThreadPool::new(args.threads));
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,315 @@
+//! Click counting of the links. Redirects are recorded in a stream of their
+//! own aggregate type, so they never touch the stream of the link definition
+//! ([`Stats`](crate::Stats)) and don't contend with its changes. The stream
+//! is created by the first redirect.
+//!
+//! With [`RedirectBuffering`] redirects of viral links are counted in memory
+//! and committed as a single [`ClickEvent::RedirectsCounted`] event.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::cqrs::{Aggregate, Decider, DomainEvent};
+use crate::json::{self, FromJson, ToJson, Value};
+use crate::{ShortenerError, Slug, SlugRef};
+
+/// Redirects of a link, keyed by the slug of the link as it was created
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clicks {
+    pub slug: Slug,
+    pub redirects: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClickEvent {
+    Redirected(Slug),
+    /// Several redirects counted at once, see [`RedirectBuffering`]
+    RedirectsCounted(Slug, u64),
+}
+
+#[derive(Clone, Debug)]
+pub enum ClickCommand {
+    /// Counts a redirect of the existing link, which is checked by the caller
+    Redirect(Slug),
+    CountRedirects(Slug, u64),
+}
+
+impl Default for Clicks {
+    fn default() -> Self {
+        Clicks { slug: Slug(String::new()), redirects: 0 }
+    }
+}
+
+impl Aggregate for Clicks {
+    type Event = ClickEvent;
+    type Id = Slug;
+    type IdRef = SlugRef;
+
+    fn aggregate_type() -> &'static SlugRef {
+        "short_link_clicks".as_ref()
+    }
+
+    fn aggregate_id(&self) -> &SlugRef {
+        &self.slug
+    }
+
+    fn apply(&mut self, event: ClickEvent) {
+        match event {
+            ClickEvent::Redirected(slug) => {
+                self.slug = slug;
+                self.redirects += 1;
+            }
+            ClickEvent::RedirectsCounted(slug, count) => {
+                self.slug = slug;
+                self.redirects += count;
+            }
+        }
+    }
+}
+
+impl Decider for Clicks {
+    type Command = ClickCommand;
+    type Error = ShortenerError;
+
+    fn decide(&self, command: ClickCommand) -> Result<Vec<ClickEvent>, ShortenerError> {
+        match command {
+            ClickCommand::Redirect(slug) => Ok(vec![ClickEvent::Redirected(slug)]),
+            ClickCommand::CountRedirects(_, 0) => Ok(vec![]),
+            ClickCommand::CountRedirects(slug, count) => Ok(vec![ClickEvent::RedirectsCounted(slug, count)]),
+        }
+    }
+}
+
+impl DomainEvent for ClickEvent {
+    const EVENT_TYPE: &'static str = "ClickEvent";
+    fn event_name(&self) -> &'static str {
+        match self {
+            ClickEvent::Redirected(_) => "Redirected",
+            ClickEvent::RedirectsCounted(_, _) => "RedirectsCounted",
+        }
+    }
+}
+
+impl ToJson for ClickEvent {
+    fn to_json(&self) -> Value {
+        let name = ("name", Value::from(self.event_name()));
+        match self {
+            ClickEvent::Redirected(slug) => Value::object([name, ("slug", Value::from(slug.as_str()))]),
+            ClickEvent::RedirectsCounted(slug, count) => Value::object([
+                name,
+                ("slug", Value::from(slug.as_str())),
+                ("count", Value::from(*count)),
+            ]),
+        }
+    }
+}
+
+impl FromJson for ClickEvent {
+    fn from_json(value: &Value) -> Result<Self, json::Error> {
+        let slug = Slug::from(value.str_field("slug")?);
+        match value.str_field("name")? {
+            "Redirected" => Ok(ClickEvent::Redirected(slug)),
+            "RedirectsCounted" => Ok(ClickEvent::RedirectsCounted(slug, value.u64_field("count")?)),
+            name => Err(json::Error::new(format!("unknown event `{name}`"))),
+        }
+    }
+}
+
+/// Snapshot of the compacted streams, see [`FileEventStore::compact`](crate::cqrs::file_store::FileEventStore::compact)
+impl ToJson for Clicks {
+    fn to_json(&self) -> Value {
+        Value::object([("slug", Value::from(self.slug.as_str())), ("redirects", Value::from(self.redirects))])
+    }
+}
+
+impl FromJson for Clicks {
+    fn from_json(value: &Value) -> Result<Self, json::Error> {
+        Ok(Clicks { slug: Slug::from(value.str_field("slug")?), redirects: value.u64_field("redirects")? })
+    }
+}
+
+/// Settings of the buffered counting of redirects. Pending redirects of a
+/// link are committed once there are `max_pending` of them, or on the first
+/// redirect of any link after the oldest ones have waited `max_delay`.
+/// Services commit all of them on drop, or on the explicit flushes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectBuffering {
+    pub max_pending: u64,
+    pub max_delay: Duration,
+    /// Whether the stats include the pending redirects
+    pub pending_in_stats: bool,
+}
+
+impl Default for RedirectBuffering {
+    fn default() -> Self {
+        Self { max_pending: 100, max_delay: Duration::from_secs(1), pending_in_stats: false }
+    }
+}
+
+/// Redirects counted in memory, keyed by the slug of the link as it was created
+pub(crate) struct RedirectBuffer {
+    config: RedirectBuffering,
+    inner: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    counts: HashMap<Slug, (u64, Instant)>,
+    oldest: Option<Instant>,
+}
+
+impl RedirectBuffer {
+    pub(crate) fn new(config: RedirectBuffering) -> Self {
+        Self { config, inner: Mutex::new(Pending::default()) }
+    }
+
+    pub(crate) fn config(&self) -> RedirectBuffering {
+        self.config
+    }
+
+    /// Counts the redirect, returns the counts to be committed now
+    pub(crate) fn add(&self, slug: &Slug) -> Vec<(Slug, u64)> {
+        let now = Instant::now();
+        let mut pending = self.lock();
+        let (count, _) = pending.counts.entry(slug.clone()).or_insert((0, now));
+        *count += 1;
+        let mut due = Vec::new();
+        if *count >= self.config.max_pending {
+            due.extend(pending.counts.remove_entry(slug).map(|(slug, (count, _))| (slug, count)));
+        }
+        pending.oldest.get_or_insert(now);
+        if pending.oldest.is_some_and(|oldest| now.duration_since(oldest) >= self.config.max_delay) {
+            due.extend(pending.take(|since| now.duration_since(since) >= self.config.max_delay));
+        }
+        due
+    }
+
+    /// Takes the counts which have waited `max_delay`
+    pub(crate) fn take_due(&self) -> Vec<(Slug, u64)> {
+        let now = Instant::now();
+        self.lock().take(|since| now.duration_since(since) >= self.config.max_delay)
+    }
+
+    pub(crate) fn take_all(&self) -> Vec<(Slug, u64)> {
+        self.lock().take(|_| true)
+    }
+
+    /// Returns the count which failed to be committed
+    pub(crate) fn restore(&self, slug: Slug, count: u64) {
+        let now = Instant::now();
+        let mut pending = self.lock();
+        pending.counts.entry(slug).or_insert((0, now)).0 += count;
+        pending.oldest = Some(pending.oldest.map_or(now, |oldest| oldest.min(now)));
+    }
+
+    pub(crate) fn pending(&self, slug: &SlugRef) -> u64 {
+        self.lock().counts.get(slug).map_or(0, |(count, _)| *count)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Pending> {
+        // counts are consistent between the statements, so they survive a panic
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Pending {
+    fn take<F: Fn(Instant) -> bool>(&mut self, is_due: F) -> Vec<(Slug, u64)> {
+        let due_slugs = self.counts
+            .iter()
+            .filter(|(_, (_, since))| is_due(*since))
+            .map(|(slug, _)| slug.clone())
+            .collect::<Vec<_>>();
+        let due = due_slugs
+            .into_iter()
+            .filter_map(|slug| self.counts.remove_entry(&slug))
+            .map(|(slug, (count, _))| (slug, count))
+            .collect();
+        self.oldest = self.counts.values().map(|(_, since)| *since).min();
+        due
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cqrs::fixture::Fixture;
+
+    #[test]
+    fn test_first_redirect() {
+        let clicks = Fixture::<Clicks>::given_no_previous_events()
+            .when(ClickCommand::Redirect(Slug::from("promo")))
+            .then_expect_events([ClickEvent::Redirected(Slug::from("promo"))]);
+        assert_eq!(clicks, Clicks { slug: Slug::from("promo"), redirects: 1 });
+    }
+
+    #[test]
+    fn test_redirect() {
+        let clicks = Fixture::<Clicks>::given([ClickEvent::Redirected(Slug::from("promo"))])
+            .when(ClickCommand::Redirect(Slug::from("promo")))
+            .then_expect_events([ClickEvent::Redirected(Slug::from("promo"))]);
+        assert_eq!(clicks.redirects, 2);
+    }
+
+    #[test]
+    fn test_count_redirects() {
+        let clicks = Fixture::<Clicks>::given([ClickEvent::Redirected(Slug::from("promo"))])
+            .when(ClickCommand::CountRedirects(Slug::from("promo"), 10))
+            .then_expect_events([ClickEvent::RedirectsCounted(Slug::from("promo"), 10)]);
+        assert_eq!(clicks.redirects, 11);
+        Fixture::<Clicks>::given_no_previous_events()
+            .when(ClickCommand::CountRedirects(Slug::from("promo"), 0))
+            .then_expect_events([]);
+    }
+
+    #[test]
+    fn test_json() {
+        let event = ClickEvent::Redirected(Slug::from("promo"));
+        let value = event.to_json();
+        assert_eq!(value.to_string(), r#"{"name":"Redirected","slug":"promo"}"#);
+        assert_eq!(ClickEvent::from_json(&value).unwrap(), event);
+
+        let event = ClickEvent::RedirectsCounted(Slug::from("promo"), 3);
+        let value = event.to_json();
+        assert_eq!(value.to_string(), r#"{"name":"RedirectsCounted","slug":"promo","count":3}"#);
+        assert_eq!(ClickEvent::from_json(&value).unwrap(), event);
+    }
+
+    fn buffer(max_pending: u64, max_delay: Duration) -> RedirectBuffer {
+        RedirectBuffer::new(RedirectBuffering { max_pending, max_delay, pending_in_stats: false })
+    }
+
+    #[test]
+    fn test_buffer_size_threshold() {
+        let buffer = buffer(3, Duration::from_secs(3600));
+        let (a, b) = (Slug::from("a"), Slug::from("b"));
+        assert!(buffer.add(&a).is_empty());
+        assert!(buffer.add(&b).is_empty());
+        assert!(buffer.add(&a).is_empty());
+        assert_eq!(buffer.pending(&a), 2);
+        assert_eq!(buffer.add(&a), [(a.clone(), 3)]);
+        assert_eq!(buffer.pending(&a), 0);
+        assert_eq!(buffer.take_due(), []);
+        assert_eq!(buffer.take_all(), [(b.clone(), 1)]);
+        assert_eq!(buffer.pending(&b), 0);
+    }
+
+    #[test]
+    fn test_buffer_time_threshold() {
+        let buffer = buffer(100, Duration::from_millis(50));
+        let (a, b) = (Slug::from("a"), Slug::from("b"));
+        buffer.add(&a);
+        buffer.add(&a);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(buffer.take_due(), [(a.clone(), 2)]);
+
+        buffer.add(&a);
+        std::thread::sleep(Duration::from_millis(60));
+        // any redirect commits the waited ones, the fresh ones stay
+        assert_eq!(buffer.add(&b), [(a.clone(), 1)]);
+        assert_eq!(buffer.pending(&b), 1);
+
+        buffer.restore(a.clone(), 5);
+        assert_eq!(buffer.pending(&a), 5);
+    }
+}
@@ -1,13 +1,30 @@
 pub mod store;
 pub mod mem_store;
+pub mod file_store;
+pub mod lsm_store;
+pub mod future;
+pub mod async_store;
+pub mod bus;
+pub mod fixture;
+pub mod upcast;
+pub mod export;
+pub mod history;
+pub mod wal;
 mod aggregate_id;
 
 pub use aggregate_id::*;
 
-pub trait DomainEvent: Clone + core::fmt::Debug + Sync + Send {
+pub trait DomainEvent: Clone + PartialEq + core::fmt::Debug + Sync + Send {
     const EVENT_TYPE: &'static str;
+    /// Version of the serialized shape of the events, bumped on its changes
+    const EVENT_VERSION: upcast::EventVersion = upcast::INITIAL_EVENT_VERSION;
     #[allow(dead_code)]
     fn event_name(&self) -> &'static str;
+
+    /// Transformations of the events serialized by the previous versions
+    fn upcasters() -> upcast::Upcasters {
+        upcast::Upcasters::new()
+    }
 }
 
 // It also could have been: Default + Send + Sync + Serialize + DeserializeOwned, when using persistent storages
@@ -21,7 +38,8 @@ pub trait Aggregate: Default + Clone {
     fn apply(&mut self, event: Self::Event);
 }
 
-#[allow(dead_code)]
+/// Handles the commands of the aggregate, usually implemented by the aggregate
+/// itself to be used by [`bus::CommandBus`]
 pub trait CommandHandler<A: Aggregate> {
     type Error: std::error::Error;
     type Command; // assumed that type is an enum
@@ -29,3 +47,42 @@ pub trait CommandHandler<A: Aggregate> {
 
     fn handle(&self, command: Self::Command, services: Self::Services) -> Result<Vec<A::Event>, Self::Error>;
 }
+
+/// Command handling split into pure functions: [`Decider::decide`] checks the
+/// invariants against the current state and returns the events, and
+/// [`Decider::evolve`] (i.e. [`Aggregate::apply`]) changes the state by them.
+/// So the rules can be tested without a store:
+///
+/// ```text
+/// given: Stats::from_events(previous events)
+/// when:  state.decide(command)
+/// then:  Ok(events) or Err(error)
+/// ```
+///
+/// Deciders are [`CommandHandler`]s without services, see [`fixture::Fixture`].
+pub trait Decider: Aggregate {
+    type Command;
+    type Error: std::error::Error;
+
+    fn decide(&self, command: Self::Command) -> Result<Vec<Self::Event>, Self::Error>;
+
+    fn evolve(mut self, event: Self::Event) -> Self {
+        self.apply(event);
+        self
+    }
+
+    /// State after the events, starting from the default one
+    fn from_events<I: IntoIterator<Item = Self::Event>>(events: I) -> Self {
+        events.into_iter().fold(Self::default(), Self::evolve)
+    }
+}
+
+impl<A: Decider> CommandHandler<A> for A {
+    type Error = A::Error;
+    type Command = <A as Decider>::Command;
+    type Services = ();
+
+    fn handle(&self, command: Self::Command, _services: ()) -> Result<Vec<A::Event>, Self::Error> {
+        self.decide(command)
+    }
+}
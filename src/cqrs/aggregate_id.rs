@@ -2,6 +2,7 @@ pub trait AggregateIdContract:
     Clone // also Sized
     + ToString 
     + Into<String> 
+    + From<String>
     + AsRef<Self::BorrowedAggregateId>
     // + AsRef<str> // commented to avoid an ambiguity on type inference 
     + Eq + PartialEq<Self::BorrowedAggregateId>
@@ -25,6 +26,13 @@ pub trait AggregateIdRefContract:
     // + ?Sized // traits are ?Szied by default
 {
     type OwnedAggregateId: AggregateIdContract<BorrowedAggregateId = Self> + Sized;
+
+    /// The key used by stores in the normalized keying mode, so ids which are
+    /// "the same" for a human (e.g. differ only in casing) share one stream.
+    /// By default it's the id itself.
+    fn normalized_key(&self) -> std::borrow::Cow<'_, str> {
+        std::borrow::Cow::Borrowed(self.as_ref())
+    }
 }
 
 pub trait IsEmptyAggregateId {
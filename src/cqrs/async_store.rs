@@ -0,0 +1,122 @@
+//! Async counterpart of [`EventStore`] and its implementations for the sync
+//! stores: [`Inline`] is fine for the stores which never wait long, like
+//! [`MemEventStore`](super::mem_store::MemEventStore), [`Offload`] keeps
+//! I/O-bound ones, like [`FileEventStore`](super::file_store::FileEventStore),
+//! off the executor threads.
+
+use std::sync::Arc;
+
+use super::future::{BoxFuture, Inline, Offload};
+use super::store::{EventStore, EventStoreError, StoredEventList};
+use super::Aggregate;
+
+pub trait AsyncEventStore<A: Aggregate>: Send + Sync {
+    fn fetch<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<StoredEventList<A>, EventStoreError>>;
+    fn is_exist<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<bool, EventStoreError>>;
+    fn commit(&self, state: StoredEventList<A>) -> BoxFuture<'_, Result<(), EventStoreError>>;
+    fn remove<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<StoredEventList<A>, EventStoreError>>;
+    /// See [`EventStore::commit_expected`]
+    fn commit_expected(&self, state: StoredEventList<A>, expected_len: usize) -> BoxFuture<'_, Result<(), EventStoreError>>;
+}
+
+impl<A, S> AsyncEventStore<A> for Inline<S>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send,
+    A::IdRef: Sync,
+    S: EventStore<A> + Send + Sync,
+{
+    fn fetch<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<StoredEventList<A>, EventStoreError>> {
+        Box::pin(async move { self.0.fetch(aggregate_id) })
+    }
+
+    fn is_exist<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<bool, EventStoreError>> {
+        Box::pin(async move { self.0.is_exist(aggregate_id) })
+    }
+
+    fn commit(&self, state: StoredEventList<A>) -> BoxFuture<'_, Result<(), EventStoreError>> {
+        Box::pin(async move { self.0.commit(state) })
+    }
+
+    fn remove<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<StoredEventList<A>, EventStoreError>> {
+        Box::pin(async move { self.0.remove(aggregate_id) })
+    }
+
+    fn commit_expected(&self, state: StoredEventList<A>, expected_len: usize) -> BoxFuture<'_, Result<(), EventStoreError>> {
+        Box::pin(async move { self.0.commit_expected(state, expected_len) })
+    }
+}
+
+impl<A, S> AsyncEventStore<A> for Offload<S>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send,
+    A::IdRef: Sync,
+    S: EventStore<A> + Send + Sync + 'static,
+{
+    fn fetch<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<StoredEventList<A>, EventStoreError>> {
+        let (store, aggregate_id) = (Arc::clone(&self.inner), aggregate_id.to_owned());
+        Box::pin(self.spawner.run(move || store.fetch(&aggregate_id)))
+    }
+
+    fn is_exist<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<bool, EventStoreError>> {
+        let (store, aggregate_id) = (Arc::clone(&self.inner), aggregate_id.to_owned());
+        Box::pin(self.spawner.run(move || store.is_exist(&aggregate_id)))
+    }
+
+    fn commit(&self, state: StoredEventList<A>) -> BoxFuture<'_, Result<(), EventStoreError>> {
+        let store = Arc::clone(&self.inner);
+        Box::pin(self.spawner.run(move || store.commit(state)))
+    }
+
+    fn remove<'a>(&'a self, aggregate_id: &'a A::IdRef) -> BoxFuture<'a, Result<StoredEventList<A>, EventStoreError>> {
+        let (store, aggregate_id) = (Arc::clone(&self.inner), aggregate_id.to_owned());
+        Box::pin(self.spawner.run(move || store.remove(&aggregate_id)))
+    }
+
+    fn commit_expected(&self, state: StoredEventList<A>, expected_len: usize) -> BoxFuture<'_, Result<(), EventStoreError>> {
+        let store = Arc::clone(&self.inner);
+        Box::pin(self.spawner.run(move || store.commit_expected(state, expected_len)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cqrs::future::{block_on, Spawner};
+    use crate::cqrs::mem_store::MemEventStore;
+    use crate::{ShortLinkStatEvent, ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    fn check_store(store: &dyn AsyncEventStore<Stats>) {
+        block_on(async {
+            let slug = SlugRef::new("a");
+            assert!(!store.is_exist(slug).await.unwrap());
+            let events = StoredEventList::new(&[ShortenerEvent::Create(Slug::from("a"), Url::from("https://example.com"))]).unwrap();
+            store.commit(events).await.unwrap();
+
+            let events = store.fetch(slug).await.unwrap();
+            let events = events.append_all(&[ShortenerEvent::ShortLinkStatEvent(Slug::from("a"), ShortLinkStatEvent::Redirect)]);
+            store.commit_expected(events.clone(), 1).await.unwrap();
+            assert!(matches!(
+                store.commit_expected(events, 1).await,
+                Err(EventStoreError::ConcurrencyConflict { expected: 1, actual: 2 }),
+            ));
+            assert_eq!(store.fetch(slug).await.unwrap().snapshot().aggregate().redirects, 1);
+
+            assert_eq!(store.remove(slug).await.unwrap().len(), 2);
+            assert!(matches!(store.fetch(slug).await, Err(EventStoreError::AggregateIsNotExist)));
+        });
+    }
+
+    #[test]
+    fn test_inline() {
+        check_store(&Inline(MemEventStore::<Stats>::new()));
+    }
+
+    #[test]
+    fn test_offload() {
+        check_store(&Offload::new(MemEventStore::<Stats>::new(), Spawner::default()));
+        let pool = crate::http::ThreadPool::new(2);
+        check_store(&Offload::new(MemEventStore::<Stats>::new(), Spawner::new(move |job| pool.execute(job))));
+    }
+}
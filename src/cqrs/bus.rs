@@ -0,0 +1,236 @@
+use super::store::{EventStore, EventStoreError, StoredEventList};
+use super::{Aggregate, CommandHandler};
+
+const DEFAULT_MAX_RETRIES: usize = 8;
+
+/// Loads aggregates from the store, lets them handle the commands and commits
+/// the produced events. Commits are checked against the fetched length of the
+/// stream, new streams are committed by [`EventStore::commit_new`]. On
+/// conflicts the command is handled again with the fresh state.
+pub struct CommandBus<'a, A: Aggregate> {
+    store: &'a dyn EventStore<A>,
+    max_retries: usize,
+}
+
+#[derive(Debug)]
+pub enum CommandBusError<E> {
+    /// The aggregate rejected the command
+    Command(E),
+    Store(EventStoreError),
+}
+
+impl<'a, A> CommandBus<'a, A>
+where
+    A: Aggregate + CommandHandler<A>,
+    A::Command: Clone,
+    A::Services: Clone,
+{
+    pub fn new(store: &'a dyn EventStore<A>) -> Self {
+        Self { store, max_retries: DEFAULT_MAX_RETRIES }
+    }
+
+    /// How many times a command is handled again on concurrency conflicts
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Handles the command by the aggregate, the default one if the stream
+    /// doesn't exist. Returns the committed stream, `None` if the aggregate
+    /// doesn't exist and the command produced no events.
+    pub fn execute(
+        &self,
+        aggregate_id: &A::IdRef,
+        command: A::Command,
+        services: A::Services,
+    ) -> Result<Option<StoredEventList<A>>, CommandBusError<A::Error>> {
+        let mut retries = 0;
+        loop {
+            match self.try_execute(aggregate_id, command.clone(), services.clone()) {
+                Err(CommandBusError::Store(EventStoreError::ConcurrencyConflict { .. } | EventStoreError::AggregateAlreadyExists))
+                    if retries < self.max_retries =>
+                {
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn try_execute(
+        &self,
+        aggregate_id: &A::IdRef,
+        command: A::Command,
+        services: A::Services,
+    ) -> Result<Option<StoredEventList<A>>, CommandBusError<A::Error>> {
+        let stored = match self.store.fetch(aggregate_id) {
+            Ok(stored) => Some(stored),
+            Err(EventStoreError::AggregateIsNotExist) => None,
+            Err(e) => return Err(CommandBusError::Store(e)),
+        };
+        let aggregate = stored
+            .as_ref()
+            .map(|stored| stored.snapshot().into_aggregate())
+            .unwrap_or_default();
+        let events = aggregate.handle(command, services).map_err(CommandBusError::Command)?;
+
+        match (stored, events.is_empty()) {
+            (stored, true) => Ok(stored),
+            (Some(stored), false) => {
+                let expected_len = stored.len();
+                let event_list = stored.append_all(&events);
+                self.store.commit_expected(event_list.clone(), expected_len).map_err(CommandBusError::Store)?;
+                Ok(Some(event_list))
+            }
+            (None, false) => {
+                let event_list = StoredEventList::new(&events).map_err(CommandBusError::Store)?;
+                if event_list.aggregate_id() != aggregate_id {
+                    return Err(CommandBusError::Store(EventStoreError::InconsistentEventAggregateId));
+                }
+                self.store.commit_new(event_list.clone()).map_err(CommandBusError::Store)?;
+                Ok(Some(event_list))
+            }
+        }
+    }
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for CommandBusError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Command(e) => write!(f, "command rejected: {e}"),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug + core::fmt::Display> core::error::Error for CommandBusError<E> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use crate::cqrs::mem_store::MemEventStore;
+    use crate::clicks::{ClickCommand, ClickEvent, Clicks};
+    use crate::redirect::RedirectConfig;
+    use crate::{ShortenerCommand, ShortenerError, ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    /// Commits a redirect of another writer right before the first `conflicts` checked commits
+    struct ConflictingStore {
+        inner: MemEventStore<Clicks>,
+        conflicts: Cell<usize>,
+    }
+
+    impl EventStore<Clicks> for ConflictingStore {
+        fn fetch(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Clicks>, EventStoreError> {
+            self.inner.fetch(aggregate_id)
+        }
+        fn is_exist(&self, aggregate_id: &SlugRef) -> Result<bool, EventStoreError> {
+            self.inner.is_exist(aggregate_id)
+        }
+        fn commit(&self, state: StoredEventList<Clicks>) -> Result<(), EventStoreError> {
+            self.inner.commit(state)
+        }
+        fn remove(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Clicks>, EventStoreError> {
+            self.inner.remove(aggregate_id)
+        }
+        fn commit_expected(&self, state: StoredEventList<Clicks>, expected_len: usize) -> Result<(), EventStoreError> {
+            if self.conflicts.get() > 0 {
+                self.conflicts.set(self.conflicts.get() - 1);
+                let slug = state.aggregate_id().to_owned();
+                let other = self.inner.fetch(&slug)?.append_all(&[ClickEvent::Redirected(slug)]);
+                self.inner.commit(other)?;
+            }
+            self.inner.commit_expected(state, expected_len)
+        }
+    }
+
+    fn create_store(conflicts: usize) -> ConflictingStore {
+        let store = ConflictingStore { inner: MemEventStore::new(), conflicts: Cell::new(0) };
+        CommandBus::new(&store).execute(SlugRef::new("a"), redirect(), ()).unwrap();
+        store.conflicts.set(conflicts);
+        store
+    }
+
+    fn redirect() -> ClickCommand {
+        ClickCommand::Redirect(Slug::from("a"))
+    }
+
+    #[test]
+    fn test_execute() {
+        let store = MemEventStore::<Stats>::new();
+        let bus = CommandBus::new(&store);
+        let slug = SlugRef::new("a");
+
+        let configure = ShortenerCommand::ConfigureRedirect(RedirectConfig::default());
+        assert!(matches!(bus.execute(slug, configure.clone(), ()), Err(CommandBusError::Command(ShortenerError::SlugNotFound))));
+        let created = bus.execute(slug, ShortenerCommand::Create(Slug::from("a"), Url::from("https://example.com")), ()).unwrap().unwrap();
+        assert_eq!(created.len(), 1);
+        assert!(matches!(
+            bus.execute(slug, ShortenerCommand::Create(Slug::from("a"), Url::from("https://example.com")), ()),
+            Err(CommandBusError::Command(ShortenerError::SlugAlreadyInUse)),
+        ));
+        let configured = bus.execute(slug, configure, ()).unwrap().unwrap();
+        assert_eq!(configured.len(), 2);
+        assert_eq!(store.fetch(slug).unwrap().len(), 2);
+
+        // the created aggregate must be the requested one
+        assert!(matches!(
+            bus.execute(SlugRef::new("b"), ShortenerCommand::Create(Slug::from("c"), Url::from("https://example.com")), ()),
+            Err(CommandBusError::Store(EventStoreError::InconsistentEventAggregateId)),
+        ));
+    }
+
+    /// Another creator commits the link right before the creation
+    struct RacingStore(MemEventStore<Stats>);
+
+    impl EventStore<Stats> for RacingStore {
+        fn fetch(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Stats>, EventStoreError> {
+            self.0.fetch(aggregate_id)
+        }
+        fn is_exist(&self, aggregate_id: &SlugRef) -> Result<bool, EventStoreError> {
+            self.0.is_exist(aggregate_id)
+        }
+        fn commit(&self, state: StoredEventList<Stats>) -> Result<(), EventStoreError> {
+            self.0.commit(state)
+        }
+        fn remove(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Stats>, EventStoreError> {
+            self.0.remove(aggregate_id)
+        }
+        fn commit_new(&self, state: StoredEventList<Stats>) -> Result<(), EventStoreError> {
+            let slug = state.aggregate_id().to_owned();
+            let other = ShortenerEvent::Create(slug, Url::from("https://example.org"));
+            self.0.commit_new(StoredEventList::new(&[other])?)?;
+            self.0.commit_new(state)
+        }
+    }
+
+    #[test]
+    fn test_lost_creation() {
+        let store = RacingStore(MemEventStore::new());
+        let create = ShortenerCommand::Create(Slug::from("a"), Url::from("https://example.com"));
+        // the command is handled again and rejected, the link of the other creator stays
+        assert!(matches!(
+            CommandBus::new(&store).execute(SlugRef::new("a"), create, ()),
+            Err(CommandBusError::Command(ShortenerError::SlugAlreadyInUse)),
+        ));
+        let link = store.fetch(SlugRef::new("a")).unwrap().snapshot().into_aggregate().link;
+        assert_eq!(link.url.as_str(), "https://example.org");
+    }
+
+    #[test]
+    fn test_retries_on_conflicts() {
+        let store = create_store(3);
+        let event_list = CommandBus::new(&store).execute(SlugRef::new("a"), redirect(), ()).unwrap().unwrap();
+        // the redirects of the other writer are kept
+        assert_eq!(event_list.snapshot().aggregate().redirects, 5);
+        assert_eq!(store.fetch(SlugRef::new("a")).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_retries() {
+        let store = create_store(3);
+        let result = CommandBus::new(&store).with_max_retries(2).execute(SlugRef::new("a"), redirect(), ());
+        assert!(matches!(result, Err(CommandBusError::Store(EventStoreError::ConcurrencyConflict { expected: 3, actual: 4 }))));
+        assert_eq!(store.fetch(SlugRef::new("a")).unwrap().snapshot().aggregate().redirects, 4);
+    }
+}
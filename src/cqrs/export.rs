@@ -0,0 +1,541 @@
+//! Portable export of the event streams of several aggregate types into a
+//! file of JSON lines, to be imported into the store of another instance:
+//!
+//! ```text
+//! {"format":"event-export","format_version":1,"exported_at":1700000000,"aggregates":[{"aggregate_type":"short_link","event_type":"ShortenerEvent","event_version":1,"streams":1,"events":2}]}
+//! {"aggregate_type":"short_link","aggregate_id":"promo","index":0,"version":1,"event":{...}}
+//! {"aggregate_type":"short_link","aggregate_id":"promo","index":1,"version":1,"event":{...}}
+//! ```
+//!
+//! The header describes the exported streams, so truncated files are
+//! rejected. Events of the older versions are upcasted on the import, as the
+//! [`file_store`](super::file_store) does.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::ops::RangeBounds;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::json::{self, FromJson, ToJson, Value};
+use super::file_store::{FileEventStore, FileTransaction};
+use super::store::{EventIndex, EventStore, EventStoreError, StoredEvent, StoredEventList, StoredEventRawList};
+use super::upcast::EventVersion;
+use super::{Aggregate, DomainEvent};
+
+const FORMAT: &str = "event-export";
+const FORMAT_VERSION: u64 = 1;
+
+/// Streams collected for the export
+#[derive(Default)]
+pub struct Export {
+    aggregates: Vec<AggregateSummary>,
+    records: Vec<Value>,
+}
+
+/// Streams of an aggregate type in the export
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggregateSummary {
+    pub aggregate_type: String,
+    pub event_type: String,
+    pub event_version: EventVersion,
+    pub streams: u64,
+    pub events: u64,
+}
+
+/// Export read back, see [`ExportFile::import`]
+pub struct ExportFile {
+    exported_at: u64,
+    aggregates: Vec<AggregateSummary>,
+    /// Records by the aggregate type, with the line numbers
+    records: HashMap<String, Vec<(usize, Value)>>,
+}
+
+type Streams<A> = Vec<(<A as Aggregate>::Id, StoredEventRawList<A>)>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Only reports what would be imported
+    DryRun,
+    /// Commits the new streams if none of the streams is inconsistent or conflicting
+    Commit,
+}
+
+/// Streams of the export compared with the ones of the store
+pub struct ImportReport<A: Aggregate> {
+    /// Streams absent in the store
+    pub new: Vec<A::Id>,
+    /// Streams stored with the same events
+    pub unchanged: Vec<A::Id>,
+    /// Streams stored with other events, they are never overwritten
+    pub conflicts: Vec<A::Id>,
+    pub inconsistent: Vec<(A::Id, EventStoreError)>,
+    /// Whether the new streams were committed
+    pub committed: bool,
+}
+
+impl Export {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the streams of the store with the ids in the range, e.g. `..` for
+    /// all of them or `(Bound::Included("a"), Bound::Excluded("m"))`
+    pub fn add<A, S, R>(&mut self, store: &S, ids: R) -> Result<&mut Self, EventStoreError>
+    where
+        A: Aggregate,
+        A::Event: ToJson,
+        A::IdRef: 'static,
+        S: EventStore<A> + ?Sized,
+        R: RangeBounds<str>,
+    {
+        let mut aggregate_ids = store.aggregate_ids()?;
+        aggregate_ids.retain(|id| ids.contains(id_str::<A>(id)));
+        aggregate_ids.sort_by(|a, b| id_str::<A>(a).cmp(id_str::<A>(b)));
+
+        let aggregate_type = A::aggregate_type().as_ref();
+        let mut summary = AggregateSummary {
+            aggregate_type: aggregate_type.to_owned(),
+            event_type: A::Event::EVENT_TYPE.to_owned(),
+            event_version: A::Event::EVENT_VERSION,
+            streams: 0,
+            events: 0,
+        };
+        for aggregate_id in aggregate_ids {
+            let event_list = match store.fetch_from(&aggregate_id, 0) {
+                Ok(event_list) => event_list,
+                // removed since it was listed
+                Err(EventStoreError::AggregateIsNotExist) => continue,
+                Err(e) => return Err(e),
+            };
+            summary.streams += 1;
+            summary.events += event_list.len() as u64;
+            self.records.extend(event_list.events().iter().map(|event| Value::object([
+                ("aggregate_type", Value::from(aggregate_type)),
+                ("aggregate_id", Value::from(event.aggregate_id().as_ref())),
+                ("index", Value::from(event.index())),
+                ("version", Value::from(A::Event::EVENT_VERSION as u64)),
+                ("event", event.event().to_json()),
+            ])));
+        }
+        self.aggregates.push(summary);
+        Ok(self)
+    }
+
+    pub fn aggregates(&self) -> &[AggregateSummary] {
+        &self.aggregates
+    }
+
+    /// Writes the header and the events, one per line
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let exported_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let header = Value::object([
+            ("format", Value::from(FORMAT)),
+            ("format_version", Value::from(FORMAT_VERSION)),
+            ("exported_at", Value::from(exported_at)),
+            ("aggregates", Value::from(self.aggregates.iter().map(AggregateSummary::to_json).collect::<Vec<_>>())),
+        ]);
+        writeln!(writer, "{header}")?;
+        for record in &self.records {
+            writeln!(writer, "{record}")?;
+        }
+        writer.flush()
+    }
+}
+
+fn id_str<A: Aggregate>(aggregate_id: &A::Id) -> &str
+where
+    A::IdRef: 'static,
+{
+    AsRef::<str>::as_ref(&**aggregate_id)
+}
+
+impl ToJson for AggregateSummary {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("aggregate_type", Value::from(self.aggregate_type.as_str())),
+            ("event_type", Value::from(self.event_type.as_str())),
+            ("event_version", Value::from(self.event_version as u64)),
+            ("streams", Value::from(self.streams)),
+            ("events", Value::from(self.events)),
+        ])
+    }
+}
+
+impl FromJson for AggregateSummary {
+    fn from_json(value: &Value) -> Result<Self, json::Error> {
+        Ok(Self {
+            aggregate_type: value.str_field("aggregate_type")?.to_owned(),
+            event_type: value.str_field("event_type")?.to_owned(),
+            event_version: EventVersion::try_from(value.u64_field("event_version")?)
+                .map_err(|_| json::Error::new("event version is out of range"))?,
+            streams: value.u64_field("streams")?,
+            events: value.u64_field("events")?,
+        })
+    }
+}
+
+impl ExportFile {
+    /// Reads the export, checks that it has all the streams of its header
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, EventStoreError> {
+        let mut header = None;
+        let mut records: HashMap<String, Vec<(usize, Value)>> = HashMap::new();
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line.map_err(storage_err)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line_no = line_no + 1;
+            let line_err = |e: json::Error| storage_err(format!("line {line_no}: {e}"));
+            let value = json::parse(&line).map_err(line_err)?;
+            if header.is_none() {
+                header = Some(read_header(&value).map_err(line_err)?);
+                continue;
+            }
+            let aggregate_type = value.str_field("aggregate_type").map_err(line_err)?.to_owned();
+            records.entry(aggregate_type).or_default().push((line_no, value));
+        }
+        let (exported_at, aggregates) = header.ok_or_else(|| storage_err("the export is empty"))?;
+
+        for summary in &aggregates {
+            let typed = records.get(&summary.aggregate_type).map_or(&[][..], Vec::as_slice);
+            let mut ids = typed.iter().map(|(_, record)| record.str_field("aggregate_id")).collect::<Result<Vec<_>, _>>()
+                .map_err(|e| storage_err(e.to_string()))?;
+            ids.sort_unstable();
+            ids.dedup();
+            if (ids.len() as u64, typed.len() as u64) != (summary.streams, summary.events) {
+                return Err(storage_err(format!(
+                    "the export is truncated: {} streams with {} events of `{}` are expected, found {} with {}",
+                    summary.streams,
+                    summary.events,
+                    summary.aggregate_type,
+                    ids.len(),
+                    typed.len(),
+                )));
+            }
+        }
+        if let Some(aggregate_type) = records.keys().find(|aggregate_type| !aggregates.iter().any(|summary| &summary.aggregate_type == *aggregate_type)) {
+            return Err(storage_err(format!("aggregate type `{aggregate_type}` is missing in the header")));
+        }
+        Ok(Self { exported_at, aggregates, records })
+    }
+
+    /// Seconds since the unix epoch
+    pub fn exported_at(&self) -> u64 {
+        self.exported_at
+    }
+
+    pub fn aggregates(&self) -> &[AggregateSummary] {
+        &self.aggregates
+    }
+
+    /// Compares the exported streams of `A` with the stored ones, commits the
+    /// new ones by [`EventStore::commit_new`] in the [`ImportMode::Commit`]
+    /// mode if the report is clean. The streams are committed one by one, the
+    /// ones created by others in the meantime are reported as conflicts. See
+    /// [`ExportFile::import_in`] to commit them together.
+    pub fn import<A, S>(&self, store: &S, mode: ImportMode) -> Result<ImportReport<A>, EventStoreError>
+    where
+        A: Aggregate,
+        A::Event: ToJson + FromJson,
+        A::IdRef: 'static,
+        S: EventStore<A> + ?Sized,
+    {
+        let mut report = ImportReport { new: vec![], unchanged: vec![], conflicts: vec![], inconsistent: vec![], committed: false };
+        let aggregate_type = A::aggregate_type().as_ref();
+        let Some(summary) = self.aggregates.iter().find(|summary| summary.aggregate_type == aggregate_type) else {
+            return Ok(report);
+        };
+        if summary.event_type != A::Event::EVENT_TYPE {
+            return Err(storage_err(format!(
+                "events of `{aggregate_type}` are {}, not {}",
+                summary.event_type,
+                A::Event::EVENT_TYPE,
+            )));
+        }
+
+        let mut new_streams = Vec::new();
+        for (aggregate_id, events) in self.streams::<A>()? {
+            if let Err(e) = events.check_consistency() {
+                report.inconsistent.push((aggregate_id, e));
+                continue;
+            }
+            let Some(event_list) = events.not_empty() else {
+                continue;
+            };
+            match store.fetch_from(&aggregate_id, 0) {
+                Ok(stored) if same_events(&stored, &event_list) => report.unchanged.push(aggregate_id),
+                Ok(_) => report.conflicts.push(aggregate_id),
+                Err(EventStoreError::AggregateIsNotExist) => new_streams.push((aggregate_id, event_list)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if mode == ImportMode::Commit && report.is_clean() {
+            for (aggregate_id, event_list) in new_streams {
+                match store.commit_new(event_list) {
+                    Ok(()) => report.new.push(aggregate_id),
+                    Err(EventStoreError::AggregateAlreadyExists) => report.conflicts.push(aggregate_id),
+                    Err(e) => return Err(e),
+                }
+            }
+            report.committed = true;
+        } else {
+            report.new.extend(new_streams.into_iter().map(|(aggregate_id, _)| aggregate_id));
+        }
+        Ok(report)
+    }
+
+    /// Commits the new streams of the clean [`ImportMode::DryRun`] report in
+    /// the transaction, so the streams of several aggregate types are
+    /// imported together or not at all. The streams created after the dry
+    /// run fail with [`EventStoreError::AggregateAlreadyExists`], the
+    /// transaction is expected to be dropped then.
+    pub fn import_in<A>(&self, transaction: &mut FileTransaction<'_>, store: &FileEventStore<A>, report: &ImportReport<A>) -> Result<(), EventStoreError>
+    where
+        A: Aggregate + ToJson + FromJson + Send + Sync + 'static,
+        A::Id: Send + Sync,
+        A::Event: ToJson + FromJson,
+        A::IdRef: 'static,
+    {
+        if !report.is_clean() || report.committed {
+            return Err(storage_err("only the clean dry run reports are imported"));
+        }
+        let new: HashSet<&A::Id> = report.new.iter().collect();
+        for (aggregate_id, events) in self.streams::<A>()? {
+            if let Some(event_list) = events.not_empty().filter(|_| new.contains(&aggregate_id)) {
+                transaction.commit_new(store, event_list)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Exported streams of `A` sorted by their ids, the events are in the order of the lines
+    fn streams<A>(&self) -> Result<Streams<A>, EventStoreError>
+    where
+        A: Aggregate,
+        A::Event: FromJson,
+        A::IdRef: 'static,
+    {
+        let upcasters = A::Event::upcasters();
+        let mut streams: BTreeMap<String, StoredEventRawList<A>> = BTreeMap::new();
+        for (line_no, record) in self.records.get(A::aggregate_type().as_ref()).into_iter().flatten() {
+            let event = (|| {
+                let aggregate_id = record.str_field("aggregate_id")?.to_owned();
+                let index: EventIndex = record.u64_field("index")?;
+                let version = EventVersion::try_from(record.u64_field("version")?)
+                    .map_err(|_| json::Error::new("event version is out of range"))?;
+                let event = upcasters.upcast::<A::Event>(version, record.field("event")?.clone())?;
+                Ok::<_, json::Error>(StoredEvent::<A>::new(A::Id::from(aggregate_id), index, A::Event::from_json(&event)?))
+            })();
+            let event = event.map_err(|e| storage_err(format!("line {line_no}: {e}")))?;
+            let aggregate_id: &str = event.aggregate_id().as_ref();
+            streams.entry(aggregate_id.to_owned()).or_default().push(event);
+        }
+        Ok(streams.into_iter().map(|(aggregate_id, events)| (A::Id::from(aggregate_id), events)).collect())
+    }
+}
+
+fn read_header(value: &Value) -> Result<(u64, Vec<AggregateSummary>), json::Error> {
+    if value.str_field("format")? != FORMAT {
+        return Err(json::Error::new("not an event export"));
+    }
+    let format_version = value.u64_field("format_version")?;
+    if format_version > FORMAT_VERSION {
+        return Err(json::Error::new(format!("export of version {format_version} is newer than the supported version {FORMAT_VERSION}")));
+    }
+    let aggregates = value
+        .field("aggregates")?
+        .as_array()
+        .ok_or_else(|| json::Error::new("field `aggregates` must be an array"))?
+        .iter()
+        .map(AggregateSummary::from_json)
+        .collect::<Result<_, _>>()?;
+    Ok((value.u64_field("exported_at")?, aggregates))
+}
+
+fn same_events<A: Aggregate>(stored: &StoredEventList<A>, imported: &StoredEventList<A>) -> bool
+where
+    A::Event: ToJson,
+{
+    stored.len() == imported.len()
+        && stored.events().iter().zip(imported.events()).all(|(stored, imported)| {
+            stored.index() == imported.index() && stored.event().to_json() == imported.event().to_json()
+        })
+}
+
+impl<A: Aggregate> ImportReport<A> {
+    /// No stream is inconsistent or conflicting, so the import is committed
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty() && self.inconsistent.is_empty()
+    }
+}
+
+fn storage_err<E: Into<Box<dyn core::error::Error + Send + Sync>>>(e: E) -> EventStoreError {
+    EventStoreError::StorageError(e.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clicks::{ClickEvent, Clicks};
+    use crate::cqrs::mem_store::MemEventStore;
+    use crate::{ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    fn create(slug: &str) -> StoredEventList<Stats> {
+        StoredEventList::new(&[ShortenerEvent::Create(Slug::from(slug), Url::from("https://example.com"))]).unwrap()
+    }
+
+    fn redirected(slug: &str, times: usize) -> StoredEventList<Clicks> {
+        let events = vec![ClickEvent::Redirected(Slug::from(slug)); times];
+        StoredEventList::new(&events).unwrap()
+    }
+
+    fn export(links: &MemEventStore<Stats>, clicks: &MemEventStore<Clicks>) -> Vec<u8> {
+        let mut export = Export::new();
+        export.add(links, ..).unwrap().add(clicks, ..).unwrap();
+        let mut file = Vec::new();
+        export.write_to(&mut file).unwrap();
+        file
+    }
+
+    fn source() -> (MemEventStore<Stats>, MemEventStore<Clicks>) {
+        let (links, clicks) = (MemEventStore::new(), MemEventStore::new());
+        for slug in ["a", "b", "c"] {
+            links.commit(create(slug)).unwrap();
+        }
+        clicks.commit(redirected("a", 2)).unwrap();
+        (links, clicks)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (links, clicks) = source();
+        let file = ExportFile::read(&export(&links, &clicks)[..]).unwrap();
+        assert_eq!(file.aggregates().len(), 2);
+        assert_eq!((file.aggregates()[0].streams, file.aggregates()[0].events), (3, 3));
+        assert_eq!((file.aggregates()[1].streams, file.aggregates()[1].events), (1, 2));
+
+        let (target_links, target_clicks) = (MemEventStore::<Stats>::new(), MemEventStore::<Clicks>::new());
+        let report = file.import(&target_links, ImportMode::Commit).unwrap();
+        assert!(report.committed);
+        assert_eq!(report.new, [Slug::from("a"), Slug::from("b"), Slug::from("c")]);
+        file.import(&target_clicks, ImportMode::Commit).unwrap();
+        assert!(target_links.is_exist(SlugRef::new("c")).unwrap());
+        assert_eq!(target_clicks.fetch(SlugRef::new("a")).unwrap().snapshot().aggregate().redirects, 2);
+
+        // importing again changes nothing
+        let report = file.import(&target_links, ImportMode::Commit).unwrap();
+        assert!(report.new.is_empty());
+        assert_eq!(report.unchanged.len(), 3);
+    }
+
+    #[test]
+    fn test_range() {
+        let (links, _) = source();
+        let mut export = Export::new();
+        export.add(&links, (std::ops::Bound::Included("b"), std::ops::Bound::Unbounded)).unwrap();
+        assert_eq!(export.aggregates()[0].streams, 2);
+        let mut export = Export::new();
+        export.add(&links, (std::ops::Bound::Included("a"), std::ops::Bound::Excluded("c"))).unwrap();
+        assert_eq!(export.aggregates()[0].streams, 2);
+    }
+
+    #[test]
+    fn test_conflicts_and_dry_run() {
+        let (links, clicks) = source();
+        let file = ExportFile::read(&export(&links, &clicks)[..]).unwrap();
+
+        let target = MemEventStore::<Stats>::new();
+        target.commit(create("a")).unwrap();
+        let other = ShortenerEvent::Create(Slug::from("b"), Url::from("https://example.org"));
+        target.commit(StoredEventList::new(&[other]).unwrap()).unwrap();
+
+        let report = file.import(&target, ImportMode::DryRun).unwrap();
+        assert_eq!((report.new.as_slice(), report.unchanged.as_slice()), (&[Slug::from("c")][..], &[Slug::from("a")][..]));
+        assert_eq!(report.conflicts, [Slug::from("b")]);
+        assert!(!report.committed);
+
+        // nothing is committed while there are conflicts
+        let report = file.import(&target, ImportMode::Commit).unwrap();
+        assert!(!report.is_clean() && !report.committed);
+        assert!(!target.is_exist(SlugRef::new("c")).unwrap());
+        let url = target.fetch(SlugRef::new("b")).unwrap().snapshot().into_aggregate().link.url;
+        assert_eq!(url.as_str(), "https://example.org");
+    }
+
+    #[test]
+    fn test_import_in_transaction() {
+        use crate::cqrs::file_store::test::TempFile;
+        use crate::cqrs::file_store::FileBackend;
+        use crate::cqrs::store::KeyMode;
+
+        let (links, clicks) = source();
+        let file = ExportFile::read(&export(&links, &clicks)[..]).unwrap();
+        let target = TempFile::new("export_import_in_transaction");
+        let backend = FileBackend::open(&target.0).unwrap();
+        let target_links = FileEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+        let target_clicks = FileEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+        let links_report = file.import(&target_links, ImportMode::DryRun).unwrap();
+        let clicks_report = file.import(&target_clicks, ImportMode::DryRun).unwrap();
+
+        // a stream created after the dry run rolls back the streams of both types
+        target_clicks.commit(redirected("a", 1)).unwrap();
+        let mut transaction = backend.transaction().unwrap();
+        file.import_in(&mut transaction, &target_links, &links_report).unwrap();
+        let result = file.import_in(&mut transaction, &target_clicks, &clicks_report);
+        assert!(matches!(result, Err(EventStoreError::AggregateAlreadyExists)));
+        drop(transaction);
+        assert!(!target_links.is_exist(SlugRef::new("a")).unwrap());
+
+        target_clicks.remove(SlugRef::new("a")).unwrap();
+        let mut transaction = backend.transaction().unwrap();
+        file.import_in(&mut transaction, &target_links, &links_report).unwrap();
+        file.import_in(&mut transaction, &target_clicks, &clicks_report).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(target_links.aggregate_ids().unwrap().len(), 3);
+        assert_eq!(target_clicks.fetch(SlugRef::new("a")).unwrap().snapshot().aggregate().redirects, 2);
+        // in a single line
+        let contents = std::fs::read_to_string(&target.0).unwrap();
+        let last = contents.lines().last().unwrap();
+        assert!(last.contains(r#""op":"transaction""#) && last.contains("short_link_clicks"));
+    }
+
+    const HEADER: &str = r#"{"format":"event-export","format_version":1,"exported_at":0,"aggregates":[{"aggregate_type":"short_link_clicks","event_type":"ClickEvent","event_version":1,"streams":1,"events":2}]}"#;
+    const CLICK: &str = r#"{"name":"Redirected","slug":"a"}"#;
+
+    #[test]
+    fn test_inconsistent_stream() {
+        let file = format!(
+            "{HEADER}\n\
+             {{\"aggregate_type\":\"short_link_clicks\",\"aggregate_id\":\"a\",\"index\":0,\"version\":1,\"event\":{CLICK}}}\n\
+             {{\"aggregate_type\":\"short_link_clicks\",\"aggregate_id\":\"a\",\"index\":2,\"version\":1,\"event\":{CLICK}}}\n",
+        );
+        let file = ExportFile::read(file.as_bytes()).unwrap();
+        let target = MemEventStore::<Clicks>::new();
+        let report = file.import(&target, ImportMode::Commit).unwrap();
+        assert!(matches!(report.inconsistent.as_slice(), [(slug, EventStoreError::InconsistentEventIndex)] if slug.as_str() == "a"));
+        assert!(!report.committed);
+        assert!(!target.is_exist(SlugRef::new("a")).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let record = format!("{{\"aggregate_type\":\"short_link_clicks\",\"aggregate_id\":\"a\",\"index\":0,\"version\":1,\"event\":{CLICK}}}");
+        let read_err = |file: String| match ExportFile::read(file.as_bytes()) {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("{file} must be rejected"),
+        };
+        assert!(read_err(format!("{HEADER}\n{record}\n")).contains("the export is truncated"));
+        assert!(read_err(String::new()).contains("the export is empty"));
+        assert!(read_err(format!("{}\n", HEADER.replace(r#""format_version":1"#, r#""format_version":2"#))).contains("newer than the supported"));
+        let foreign = record.replace("short_link_clicks", "short_link");
+        assert!(read_err(format!("{HEADER}\n{record}\n{record}\n{foreign}\n")).contains("`short_link` is missing in the header"));
+
+        // events of another type
+        let file = ExportFile::read(format!("{HEADER}\n{record}\n{record}\n").as_bytes()).unwrap();
+        assert!(file.import(&MemEventStore::<Clicks>::new(), ImportMode::DryRun).is_ok());
+        let renamed = HEADER.replace("short_link_clicks", "short_link");
+        let file = ExportFile::read(format!("{renamed}\n{foreign}\n{foreign}\n").as_bytes()).unwrap();
+        assert!(file.import(&MemEventStore::<Stats>::new(), ImportMode::DryRun).is_err());
+    }
+}
@@ -0,0 +1,1355 @@
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::json::{self, FromJson, ToJson, Value};
+use super::upcast::{EventVersion, Upcasters, INITIAL_EVENT_VERSION};
+use super::{Aggregate, DomainEvent};
+use super::store::{changes, check_expected_len, now_millis, Change, EventIndex, EventStore, EventStoreError, KeyMode, LogEntry, LogId, LogPosition, Snapshot, StoredEvent, StoredEventList, StoredEventRawList};
+
+/// Append-only file of JSON lines with the streams of the aggregates of any
+/// types:
+///
+/// ```text
+/// {"op":"append","aggregate_type":"short_link","aggregate_id":"promo","index":0,"version":1,"event":{...},"recorded_at":1767225600000}
+/// {"op":"remove","aggregate_type":"short_link","aggregate_id":"promo","recorded_at":1767225600000}
+/// {"op":"transaction","records":[{"op":"append",...},{"op":"append",...}]}
+/// {"op":"snapshot","aggregate_type":"short_link","aggregate_id":"promo","index":999,"state":{...}}
+/// {"op":"archived","aggregate_type":"short_link","count":1000}
+/// ```
+///
+/// [`FileEventStore::compact`] moves the old events of the long streams to
+/// the archive file next to it, see [`FileBackend::archive_path`], and
+/// replaces them by the snapshots of the streams and the placeholders which
+/// keep the positions of the log.
+///
+/// The whole file is read on opening, the records of an aggregate type are
+/// loaded by its first [`FileEventStore`]. Clones share the file, so the
+/// stores of a backend may commit their streams together, see
+/// [`FileBackend::transaction`]. Every commit is appended as a line, the
+/// ones of several records as transactions, a failed write is cut off, and
+/// an incomplete last line left by a crash is dropped on opening. Commits
+/// are left in the buffers of the OS until [`EventStore::sync`], see
+/// [`wal`](super::wal) for the durable ones.
+#[derive(Clone)]
+pub struct FileBackend {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+    state: RwLock<State>,
+}
+
+struct State {
+    file: File,
+    /// Records of the aggregate types without stores yet, with the line numbers
+    unloaded: HashMap<String, Vec<(usize, Value)>>,
+    streams: TypedStreams,
+}
+
+/// Values are `Streams<A>` of the aggregate type
+type TypedStreams = HashMap<&'static str, Box<dyn Any + Send + Sync>>;
+
+/// Restores a stream changed by a transaction
+type Undo = Box<dyn FnOnce(&mut TypedStreams)>;
+
+type Streams<A> = HashMap<String, StoredEventRawList<A>>;
+
+/// Event store of the streams of `A` in a [`FileBackend`]. Streams are loaded
+/// as is, even inconsistent ones, see [`FileEventStore::check_consistency`].
+/// Events of the older versions are upcasted by [`DomainEvent::upcasters`],
+/// the ones without the version are of the [`INITIAL_EVENT_VERSION`].
+pub struct FileEventStore<A: Aggregate> {
+    backend: FileBackend,
+    key_mode: KeyMode,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+/// Streams with more than `threshold` events after the archived ones are
+/// compacted down to the last `keep` events, see [`FileEventStore::compact`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compaction {
+    pub threshold: usize,
+    pub keep: usize,
+}
+
+/// Counts of the compacted streams and of their archived events
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Compacted {
+    pub streams: usize,
+    pub events: usize,
+}
+
+/// Commits of the streams of several aggregates of a [`FileBackend`], written
+/// as a single line by [`FileTransaction::commit`]. The backend stays locked,
+/// so its stores must not be used until the transaction is committed or
+/// dropped, which rolls it back.
+pub struct FileTransaction<'a> {
+    backend: &'a FileBackend,
+    state: RwLockWriteGuard<'a, State>,
+    records: Vec<Value>,
+    undo: Vec<Undo>,
+}
+
+impl FileBackend {
+    /// Opens the file, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, EventStoreError> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(storage_err)?;
+
+        let mut contents = Vec::new();
+        (&file).read_to_end(&mut contents).map_err(storage_err)?;
+        // a crash may leave the last line incomplete, it's dropped unless it's a whole record
+        let complete_len = contents.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        let tail = std::str::from_utf8(&contents[complete_len..]).ok().filter(|tail| line_records(tail).is_ok());
+        if tail.is_some() {
+            (&file).write_all(b"\n").map_err(storage_err)?;
+        } else if complete_len < contents.len() {
+            file.set_len(complete_len as u64).map_err(storage_err)?;
+        }
+        let text = std::str::from_utf8(&contents[..complete_len]).map_err(storage_err)?;
+
+        let mut unloaded: HashMap<String, Vec<(usize, Value)>> = HashMap::new();
+        for (line_no, line) in text.lines().chain(tail).enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line_no = line_no + 1;
+            let line_err = |e: json::Error| storage_err(format!("{}:{line_no}: {e}", path.display()));
+            for record in line_records(line).map_err(line_err)? {
+                let aggregate_type = record.str_field("aggregate_type").map_err(line_err)?.to_owned();
+                unloaded.entry(aggregate_type).or_default().push((line_no, record));
+            }
+        }
+
+        let state = State { file, unloaded, streams: TypedStreams::new() };
+        Ok(Self { shared: Arc::new(Shared { path, state: RwLock::new(state) }) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    /// Cold file of the archived records, e.g. `links.archive.jsonl` of `links.jsonl`
+    pub fn archive_path(&self) -> PathBuf {
+        self.shared.path.with_extension("archive.jsonl")
+    }
+
+    /// Locks the backend for the commits of several streams, see [`FileTransaction`]
+    pub fn transaction(&self) -> Result<FileTransaction<'_>, EventStoreError> {
+        let state = self.shared.state.write().map_err(map_locking_err)?;
+        Ok(FileTransaction { backend: self, state, records: Vec::new(), undo: Vec::new() })
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, State>, EventStoreError> {
+        self.shared.state.read().map_err(map_locking_err)
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, State>, EventStoreError> {
+        self.shared.state.write().map_err(map_locking_err)
+    }
+
+    fn is_same(&self, other: &FileBackend) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+}
+
+fn typed_ref<A>(streams: &TypedStreams) -> Result<&Streams<A>, EventStoreError>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send + Sync,
+{
+    streams
+        .get(A::aggregate_type().as_ref())
+        .and_then(|typed| typed.downcast_ref())
+        .ok_or_else(|| type_clash_err::<A>())
+}
+
+fn typed_mut<A>(streams: &mut TypedStreams) -> Result<&mut Streams<A>, EventStoreError>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send + Sync,
+{
+    streams
+        .get_mut(A::aggregate_type().as_ref())
+        .and_then(|typed| typed.downcast_mut())
+        .ok_or_else(|| type_clash_err::<A>())
+}
+
+fn type_clash_err<A: Aggregate + 'static>() -> EventStoreError {
+    let aggregate_type = A::aggregate_type().as_ref();
+    storage_err(format!("aggregate type `{aggregate_type}` is used by another aggregate"))
+}
+
+impl<A> FileEventStore<A>
+where
+    A: Aggregate + ToJson + FromJson + Send + Sync + 'static,
+    A::Id: Send + Sync,
+    A::Event: ToJson + FromJson,
+{
+    /// Opens the store over a backend of its own, creating the file if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, EventStoreError> {
+        Self::open_with_key_mode(path, KeyMode::Exact)
+    }
+
+    pub fn open_with_key_mode<P: AsRef<Path>>(path: P, key_mode: KeyMode) -> Result<Self, EventStoreError> {
+        Self::with_backend(FileBackend::open(path)?, key_mode)
+    }
+
+    /// Store of the streams of `A` in the shared backend, loads them on the
+    /// first store of the type. The stores of the same aggregate type in a
+    /// backend must use the same key mode.
+    pub fn with_backend(backend: FileBackend, key_mode: KeyMode) -> Result<Self, EventStoreError> {
+        {
+            let mut state = backend.write()?;
+            let aggregate_type = A::aggregate_type().as_ref();
+            if !state.streams.contains_key(aggregate_type) {
+                let records = state.unloaded.get(aggregate_type).map_or(&[][..], Vec::as_slice);
+                let streams = load::<A>(backend.path(), records, key_mode)?;
+                state.unloaded.remove(aggregate_type);
+                state.streams.insert(aggregate_type, Box::new(streams));
+            }
+            typed_ref::<A>(&state.streams)?;
+        }
+        Ok(Self { backend, key_mode, _aggregate: PhantomData })
+    }
+
+    pub fn backend(&self) -> &FileBackend {
+        &self.backend
+    }
+
+    pub fn path(&self) -> &Path {
+        self.backend.path()
+    }
+
+    /// Checks every stream, returns the ids of the inconsistent ones with the errors
+    pub fn check_consistency(&self) -> Result<Vec<(A::Id, EventStoreError)>, EventStoreError> {
+        let state = self.backend.read()?;
+        Ok(typed_ref::<A>(&state.streams)?
+            .values()
+            .filter_map(|events| {
+                let aggregate_id = events.aggregate_id()?.to_owned();
+                events.check_consistency().err().map(|e| (aggregate_id, e))
+            })
+            .collect())
+    }
+
+    /// Moves the old events of the long streams to the archive file, the
+    /// file keeps their snapshots, so the events before the snapshots are
+    /// read only by [`EventStore::fetch_from`]. The indices of the events
+    /// and the positions of the log stay the same.
+    ///
+    /// The archived records are appended to the archive before the file is
+    /// replaced by the compacted one, so an interrupted compaction leaves
+    /// both files readable, with some records in both of them.
+    pub fn compact(&self, compaction: Compaction) -> Result<Compacted, EventStoreError> {
+        let mut state = self.backend.write()?;
+        let aggregate_type = A::aggregate_type().as_ref();
+        // snapshot records of the streams by their keys, the inconsistent streams are left as is
+        let snapshots: HashMap<String, (EventIndex, Value)> = typed_ref::<A>(&state.streams)?
+            .iter()
+            .filter(|(_, events)| events.events().len() > compaction.threshold.max(compaction.keep))
+            .filter(|(_, events)| events.check_consistency().is_ok())
+            .filter_map(|(key, events)| {
+                let index = (events.len() - compaction.keep - 1) as EventIndex;
+                let snapshot = Value::object([
+                    ("op", Value::from("snapshot")),
+                    ("aggregate_type", Value::from(aggregate_type)),
+                    ("aggregate_id", Value::from(events.aggregate_id()?.as_ref())),
+                    ("index", Value::from(index)),
+                    ("state", events.snapshot_at(index)?.aggregate().to_json()),
+                ]);
+                Some((key.clone(), (index, snapshot)))
+            })
+            .collect();
+        if snapshots.is_empty() {
+            return Ok(Compacted::default());
+        }
+
+        let path = self.path();
+        let mut lines = Vec::new();
+        for (line_no, line) in BufReader::new(File::open(path).map_err(storage_err)?).lines().enumerate() {
+            let line = line.map_err(storage_err)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = json::parse(&line).map_err(|e| storage_err(format!("{}:{}: {e}", path.display(), line_no + 1)))?;
+            lines.push(record);
+        }
+
+        // only the records after the last removal are of the stored streams
+        let key_of = |record: &Value| -> Result<Option<String>, json::Error> {
+            // the placeholders of the archived records have no ids
+            let Some(aggregate_id) = record.opt_str_field("aggregate_id")? else {
+                return Ok(None);
+            };
+            if record.str_field("aggregate_type")? != aggregate_type {
+                return Ok(None);
+            }
+            let aggregate_id = A::Id::from(aggregate_id.to_owned());
+            Ok(Some(self.key(aggregate_id.as_ref()).into_owned()).filter(|key| snapshots.contains_key(key)))
+        };
+        let mut last_removal: HashMap<String, usize> = HashMap::new();
+        for (ordinal, record) in lines.iter().flat_map(line_records_of).enumerate() {
+            if record.str_field("op").map_err(storage_err)? == "remove" {
+                if let Some(key) = key_of(record).map_err(storage_err)? {
+                    last_removal.insert(key, ordinal);
+                }
+            }
+        }
+
+        let mut compacted = Compacted::default();
+        let mut archive = String::new();
+        let mut compacted_lines: Vec<Value> = Vec::new();
+        let mut position: LogPosition = 0;
+        let mut ordinal = 0;
+        for line in &lines {
+            let mut records: Vec<Value> = Vec::new();
+            for record in line_records_of(line) {
+                let op = record.str_field("op").map_err(storage_err)?;
+                let stored = match key_of(record).map_err(storage_err)? {
+                    Some(key) if last_removal.get(&key).is_none_or(|removal| *removal < ordinal) => snapshots.get(&key),
+                    _ => None,
+                };
+                match (op, stored) {
+                    ("append", Some((index, snapshot))) if record.u64_field("index").map_err(storage_err)? <= *index => {
+                        let mut archived = record.clone();
+                        archived.insert("position", Value::from(position));
+                        push_line(&mut archive, archived);
+                        push_record(&mut records, Value::object([
+                            ("op", Value::from("archived")),
+                            ("aggregate_type", Value::from(aggregate_type)),
+                            ("count", Value::from(1u64)),
+                        ]));
+                        compacted.events += 1;
+                        if record.u64_field("index").map_err(storage_err)? == *index {
+                            records.push(snapshot.clone());
+                            compacted.streams += 1;
+                        }
+                    }
+                    // replaced by the new one
+                    ("snapshot", Some(_)) => {}
+                    _ => push_record(&mut records, record.clone()),
+                }
+                position += positions(record).map_err(storage_err)?;
+                ordinal += 1;
+            }
+            match line.str_field("op").map_err(storage_err)? {
+                "transaction" if records.is_empty() => {}
+                "transaction" => compacted_lines.push(Value::object([("op", Value::from("transaction")), ("records", Value::Array(records))])),
+                _ => records.into_iter().for_each(|record| push_record(&mut compacted_lines, record)),
+            }
+        }
+        if compacted.streams != snapshots.len() {
+            return Err(storage_err(format!("{} doesn't match the loaded streams, compaction is aborted", path.display())));
+        }
+
+        let mut archive_file = OpenOptions::new().create(true).append(true).open(self.backend.archive_path()).map_err(storage_err)?;
+        archive_file.write_all(archive.as_bytes()).map_err(storage_err)?;
+        archive_file.sync_all().map_err(storage_err)?;
+
+        let mut compacted_file = String::new();
+        for line in &compacted_lines {
+            push_line(&mut compacted_file, line.clone());
+        }
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".compacting");
+        let mut temp_file = File::create(&temp_path).map_err(storage_err)?;
+        temp_file.write_all(compacted_file.as_bytes()).map_err(storage_err)?;
+        temp_file.sync_all().map_err(storage_err)?;
+        std::fs::rename(&temp_path, path).map_err(storage_err)?;
+        state.file = OpenOptions::new().read(true).append(true).open(path).map_err(storage_err)?;
+
+        // the unloaded records keep the line numbers for the errors
+        for records in state.unloaded.values_mut() {
+            records.clear();
+        }
+        for (line_no, line) in compacted_lines.iter().enumerate() {
+            for record in line_records_of(line) {
+                if let Some(records) = state.unloaded.get_mut(record.str_field("aggregate_type").map_err(storage_err)?) {
+                    records.push((line_no + 1, record.clone()));
+                }
+            }
+        }
+
+        let streams = typed_mut::<A>(&mut state.streams)?;
+        for (key, (index, _)) in snapshots {
+            if let Some(events) = streams.get_mut(&key) {
+                events.compact(index);
+            }
+        }
+        Ok(compacted)
+    }
+
+    fn key<'a>(&self, aggregate_id: &'a A::IdRef) -> Cow<'a, str> {
+        self.key_mode.key(aggregate_id)
+    }
+
+    fn commit_checked(&self, event_list: StoredEventList<A>, expected_len: Option<usize>) -> Result<(), EventStoreError> {
+        let key = self.key(event_list.aggregate_id()).into_owned();
+        let mut state = self.backend.write()?;
+        let State { file, streams, .. } = &mut *state;
+        let streams = typed_mut::<A>(streams)?;
+        let stored_len = streams.get(&key).map_or(0, |events| events.len());
+        if let Some(expected_len) = expected_len {
+            check_expected_len(expected_len, stored_len)?;
+        }
+
+        // a single line, so a crash doesn't leave a part of the commit
+        let mut records = records(&event_list, streams.get(&key))?;
+        let mut line = String::new();
+        match records.len() {
+            0 => {}
+            1 => push_line(&mut line, records.remove(0)),
+            _ => push_line(&mut line, Value::object([("op", Value::from("transaction")), ("records", Value::Array(records))])),
+        }
+        append(file, &line)?;
+
+        streams.insert(key, event_list.raw());
+        Ok(())
+    }
+}
+
+/// Loads the records of the aggregate type into the streams
+fn load<A>(path: &Path, records: &[(usize, Value)], key_mode: KeyMode) -> Result<Streams<A>, EventStoreError>
+where
+    A: Aggregate + FromJson,
+    A::Event: FromJson,
+    A::IdRef: 'static,
+{
+    let upcasters = A::Event::upcasters();
+    let mut streams = Streams::<A>::new();
+    for (line_no, record) in records {
+        let entry = Entry::<A>::read(record, &upcasters)
+            .map_err(|e| storage_err(format!("{}:{line_no}: {e}", path.display())))?;
+        match entry {
+            Entry::Record(Record { change: Change::Append(event), .. }) => {
+                let key = key_mode.key(event.aggregate_id()).into_owned();
+                streams.entry(key).or_default().push(event);
+            }
+            Entry::Record(Record { change: Change::Remove(aggregate_id), .. }) => {
+                streams.remove(key_mode.key(aggregate_id.as_ref()).as_ref());
+            }
+            Entry::Snapshot(snapshot) => {
+                let key = key_mode.key(snapshot.aggregate().aggregate_id()).into_owned();
+                streams.insert(key, StoredEventRawList::from_snapshot(snapshot));
+            }
+            Entry::Archived => {}
+        }
+    }
+    Ok(streams)
+}
+
+/// Records of the aggregate type in the archive file with their log positions
+fn read_archive<A>(path: &Path, upcasters: &Upcasters) -> Result<Vec<(LogPosition, Record<A>)>, EventStoreError>
+where
+    A: Aggregate,
+    A::Event: FromJson,
+    A::IdRef: 'static,
+{
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(storage_err(e)),
+    };
+    let aggregate_type = A::aggregate_type().as_ref();
+    let mut records = Vec::new();
+    for (line_no, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(storage_err)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_err = |e: json::Error| storage_err(format!("{}:{}: {e}", path.display(), line_no + 1));
+        let record = json::parse(&line).map_err(line_err)?;
+        if record.str_field("aggregate_type").map_err(line_err)? == aggregate_type {
+            let position = record.u64_field("position").map_err(line_err)?;
+            records.push((position, Record::read(&record, upcasters).map_err(line_err)?));
+        }
+    }
+    Ok(records)
+}
+
+/// Records committing the list over the stored stream
+fn records<A>(event_list: &StoredEventList<A>, stored: Option<&StoredEventRawList<A>>) -> Result<Vec<Value>, EventStoreError>
+where
+    A: Aggregate,
+    A::Event: ToJson,
+    A::IdRef: 'static,
+{
+    Ok(changes(event_list, stored)?.into_iter().map(|change| Record::now(change).to_json()).collect())
+}
+
+impl<A> EventStore<A> for FileEventStore<A>
+where
+    A: Aggregate + ToJson + FromJson + Send + Sync + 'static,
+    A::Id: Send + Sync,
+    A::Event: ToJson + FromJson,
+{
+    fn fetch(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        let state = self.backend.read()?;
+        typed_ref::<A>(&state.streams)?
+            .get(self.key(aggregate_id).as_ref())
+            .and_then(|events| events.clone().not_empty())
+            .ok_or(EventStoreError::AggregateIsNotExist)
+    }
+
+    fn is_exist(&self, aggregate_id: &A::IdRef) -> Result<bool, EventStoreError> {
+        let state = self.backend.read()?;
+        Ok(typed_ref::<A>(&state.streams)?.contains_key(self.key(aggregate_id).as_ref()))
+    }
+
+    /// Writes only the events after the stored ones, a list which is shorter
+    /// or differs from the stored events replaces them
+    fn commit(&self, event_list: StoredEventList<A>) -> Result<(), EventStoreError> {
+        self.commit_checked(event_list, None)
+    }
+
+    fn commit_expected(&self, event_list: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError> {
+        self.commit_checked(event_list, Some(expected_len))
+    }
+
+    fn remove(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        let key = self.key(aggregate_id);
+        let mut state = self.backend.write()?;
+        let State { file, streams, .. } = &mut *state;
+        let streams = typed_mut::<A>(streams)?;
+        let event_list = streams
+            .get(key.as_ref())
+            .and_then(|events| events.clone().not_empty())
+            .ok_or(EventStoreError::AggregateIsNotExist)?;
+
+        let mut line = String::new();
+        push_line(&mut line, Record::<A>::now(Change::Remove(event_list.aggregate_id().to_owned())).to_json());
+        append(file, &line)?;
+
+        streams.remove(key.as_ref());
+        Ok(event_list)
+    }
+
+    /// Reads the archived events of a compacted stream from the archive file
+    fn fetch_from(&self, aggregate_id: &A::IdRef, index: EventIndex) -> Result<StoredEventList<A>, EventStoreError> {
+        // no compactions while the archive is read
+        let state = self.backend.read()?;
+        let key = self.key(aggregate_id);
+        let stored = typed_ref::<A>(&state.streams)?
+            .get(key.as_ref())
+            .and_then(|events| events.clone().not_empty())
+            .ok_or(EventStoreError::AggregateIsNotExist)?;
+        let first_index = stored.first_index();
+        if index >= first_index {
+            return Ok(stored);
+        }
+
+        // the latest archived event of every index, the older ones are of the removed streams
+        let archive_path = self.backend.archive_path();
+        let mut archived: BTreeMap<EventIndex, (LogPosition, StoredEvent<A>)> = BTreeMap::new();
+        for (position, record) in read_archive::<A>(&archive_path, &A::Event::upcasters())? {
+            if let Change::Append(event) = record.change {
+                if event.index() < first_index && self.key(event.aggregate_id()) == key
+                    && archived.get(&event.index()).is_none_or(|(latest, _)| *latest < position)
+                {
+                    archived.insert(event.index(), (position, event));
+                }
+            }
+        }
+        let mut events = StoredEventRawList::from_events(archived.into_values().map(|(_, event)| event).collect());
+        for event in stored.events() {
+            events.push(event.clone());
+        }
+        events.check_consistency().map_err(|_| {
+            storage_err(format!("archived events of `{}` are missing in {}", aggregate_id.as_ref(), archive_path.display()))
+        })?;
+        events.not_empty().ok_or(EventStoreError::AggregateIsNotExist)
+    }
+
+    /// Reads the log from the file and the archive, the positions are of the
+    /// records of all the aggregate types
+    fn log(&self) -> Result<Vec<LogEntry<A>>, EventStoreError> {
+        // no commits while the file is read
+        let _state = self.backend.read()?;
+        let path = self.path();
+        let upcasters = A::Event::upcasters();
+        let mut log: Vec<LogEntry<A>> = read_archive::<A>(&self.backend.archive_path(), &upcasters)?
+            .into_iter()
+            .map(|(position, Record { change, recorded_at })| LogEntry { position, recorded_at, change })
+            .collect();
+        let file = File::open(path).map_err(storage_err)?;
+        let aggregate_type = A::aggregate_type().as_ref();
+        let mut position: LogPosition = 0;
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(storage_err)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line_err = |e: json::Error| storage_err(format!("{}:{}: {e}", path.display(), line_no + 1));
+            for record in line_records(&line).map_err(line_err)? {
+                if record.str_field("aggregate_type").map_err(line_err)? == aggregate_type {
+                    if let Entry::Record(Record { change, recorded_at }) = Entry::<A>::read(&record, &upcasters).map_err(line_err)? {
+                        log.push(LogEntry { position, recorded_at, change });
+                    }
+                }
+                position += positions(&record).map_err(line_err)?;
+            }
+        }
+        // the records archived by an interrupted compaction are still in the file
+        log.sort_by_key(|entry| entry.position);
+        log.dedup_by_key(|entry| entry.position);
+        Ok(log)
+    }
+
+    /// The log of the file, shared by the stores of all the aggregate types
+    fn log_id(&self) -> Option<LogId> {
+        Some(LogId::of(&self.backend.shared))
+    }
+
+    /// Ids of all the stored aggregates, including the inconsistent ones
+    fn aggregate_ids(&self) -> Result<Vec<A::Id>, EventStoreError> {
+        let state = self.backend.read()?;
+        Ok(typed_ref::<A>(&state.streams)?
+            .values()
+            .filter_map(|events| events.aggregate_id())
+            .map(|id| id.to_owned())
+            .collect())
+    }
+
+    /// Syncs the whole file, with the commits of the other aggregate types
+    fn sync(&self) -> Result<(), EventStoreError> {
+        self.backend.read()?.file.sync_all().map_err(storage_err)
+    }
+}
+
+impl FileTransaction<'_> {
+    /// Commits the list if its stream has the expected length, as
+    /// [`EventStore::commit_expected`] does. The following commits of the
+    /// transaction see the list as stored.
+    pub fn commit_expected<A>(&mut self, store: &FileEventStore<A>, event_list: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError>
+    where
+        A: Aggregate + ToJson + FromJson + Send + Sync + 'static,
+        A::Id: Send + Sync,
+        A::Event: ToJson + FromJson,
+    {
+        if !store.backend.is_same(self.backend) {
+            return Err(storage_err("the store is of another backend"));
+        }
+        let key = store.key(event_list.aggregate_id()).into_owned();
+        let streams = typed_mut::<A>(&mut self.state.streams)?;
+        let stored_len = streams.get(&key).map_or(0, |events| events.len());
+        check_expected_len(expected_len, stored_len)?;
+
+        self.records.extend(records(&event_list, streams.get(&key))?);
+        let previous = streams.insert(key.clone(), event_list.raw());
+        self.undo.push(Box::new(move |streams| {
+            if let Ok(streams) = typed_mut::<A>(streams) {
+                match previous {
+                    Some(previous) => streams.insert(key, previous),
+                    None => streams.remove(&key),
+                };
+            }
+        }));
+        Ok(())
+    }
+
+    /// Commits the list of a new stream, as [`EventStore::commit_new`] does
+    pub fn commit_new<A>(&mut self, store: &FileEventStore<A>, event_list: StoredEventList<A>) -> Result<(), EventStoreError>
+    where
+        A: Aggregate + ToJson + FromJson + Send + Sync + 'static,
+        A::Id: Send + Sync,
+        A::Event: ToJson + FromJson,
+    {
+        match self.commit_expected(store, event_list, 0) {
+            Err(EventStoreError::ConcurrencyConflict { .. }) => Err(EventStoreError::AggregateAlreadyExists),
+            result => result,
+        }
+    }
+
+    /// Writes all the commits as a single line
+    pub fn commit(mut self) -> Result<(), EventStoreError> {
+        if !self.records.is_empty() {
+            let records = std::mem::take(&mut self.records);
+            let mut line = String::new();
+            push_line(&mut line, Value::object([("op", Value::from("transaction")), ("records", Value::Array(records))]));
+            append(&mut self.state.file, &line)?;
+        }
+        self.undo.clear();
+        Ok(())
+    }
+}
+
+impl Drop for FileTransaction<'_> {
+    fn drop(&mut self) {
+        while let Some(undo) = self.undo.pop() {
+            undo(&mut self.state.streams);
+        }
+    }
+}
+
+/// Change of a stream in the file
+pub(crate) struct Record<A: Aggregate> {
+    pub(crate) change: Change<A>,
+    /// Milliseconds since the unix epoch, absent in the older files
+    pub(crate) recorded_at: Option<u64>,
+}
+
+impl<A: Aggregate> Record<A> {
+    pub(crate) fn now(change: Change<A>) -> Self {
+        Record { change, recorded_at: Some(now_millis()) }
+    }
+}
+
+impl<A: Aggregate> ToJson for Record<A>
+where
+    A::Event: ToJson,
+    A::IdRef: 'static,
+{
+    fn to_json(&self) -> Value {
+        let aggregate_type = ("aggregate_type", Value::from(A::aggregate_type().as_ref()));
+        let mut record = match &self.change {
+            Change::Append(event) => Value::object([
+                ("op", Value::from("append")),
+                aggregate_type,
+                ("aggregate_id", Value::from(event.aggregate_id().as_ref())),
+                ("index", Value::from(event.index())),
+                ("version", Value::from(A::Event::EVENT_VERSION as u64)),
+                ("event", event.event().to_json()),
+            ]),
+            Change::Remove(aggregate_id) => Value::object([
+                ("op", Value::from("remove")),
+                aggregate_type,
+                ("aggregate_id", Value::from(aggregate_id.to_string())),
+            ]),
+        };
+        if let Some(recorded_at) = self.recorded_at {
+            record.insert("recorded_at", Value::from(recorded_at));
+        }
+        record
+    }
+}
+
+impl<A: Aggregate> Record<A>
+where
+    A::Event: FromJson,
+    A::IdRef: 'static,
+{
+    /// Reads the record of the aggregate type
+    pub(crate) fn read(value: &Value, upcasters: &Upcasters) -> Result<Self, json::Error> {
+        let aggregate_id = A::Id::from(value.str_field("aggregate_id")?.to_owned());
+        let recorded_at = match value.opt_field("recorded_at") {
+            Some(_) => Some(value.u64_field("recorded_at")?),
+            None => None,
+        };
+        let change = match value.str_field("op")? {
+            "append" => {
+                let index: EventIndex = value.u64_field("index")?;
+                let version = match value.opt_field("version") {
+                    Some(_) => EventVersion::try_from(value.u64_field("version")?)
+                        .map_err(|_| json::Error::new("event version is out of range"))?,
+                    None => INITIAL_EVENT_VERSION,
+                };
+                let event = upcasters.upcast::<A::Event>(version, value.field("event")?.clone())?;
+                let event = A::Event::from_json(&event)?;
+                Change::Append(StoredEvent::new(aggregate_id, index, event))
+            }
+            "remove" => Change::Remove(aggregate_id),
+            op => return Err(json::Error::new(format!("unknown op `{op}`"))),
+        };
+        Ok(Record { change, recorded_at })
+    }
+}
+
+/// Record of any op of the file
+enum Entry<A: Aggregate> {
+    Record(Record<A>),
+    /// State of a compacted stream after its archived events
+    Snapshot(Snapshot<A>),
+    /// Placeholder of the archived records
+    Archived,
+}
+
+impl<A: Aggregate + FromJson> Entry<A>
+where
+    A::Event: FromJson,
+    A::IdRef: 'static,
+{
+    /// Reads the entry of the aggregate type
+    fn read(value: &Value, upcasters: &Upcasters) -> Result<Self, json::Error> {
+        match value.str_field("op")? {
+            "snapshot" => {
+                let aggregate = A::from_json(value.field("state")?)?;
+                Ok(Entry::Snapshot(Snapshot::new(aggregate, value.u64_field("index")?)))
+            }
+            "archived" => Ok(Entry::Archived),
+            _ => Record::read(value, upcasters).map(Entry::Record),
+        }
+    }
+}
+
+/// Count of the log positions of the record of any aggregate type
+fn positions(record: &Value) -> Result<LogPosition, json::Error> {
+    match record.str_field("op")? {
+        "snapshot" => Ok(0),
+        "archived" => record.u64_field("count"),
+        _ => Ok(1),
+    }
+}
+
+/// Records of the line, several ones of a transaction
+fn line_records(line: &str) -> Result<Vec<Value>, json::Error> {
+    let record = json::parse(line)?;
+    match record.str_field("op")? {
+        "transaction" => Ok(record
+            .field("records")?
+            .as_array()
+            .ok_or_else(|| json::Error::new("records of the transaction must be an array"))?
+            .to_vec()),
+        _ => Ok(vec![record]),
+    }
+}
+
+/// Records of the parsed line, several ones of a transaction
+fn line_records_of(line: &Value) -> &[Value] {
+    match line.str_field("op") {
+        Ok("transaction") => line.get("records").and_then(Value::as_array).unwrap_or_default(),
+        _ => std::slice::from_ref(line),
+    }
+}
+
+/// Adds the record, merging the placeholders of the archived records of the same type
+fn push_record(records: &mut Vec<Value>, record: Value) {
+    let is_archived = |record: &Value| record.str_field("op").is_ok_and(|op| op == "archived");
+    if let Some(last) = records.last_mut() {
+        if is_archived(last) && is_archived(&record) && last.get("aggregate_type") == record.get("aggregate_type") {
+            let count = last.u64_field("count").unwrap_or(0) + record.u64_field("count").unwrap_or(0);
+            last.insert("count", Value::from(count));
+            return;
+        }
+    }
+    records.push(record);
+}
+
+/// Appends the lines, cutting off the ones written partially on a failure,
+/// so the following commits don't follow a broken line
+fn append(file: &mut File, lines: &str) -> Result<(), EventStoreError> {
+    let len = file.metadata().map_err(storage_err)?.len();
+    if let Err(e) = file.write_all(lines.as_bytes()).and_then(|_| file.flush()) {
+        let _ = file.set_len(len);
+        return Err(storage_err(e));
+    }
+    Ok(())
+}
+
+fn push_line(lines: &mut String, record: Value) {
+    lines.push_str(&record.to_string());
+    lines.push('\n');
+}
+
+fn storage_err<E: Into<Box<dyn core::error::Error + Send + Sync>>>(e: E) -> EventStoreError {
+    EventStoreError::StorageError(e.into())
+}
+
+fn map_locking_err<E>(_: E) -> EventStoreError {
+    EventStoreError::StorageError("FileStorage RwLock had been poisoned".into())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::cqrs::store::test::{commit_differing_lists, stored_events};
+    use crate::{ShortLinkStatEvent, ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    /// Path of a file in the temp dir, removed on drop
+    pub(crate) struct TempFile(pub PathBuf);
+
+    impl TempFile {
+        pub(crate) fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{name}-{}-{:?}.jsonl", std::process::id(), std::thread::current().id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn create(slug: &str) -> StoredEventList<Stats> {
+        StoredEventList::new(&[ShortenerEvent::Create(Slug::from(slug), Url::from("https://example.com"))]).unwrap()
+    }
+
+    fn redirect(slug: &str) -> ShortenerEvent {
+        ShortenerEvent::ShortLinkStatEvent(Slug::from(slug), ShortLinkStatEvent::Redirect)
+    }
+
+    #[test]
+    fn test_persistence() {
+        let file = TempFile::new("file_store_persistence");
+        {
+            let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+            store.commit(create("a")).unwrap();
+            store.commit(create("b")).unwrap();
+            store.commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a"), redirect("a")])).unwrap();
+            store.commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a")])).unwrap();
+            store.remove(SlugRef::new("b")).unwrap();
+        }
+        let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+        let events = store.fetch(SlugRef::new("a")).unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events.snapshot().aggregate().redirects, 3);
+        assert!(!store.is_exist(SlugRef::new("b")).unwrap());
+        assert!(store.check_consistency().unwrap().is_empty());
+
+        // the file has one line per commit
+        assert_eq!(std::fs::read_to_string(&file.0).unwrap().lines().count(), 5);
+    }
+
+    #[test]
+    fn test_replacing_commit() {
+        let file = TempFile::new("file_store_replacing_commit");
+        {
+            let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+            store.commit(create("a").append_all(&[redirect("a")])).unwrap();
+            store.commit(create("a")).unwrap();
+            let redirected = create("a").append_all(&[redirect("a")]);
+            assert!(matches!(
+                store.commit_expected(redirected.clone(), 0),
+                Err(EventStoreError::ConcurrencyConflict { expected: 0, actual: 1 }),
+            ));
+            store.commit_expected(redirected, 1).unwrap();
+        }
+        let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+        assert_eq!(store.fetch(SlugRef::new("a")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_differing_commit() {
+        let file = TempFile::new("file_store_differing_commit");
+        let events = commit_differing_lists(&FileEventStore::<Stats>::open(&file.0).unwrap());
+        assert_eq!(stored_events(&FileEventStore::<Stats>::open(&file.0).unwrap()), events);
+    }
+
+    #[test]
+    fn test_inconsistent_file() {
+        let file = TempFile::new("file_store_inconsistent");
+        let event = r#"{"name":"Create","slug":"a","url":"https://example.com"}"#;
+        std::fs::write(&file.0, format!(
+            "{{\"op\":\"append\",\"aggregate_type\":\"short_link\",\"aggregate_id\":\"a\",\"index\":0,\"event\":{event}}}\n\
+             {{\"op\":\"append\",\"aggregate_type\":\"short_link\",\"aggregate_id\":\"a\",\"index\":2,\"event\":{event}}}\n",
+        )).unwrap();
+        let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+        let inconsistent = store.check_consistency().unwrap();
+        assert_eq!(inconsistent.len(), 1);
+        assert!(matches!(inconsistent[0], (ref slug, EventStoreError::InconsistentEventIndex) if slug.as_str() == "a"));
+    }
+
+    #[test]
+    fn test_shared_file() {
+        use crate::clicks::{ClickEvent, Clicks};
+        let file = TempFile::new("file_store_shared");
+        let clicked = |slug: &str| StoredEventList::<Clicks>::new(&[ClickEvent::Redirected(Slug::from(slug))]).unwrap();
+        {
+            let links = FileEventStore::<Stats>::open(&file.0).unwrap();
+            let clicks = FileEventStore::<Clicks>::open(&file.0).unwrap();
+            links.commit(create("a")).unwrap();
+            clicks.commit(clicked("a")).unwrap();
+            clicks.commit(clicks.fetch(SlugRef::new("a")).unwrap().append_all(&[ClickEvent::Redirected(Slug::from("a"))])).unwrap();
+            links.commit(create("b")).unwrap();
+            clicks.commit(clicked("b")).unwrap();
+            clicks.remove(SlugRef::new("b")).unwrap();
+        }
+        let links = FileEventStore::<Stats>::open(&file.0).unwrap();
+        let clicks = FileEventStore::<Clicks>::open(&file.0).unwrap();
+        assert_eq!(links.fetch(SlugRef::new("a")).unwrap().len(), 1);
+        assert!(links.is_exist(SlugRef::new("b")).unwrap());
+        assert_eq!(clicks.fetch(SlugRef::new("a")).unwrap().snapshot().aggregate().redirects, 2);
+        assert!(!clicks.is_exist(SlugRef::new("b")).unwrap());
+    }
+
+    #[test]
+    fn test_transaction() {
+        use crate::clicks::{ClickEvent, Clicks};
+        let file = TempFile::new("file_store_transaction");
+        let clicked = |slug: &str| StoredEventList::<Clicks>::new(&[ClickEvent::Redirected(Slug::from(slug))]).unwrap();
+        {
+            let backend = FileBackend::open(&file.0).unwrap();
+            let links = FileEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+            let clicks = FileEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+            links.commit(create("a")).unwrap();
+
+            let redirected = links.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a")]);
+            let mut transaction = backend.transaction().unwrap();
+            transaction.commit_expected(&links, redirected, 1).unwrap();
+            transaction.commit_expected(&clicks, clicked("a"), 0).unwrap();
+            transaction.commit_expected(&links, create("b"), 0).unwrap();
+            transaction.commit().unwrap();
+
+            // a conflict of any stream rolls back the whole transaction, nothing is written
+            let mut transaction = backend.transaction().unwrap();
+            transaction.commit_expected(&links, create("c"), 0).unwrap();
+            assert!(matches!(
+                transaction.commit_expected(&clicks, clicked("a"), 0),
+                Err(EventStoreError::ConcurrencyConflict { expected: 0, actual: 1 }),
+            ));
+            drop(transaction);
+            assert!(!links.is_exist(SlugRef::new("c")).unwrap());
+
+            let other = FileEventStore::<Stats>::open(&file.0).unwrap();
+            let mut transaction = backend.transaction().unwrap();
+            assert!(matches!(transaction.commit_expected(&other, create("c"), 0), Err(EventStoreError::StorageError(_))));
+        }
+        // the transaction is a single line
+        let log = std::fs::read_to_string(&file.0).unwrap();
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.lines().last().unwrap().starts_with(r#"{"op":"transaction","records":["#));
+
+        let links = FileEventStore::<Stats>::open(&file.0).unwrap();
+        let clicks = FileEventStore::<Clicks>::open(&file.0).unwrap();
+        assert_eq!(links.fetch(SlugRef::new("a")).unwrap().len(), 2);
+        assert!(links.is_exist(SlugRef::new("b")).unwrap());
+        assert!(!links.is_exist(SlugRef::new("c")).unwrap());
+        assert_eq!(clicks.fetch(SlugRef::new("a")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_log() {
+        use crate::clicks::{ClickEvent, Clicks};
+        let file = TempFile::new("file_store_log");
+        std::fs::write(&file.0, UNVERSIONED_LOG).unwrap();
+        let backend = FileBackend::open(&file.0).unwrap();
+        let links = FileEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+        let clicks = FileEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+        clicks.commit(StoredEventList::new(&[ClickEvent::Redirected(Slug::from("a"))]).unwrap()).unwrap();
+        let mut transaction = backend.transaction().unwrap();
+        transaction.commit_expected(&links, create("b"), 0).unwrap();
+        transaction.commit().unwrap();
+        links.remove(SlugRef::new("a")).unwrap();
+
+        // the records of all the types take positions, the ones of transactions too
+        let log = FileEventStore::<Stats>::open(&file.0).unwrap().log().unwrap();
+        assert_eq!(log.iter().map(|entry| entry.position).collect::<Vec<_>>(), [0, 1, 3, 4]);
+        assert_eq!(log.iter().map(|entry| entry.recorded_at.is_some()).collect::<Vec<_>>(), [false, false, true, true]);
+        assert!(matches!(&log[0].change, Change::Append(event) if event.index() == 0));
+        assert!(matches!(&log[3].change, Change::Remove(slug) if slug.as_str() == "a"));
+        assert_eq!(clicks.log().unwrap()[0].position, 2);
+    }
+
+    #[test]
+    fn test_compaction() {
+        use crate::clicks::{ClickEvent, Clicks};
+        let file = TempFile::new("file_store_compaction");
+        let click = |slug: &str| ClickEvent::Redirected(Slug::from(slug));
+        let (archive, log) = {
+            let backend = FileBackend::open(&file.0).unwrap();
+            let links = FileEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+            let clicks = FileEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+            links.commit(create("a")).unwrap();
+            clicks.commit(StoredEventList::new(&[click("a"), click("a")]).unwrap()).unwrap();
+            clicks.commit(StoredEventList::new(&[click("b")]).unwrap()).unwrap();
+            let clicked = clicks.fetch(SlugRef::new("a")).unwrap().append_all(&[click("a")]);
+            let mut transaction = backend.transaction().unwrap();
+            transaction.commit_expected(&links, create("b"), 0).unwrap();
+            transaction.commit_expected(&clicks, clicked, 2).unwrap();
+            transaction.commit().unwrap();
+            for _ in 0..5 {
+                clicks.commit(clicks.fetch(SlugRef::new("a")).unwrap().append_all(&[ClickEvent::RedirectsCounted(Slug::from("a"), 2)])).unwrap();
+            }
+            let log = clicks.log().unwrap().iter().map(|entry| entry.position).collect::<Vec<_>>();
+
+            let compaction = Compaction { threshold: 5, keep: 2 };
+            assert_eq!(clicks.compact(compaction).unwrap(), Compacted { streams: 1, events: 6 });
+            assert_eq!(clicks.compact(compaction).unwrap(), Compacted::default());
+            let a = clicks.fetch(SlugRef::new("a")).unwrap();
+            assert_eq!((a.len(), a.first_index(), a.events().len()), (8, 6, 2));
+            assert_eq!(a.snapshot().aggregate().redirects, 13);
+            // the stream goes on after the compaction
+            clicks.commit(a.append_all(&[click("a")])).unwrap();
+            assert!(links.is_exist(SlugRef::new("b")).unwrap());
+            (TempFile(backend.archive_path()), log)
+        };
+        assert_eq!(std::fs::read_to_string(&archive.0).unwrap().lines().count(), 6);
+
+        let backend = FileBackend::open(&file.0).unwrap();
+        let links = FileEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+        let clicks = FileEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact).unwrap();
+        assert!(links.is_exist(SlugRef::new("b")).unwrap());
+        assert!(clicks.check_consistency().unwrap().is_empty());
+        let a = clicks.fetch(SlugRef::new("a")).unwrap();
+        assert_eq!((a.len(), a.first_index()), (9, 6));
+        assert_eq!(a.snapshot().aggregate().redirects, 14);
+        assert_eq!(clicks.fetch_from(SlugRef::new("a"), 6).unwrap().first_index(), 6);
+        let a = clicks.fetch_from(SlugRef::new("a"), 2).unwrap();
+        assert_eq!((a.len(), a.first_index()), (9, 0));
+        assert_eq!(a.snapshot_at(2).unwrap().aggregate().redirects, 3);
+        assert!(clicks.fetch(SlugRef::new("a")).unwrap().snapshot_at(2).is_none());
+        assert_eq!(clicks.fetch(SlugRef::new("b")).unwrap().len(), 1);
+
+        // the log is the same, with the archived records
+        let positions = clicks.log().unwrap().iter().map(|entry| entry.position).collect::<Vec<_>>();
+        assert_eq!(positions[..log.len()], log);
+        assert_eq!(links.log().unwrap().iter().map(|entry| entry.position).collect::<Vec<_>>(), [0, 4]);
+
+        // the next compaction replaces the snapshot
+        assert_eq!(clicks.compact(Compaction { threshold: 2, keep: 1 }).unwrap(), Compacted { streams: 1, events: 2 });
+        let reopened = FileEventStore::<Clicks>::open(&file.0).unwrap();
+        assert_eq!(reopened.fetch(SlugRef::new("a")).unwrap().first_index(), 8);
+        assert_eq!(reopened.fetch_from(SlugRef::new("a"), 0).unwrap().snapshot().aggregate().redirects, 14);
+        assert_eq!(std::fs::read_to_string(&file.0).unwrap().lines().filter(|line| line.contains(r#""op":"snapshot""#)).count(), 1);
+    }
+
+    #[test]
+    fn test_compaction_keeps_redirect_config() {
+        use crate::commands::{CommandHandler, RedirectCommandHandler};
+        use crate::cqrs::mem_store::MemEventStore;
+        use crate::queries::RedirectConfigQueryHandler;
+        use crate::redirect::{CacheControl, RedirectConfig};
+        use crate::{gen, UrlShortenerService};
+        use std::sync::Arc;
+
+        let file = TempFile::new("file_store_compaction_config");
+        let config = RedirectConfig { cache_control: Some(CacheControl::NoStore), preview: true, ..Default::default() };
+        let _archive = {
+            let links = Arc::new(FileEventStore::<Stats>::open(&file.0).unwrap());
+            let mut service = UrlShortenerService::new(Box::new(Arc::clone(&links)), Box::new(MemEventStore::new()), Box::new(gen::SimplestSlugGenerator));
+            service.handle_create_short_link(Url::from("https://example.com"), Some(Slug::from("a"))).unwrap();
+            service.handle_configure_redirect(Slug::from("a"), RedirectConfig::default()).unwrap();
+            service.handle_configure_redirect(Slug::from("a"), config).unwrap();
+            // the configuring events are archived too
+            assert_eq!(links.compact(Compaction { threshold: 2, keep: 0 }).unwrap(), Compacted { streams: 1, events: 3 });
+            assert_eq!(service.get_redirect_config(Slug::from("a")).unwrap(), config);
+            TempFile(links.backend().archive_path())
+        };
+
+        let links = FileEventStore::<Stats>::open(&file.0).unwrap();
+        assert!(links.fetch(SlugRef::new("a")).unwrap().events().is_empty());
+        let mut service = UrlShortenerService::new(Box::new(links), Box::new(MemEventStore::new()), Box::new(gen::SimplestSlugGenerator));
+        assert_eq!(service.get_redirect_config(Slug::from("a")).unwrap(), config);
+        assert_eq!(service.handle_redirect_with_config(Slug::from("a")).unwrap().config, config);
+    }
+
+    /// Log of the shortener written before the events were versioned
+    const UNVERSIONED_LOG: &str = r#"{"op":"append","aggregate_type":"short_link","aggregate_id":"a","index":0,"event":{"name":"Create","slug":"a","url":"https://example.com"}}
+{"op":"append","aggregate_type":"short_link","aggregate_id":"a","index":1,"event":{"name":"ShortLinkStatEvent","slug":"a","stat":"Redirect"}}
+"#;
+
+    #[test]
+    fn test_unversioned_log() {
+        let file = TempFile::new("file_store_unversioned");
+        std::fs::write(&file.0, UNVERSIONED_LOG).unwrap();
+        let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+        let events = store.fetch(SlugRef::new("a")).unwrap();
+        assert_eq!(events.snapshot().aggregate().redirects, 1);
+
+        // the new events are written with the version
+        store.commit(events.append_all(&[redirect("a")])).unwrap();
+        let log = std::fs::read_to_string(&file.0).unwrap();
+        assert!(log.lines().last().unwrap().contains(r#""version":1"#));
+        assert_eq!(FileEventStore::<Stats>::open(&file.0).unwrap().fetch(SlugRef::new("a")).unwrap().len(), 3);
+    }
+
+    /// Link of the 3rd version of the schema: the 2nd one renamed `Create`
+    /// into `Created` with the `url` into `target` and added the owner, the
+    /// 3rd one added the expiry
+    #[derive(Clone, Default)]
+    struct Link {
+        slug: String,
+        owner: Option<String>,
+        expires_at: Option<u64>,
+        visits: u64,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum LinkEvent {
+        Created { slug: String, target: String, owner: Option<String>, expires_at: Option<u64> },
+        Visited(String),
+    }
+
+    impl DomainEvent for LinkEvent {
+        const EVENT_TYPE: &'static str = "LinkEvent";
+        const EVENT_VERSION: EventVersion = 3;
+
+        fn event_name(&self) -> &'static str {
+            match self {
+                LinkEvent::Created { .. } => "Created",
+                LinkEvent::Visited(_) => "Visited",
+            }
+        }
+
+        fn upcasters() -> Upcasters {
+            Upcasters::new()
+                .with(1, |mut event| {
+                    if event.str_field("name")? == "Create" {
+                        event.insert("name", Value::from("Created"));
+                        let url = event.remove("url").unwrap_or(Value::Null);
+                        event.insert("target", url);
+                        event.insert("owner", Value::Null);
+                    }
+                    Ok(event)
+                })
+                .with(2, |mut event| {
+                    if event.str_field("name")? == "Created" {
+                        event.insert("expires_at", Value::Null);
+                    }
+                    Ok(event)
+                })
+        }
+    }
+
+    impl ToJson for LinkEvent {
+        fn to_json(&self) -> Value {
+            match self {
+                LinkEvent::Created { slug, target, owner, expires_at } => Value::object([
+                    ("name", Value::from("Created")),
+                    ("slug", Value::from(slug.as_str())),
+                    ("target", Value::from(target.as_str())),
+                    ("owner", owner.as_deref().map_or(Value::Null, Value::from)),
+                    ("expires_at", expires_at.map_or(Value::Null, Value::from)),
+                ]),
+                LinkEvent::Visited(slug) => Value::object([
+                    ("name", Value::from("Visited")),
+                    ("slug", Value::from(slug.as_str())),
+                ]),
+            }
+        }
+    }
+
+    impl FromJson for LinkEvent {
+        fn from_json(value: &Value) -> Result<Self, json::Error> {
+            let slug = value.str_field("slug")?.to_owned();
+            match value.str_field("name")? {
+                "Created" => Ok(LinkEvent::Created {
+                    slug,
+                    target: value.str_field("target")?.to_owned(),
+                    // required fields, so the upcasters can't be skipped
+                    owner: value.field("owner")?.as_str().map(str::to_owned),
+                    expires_at: value.field("expires_at")?.as_u64(),
+                }),
+                "Visited" => Ok(LinkEvent::Visited(slug)),
+                name => Err(json::Error::new(format!("unknown event `{name}`"))),
+            }
+        }
+    }
+
+    impl Aggregate for Link {
+        type Event = LinkEvent;
+        type Id = String;
+        type IdRef = str;
+
+        fn aggregate_type() -> &'static str {
+            "link"
+        }
+
+        fn aggregate_id(&self) -> &str {
+            &self.slug
+        }
+
+        fn apply(&mut self, event: LinkEvent) {
+            match event {
+                LinkEvent::Created { slug, owner, expires_at, .. } => {
+                    self.slug = slug;
+                    self.owner = owner;
+                    self.expires_at = expires_at;
+                }
+                LinkEvent::Visited(_) => self.visits += 1,
+            }
+        }
+    }
+
+    impl ToJson for Link {
+        fn to_json(&self) -> Value {
+            Value::object([
+                ("slug", Value::from(self.slug.as_str())),
+                ("owner", self.owner.as_deref().map_or(Value::Null, Value::from)),
+                ("expires_at", self.expires_at.map_or(Value::Null, Value::from)),
+                ("visits", Value::from(self.visits)),
+            ])
+        }
+    }
+
+    impl FromJson for Link {
+        fn from_json(value: &Value) -> Result<Self, json::Error> {
+            Ok(Link {
+                slug: value.str_field("slug")?.to_owned(),
+                owner: value.field("owner")?.as_str().map(str::to_owned),
+                expires_at: value.field("expires_at")?.as_u64(),
+                visits: value.u64_field("visits")?,
+            })
+        }
+    }
+
+    /// Log written by the three versions of the code
+    const LINK_LOG: &str = r#"{"op":"append","aggregate_type":"link","aggregate_id":"a","index":0,"event":{"name":"Create","slug":"a","url":"https://example.com"}}
+{"op":"append","aggregate_type":"link","aggregate_id":"a","index":1,"event":{"name":"Visited","slug":"a"}}
+{"op":"append","aggregate_type":"link","aggregate_id":"b","index":0,"version":2,"event":{"name":"Created","slug":"b","target":"https://example.org","owner":"alice"}}
+{"op":"append","aggregate_type":"link","aggregate_id":"a","index":2,"version":2,"event":{"name":"Visited","slug":"a"}}
+{"op":"append","aggregate_type":"link","aggregate_id":"c","index":0,"version":3,"event":{"name":"Created","slug":"c","target":"https://example.net","owner":null,"expires_at":1700000000}}
+"#;
+
+    #[test]
+    fn test_upcasting() {
+        let file = TempFile::new("file_store_upcasting");
+        std::fs::write(&file.0, LINK_LOG).unwrap();
+        let store = FileEventStore::<Link>::open(&file.0).unwrap();
+        assert!(store.check_consistency().unwrap().is_empty());
+
+        let a = store.fetch("a").unwrap();
+        assert!(matches!(&a.events()[0].event(), LinkEvent::Created { target, owner: None, expires_at: None, .. } if target == "https://example.com"));
+        let a = a.snapshot().into_aggregate();
+        assert_eq!(a.visits, 2);
+        let b = store.fetch("b").unwrap().snapshot().into_aggregate();
+        assert_eq!((b.owner.as_deref(), b.expires_at), (Some("alice"), None));
+        let c = store.fetch("c").unwrap().snapshot().into_aggregate();
+        assert_eq!((c.owner, c.expires_at), (None, Some(1700000000)));
+    }
+
+    #[test]
+    fn test_newer_event_version() {
+        let file = TempFile::new("file_store_newer_version");
+        std::fs::write(&file.0, LINK_LOG.replace(r#""version":3"#, r#""version":4"#)).unwrap();
+        let Err(EventStoreError::StorageError(e)) = FileEventStore::<Link>::open(&file.0) else {
+            panic!("events of the newer version must not be loaded");
+        };
+        assert!(e.to_string().ends_with("LinkEvent of version 4 is newer than the supported version 3"));
+    }
+
+    #[test]
+    fn test_torn_last_line() {
+        let file = TempFile::new("file_store_torn");
+        {
+            let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+            store.commit(create("a")).unwrap();
+            store.commit(create("b")).unwrap();
+        }
+        let contents = std::fs::read_to_string(&file.0).unwrap();
+        let last_line = contents[..contents.len() - 1].rfind('\n').unwrap() + 1;
+
+        // a crash before the newline keeps the record, the ones before it drop it
+        for (len, stored) in [(contents.len() - 1, true), (contents.len() - 2, false), (last_line + 1, false)] {
+            std::fs::write(&file.0, &contents[..len]).unwrap();
+            let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+            assert_eq!(store.is_exist(SlugRef::new("b")).unwrap(), stored, "file of {len} bytes");
+            store.commit(create("c")).unwrap();
+            drop(store);
+            let store = FileEventStore::<Stats>::open(&file.0).unwrap();
+            assert!(store.is_exist(SlugRef::new("a")).unwrap() && store.is_exist(SlugRef::new("c")).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_malformed_file() {
+        let file = TempFile::new("file_store_malformed");
+        std::fs::write(&file.0, "{\"op\":\"append\"\n").unwrap();
+        assert!(matches!(FileEventStore::<Stats>::open(&file.0), Err(EventStoreError::StorageError(_))));
+    }
+
+}
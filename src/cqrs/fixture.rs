@@ -0,0 +1,223 @@
+//! Given/when/then fixture of the command handling of aggregates, no store
+//! is involved:
+//!
+//! ```
+//! use intl_svc_test_task::cqrs::fixture::Fixture;
+//! use intl_svc_test_task::redirect::RedirectConfig;
+//! use intl_svc_test_task::{ShortenerCommand, ShortenerError, ShortenerEvent, Slug, Stats, Url};
+//!
+//! let created = ShortenerEvent::Create(Slug::from("promo"), Url::from("https://example.com"));
+//! let stats = Fixture::<Stats>::given([created])
+//!     .when(ShortenerCommand::ConfigureRedirect(RedirectConfig::default()))
+//!     .then_expect_events([ShortenerEvent::RedirectConfigured(Slug::from("promo"), RedirectConfig::default())]);
+//! assert_eq!(stats.link.slug, Slug::from("promo"));
+//!
+//! Fixture::<Stats>::given_no_previous_events()
+//!     .when(ShortenerCommand::ConfigureRedirect(RedirectConfig::default()))
+//!     .then_expect_error(ShortenerError::SlugNotFound);
+//! ```
+
+use super::{Aggregate, CommandHandler};
+
+/// State of the aggregate built from the previous events
+pub struct Fixture<A: Aggregate> {
+    state: A,
+}
+
+/// Result of the command, to be checked by the `then_*` methods
+pub struct Outcome<A: Aggregate + CommandHandler<A>> {
+    state: A,
+    result: Result<Vec<A::Event>, <A as CommandHandler<A>>::Error>,
+}
+
+impl<A: Aggregate + CommandHandler<A>> Fixture<A> {
+    pub fn given_no_previous_events() -> Self {
+        Self { state: A::default() }
+    }
+
+    pub fn given<I: IntoIterator<Item = A::Event>>(events: I) -> Self {
+        let mut state = A::default();
+        for event in events {
+            state.apply(event);
+        }
+        Self { state }
+    }
+
+    /// Handles the command with the default services, e.g. `()`
+    pub fn when(self, command: <A as CommandHandler<A>>::Command) -> Outcome<A>
+    where
+        <A as CommandHandler<A>>::Services: Default,
+    {
+        self.when_with_services(command, Default::default())
+    }
+
+    pub fn when_with_services(
+        self,
+        command: <A as CommandHandler<A>>::Command,
+        services: <A as CommandHandler<A>>::Services,
+    ) -> Outcome<A> {
+        let result = self.state.handle(command, services);
+        Outcome { state: self.state, result }
+    }
+}
+
+impl<A: Aggregate + CommandHandler<A>> Outcome<A> {
+    /// Checks the produced events, returns the state after them
+    #[track_caller]
+    pub fn then_expect_events<I: IntoIterator<Item = A::Event>>(self, expected: I) -> A
+    where
+        A::Event: PartialEq,
+    {
+        let expected = expected.into_iter().collect::<Vec<_>>();
+        match self.result {
+            Ok(events) if events == expected => events.into_iter().fold(self.state, |mut state, event| {
+                state.apply(event);
+                state
+            }),
+            Ok(events) => panic!("expected events {expected:?}, got {events:?}"),
+            Err(e) => panic!("expected events {expected:?}, got error {e:?}"),
+        }
+    }
+
+    #[track_caller]
+    pub fn then_expect_error(self, expected: <A as CommandHandler<A>>::Error)
+    where
+        <A as CommandHandler<A>>::Error: PartialEq,
+    {
+        match self.result {
+            Err(e) if e == expected => {}
+            Err(e) => panic!("expected error {expected:?}, got error {e:?}"),
+            Ok(events) => panic!("expected error {expected:?}, got events {events:?}"),
+        }
+    }
+
+    /// For the errors which can't be compared
+    #[track_caller]
+    pub fn then_expect_error_matching<F: FnOnce(&<A as CommandHandler<A>>::Error) -> bool>(self, predicate: F) {
+        match self.result {
+            Err(e) if predicate(&e) => {}
+            Err(e) => panic!("unexpected error {e:?}"),
+            Ok(events) => panic!("expected an error, got events {events:?}"),
+        }
+    }
+
+    pub fn into_result(self) -> Result<Vec<A::Event>, <A as CommandHandler<A>>::Error> {
+        self.result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cqrs::DomainEvent;
+
+    /// Aggregate unrelated to the shortener, with services
+    #[derive(Clone, Default)]
+    struct Counter {
+        id: String,
+        value: u32,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum CounterEvent {
+        Opened(String),
+        Added(u32),
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Overflow;
+
+    impl DomainEvent for CounterEvent {
+        const EVENT_TYPE: &'static str = "CounterEvent";
+        fn event_name(&self) -> &'static str {
+            match self {
+                CounterEvent::Opened(_) => "Opened",
+                CounterEvent::Added(_) => "Added",
+            }
+        }
+    }
+
+    impl std::fmt::Display for Overflow {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "overflow")
+        }
+    }
+
+    impl std::error::Error for Overflow {}
+
+    impl Aggregate for Counter {
+        type Event = CounterEvent;
+        type Id = String;
+        type IdRef = str;
+        fn aggregate_type() -> &'static str {
+            "counter"
+        }
+        fn aggregate_id(&self) -> &str {
+            &self.id
+        }
+        fn apply(&mut self, event: CounterEvent) {
+            match event {
+                CounterEvent::Opened(id) => self.id = id,
+                CounterEvent::Added(n) => self.value += n,
+            }
+        }
+    }
+
+    /// The limit comes from the services
+    impl CommandHandler<Counter> for Counter {
+        type Error = Overflow;
+        type Command = u32;
+        type Services = u32;
+
+        fn handle(&self, command: u32, limit: u32) -> Result<Vec<CounterEvent>, Overflow> {
+            match self.value.checked_add(command) {
+                Some(value) if value <= limit => Ok(vec![CounterEvent::Added(command)]),
+                _ => Err(Overflow),
+            }
+        }
+    }
+
+    fn opened() -> CounterEvent {
+        CounterEvent::Opened("c".into())
+    }
+
+    #[test]
+    fn test_events() {
+        let counter = Fixture::<Counter>::given([opened(), CounterEvent::Added(2)])
+            .when_with_services(3, 10)
+            .then_expect_events([CounterEvent::Added(3)]);
+        assert_eq!(counter.value, 5);
+    }
+
+    #[test]
+    fn test_errors() {
+        Fixture::<Counter>::given([opened(), CounterEvent::Added(8)])
+            .when_with_services(3, 10)
+            .then_expect_error(Overflow);
+        Fixture::<Counter>::given([opened(), CounterEvent::Added(1)])
+            .when_with_services(u32::MAX, u32::MAX)
+            .then_expect_error_matching(|e| e.to_string() == "overflow");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected events [Added(4)], got [Added(3)]")]
+    fn test_unexpected_events() {
+        Fixture::<Counter>::given([opened()]).when_with_services(3, 10).then_expect_events([CounterEvent::Added(4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected error Overflow, got events [Added(3)]")]
+    fn test_unexpected_success() {
+        Fixture::<Counter>::given([opened()]).when_with_services(3, 10).then_expect_error(Overflow);
+    }
+
+    #[test]
+    fn test_into_result() {
+        use crate::redirect::RedirectConfig;
+        use crate::{ShortenerCommand, ShortenerError, Stats};
+        // deciders are handled with `when`, without services
+        let outcome = Fixture::<Stats>::given_no_previous_events()
+            .when(ShortenerCommand::ConfigureRedirect(RedirectConfig::default()));
+        assert_eq!(outcome.into_result(), Err(ShortenerError::SlugNotFound));
+    }
+}
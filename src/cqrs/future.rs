@@ -0,0 +1,161 @@
+//! Runtime-agnostic helpers of the async traits, std only
+
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Future returned by the async traits, boxed to keep them dyn-compatible
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Runs blocking jobs off the executor threads, e.g. `tokio::task::spawn_blocking`
+/// or [`ThreadPool::execute`](crate::http::ThreadPool::execute)
+#[derive(Clone)]
+pub struct Spawner(Arc<dyn Fn(Job) + Send + Sync>);
+
+impl Spawner {
+    pub fn new<F: Fn(Job) + Send + Sync + 'static>(spawn: F) -> Self {
+        Self(Arc::new(spawn))
+    }
+
+    /// Runs `f` by the spawner, the future resolves to its result.
+    ///
+    /// ## Panics
+    ///
+    /// The future panics if `f` had panicked or the spawner had dropped it.
+    pub fn run<R: Send + 'static, F: FnOnce() -> R + Send + 'static>(&self, f: F) -> Offloaded<R> {
+        let slot = Arc::new(Mutex::new(Slot { result: None, waker: None, abandoned: false }));
+        let sender = SlotSender(Arc::clone(&slot));
+        (self.0)(Box::new(move || sender.send(f())));
+        Offloaded(slot)
+    }
+}
+
+impl Default for Spawner {
+    /// Spawns a thread per job
+    fn default() -> Self {
+        Self::new(|job| {
+            thread::spawn(job);
+        })
+    }
+}
+
+struct Slot<R> {
+    result: Option<R>,
+    waker: Option<Waker>,
+    abandoned: bool,
+}
+
+/// Sends the result of the job, marks the slot abandoned if dropped without it
+struct SlotSender<R>(Arc<Mutex<Slot<R>>>);
+
+impl<R> SlotSender<R> {
+    fn send(self, result: R) {
+        if let Ok(mut slot) = self.0.lock() {
+            slot.result = Some(result);
+        }
+    }
+}
+
+impl<R> Drop for SlotSender<R> {
+    fn drop(&mut self) {
+        let waker = match self.0.lock() {
+            Ok(mut slot) => {
+                slot.abandoned = slot.result.is_none();
+                slot.waker.take()
+            }
+            Err(_) => None,
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Result of a job run by a [`Spawner`]
+pub struct Offloaded<R>(Arc<Mutex<Slot<R>>>);
+
+impl<R> Future for Offloaded<R> {
+    type Output = R;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut slot = match self.0.lock() {
+            Ok(slot) => slot,
+            Err(_) => panic!("offloaded job had panicked"),
+        };
+        if let Some(result) = slot.result.take() {
+            return Poll::Ready(result);
+        }
+        if slot.abandoned {
+            panic!("offloaded job had panicked or had been dropped");
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Adapter of a sync implementation to the async traits, calls it right in
+/// the `poll`. Fine for the calls which never wait long.
+pub struct Inline<S>(pub S);
+
+/// Adapter of a sync implementation to the async traits, runs the calls by
+/// the [`Spawner`], so they don't block the executor threads
+pub struct Offload<S> {
+    pub(crate) inner: Arc<S>,
+    pub(crate) spawner: Spawner,
+}
+
+impl<S> Offload<S> {
+    pub fn new(inner: S, spawner: Spawner) -> Self {
+        Self::shared(Arc::new(inner), spawner)
+    }
+
+    /// Adapter of the implementation which is used by others too
+    pub fn shared(inner: Arc<S>, spawner: Spawner) -> Self {
+        Self { inner, spawner }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs the future to completion on the current thread, for the code which
+/// isn't async itself (e.g. tests and binaries)
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_spawner_run() {
+        let spawner = Spawner::default();
+        let futures = (0..10u64).map(|i| spawner.run(move || i * 2)).collect::<Vec<_>>();
+        let results = futures.into_iter().map(block_on).collect::<Vec<_>>();
+        assert_eq!(results, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "offloaded job had panicked or had been dropped")]
+    fn test_dropped_job() {
+        block_on(Spawner::new(drop).run(|| 1));
+    }
+}
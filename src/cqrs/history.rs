@@ -0,0 +1,149 @@
+//! Past states of all the aggregates of a store, rebuilt from the log of its
+//! commits, see [`EventStore::log`]:
+//!
+//! ```
+//! use intl_svc_test_task::cqrs::history::{states_as_of, AsOf};
+//! use intl_svc_test_task::cqrs::mem_store::MemEventStore;
+//! use intl_svc_test_task::cqrs::store::{EventStore, StoredEventList};
+//! use intl_svc_test_task::{ShortenerEvent, Slug, Stats, Url};
+//!
+//! let store = MemEventStore::<Stats>::new();
+//! let created = ShortenerEvent::Create(Slug::from("promo"), Url::from("https://example.com"));
+//! store.commit(StoredEventList::new(&[created]).unwrap()).unwrap();
+//!
+//! assert!(states_as_of(&store, AsOf::Position(0)).unwrap().is_empty());
+//! assert_eq!(states_as_of(&store, AsOf::Position(1)).unwrap()[0].link.slug, Slug::from("promo"));
+//! assert_eq!(states_as_of(&store, "2000-01-01".parse().unwrap()).unwrap().len(), 0);
+//! ```
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use super::store::{Change, EventStore, EventStoreError, LogEntry, LogPosition};
+use super::Aggregate;
+
+/// Moment of the past, the state after the log entries up to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsOf {
+    /// Milliseconds since the unix epoch, the entries recorded at it are included
+    Time(u64),
+    /// The entries before the position
+    Position(LogPosition),
+}
+
+impl AsOf {
+    /// Whether the state includes the entry. The log is in the order of the
+    /// commits, so the entries after the first excluded one are excluded
+    /// too, even if the clock went back. The entries without the time are
+    /// older than any time.
+    fn includes<A: Aggregate>(&self, entry: &LogEntry<A>) -> bool {
+        match *self {
+            AsOf::Time(time) => entry.recorded_at.is_none_or(|recorded_at| recorded_at <= time),
+            AsOf::Position(position) => entry.position < position,
+        }
+    }
+}
+
+/// Parses `#<position>`, milliseconds since the unix epoch, or the UTC time
+/// as `YYYY-MM-DD`, `YYYY-MM-DDTHH:MM` or `YYYY-MM-DDTHH:MM:SS` (a space may
+/// replace `T`)
+impl FromStr for AsOf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(position) = s.strip_prefix('#') {
+            return position.parse().map(AsOf::Position).map_err(|e| format!("invalid position `{position}`: {e}"));
+        }
+        if s.bytes().all(|b| b.is_ascii_digit()) {
+            return s.parse().map(AsOf::Time).map_err(|e| format!("invalid time `{s}`: {e}"));
+        }
+        parse_utc(s).map(AsOf::Time).ok_or_else(|| format!("invalid time `{s}`, expected e.g. 2026-01-01T00:00"))
+    }
+}
+
+/// Milliseconds since the unix epoch of the UTC time
+fn parse_utc(s: &str) -> Option<u64> {
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let mut date = date.splitn(3, '-').map(str::parse::<u64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    let seconds = match time {
+        None => 0,
+        Some(time) => {
+            let parts = time.split(':').map(str::parse::<u64>).collect::<Result<Vec<_>, _>>().ok()?;
+            let (hours, minutes, seconds) = match parts[..] {
+                [hours, minutes] => (hours, minutes, 0),
+                [hours, minutes, seconds] => (hours, minutes, seconds),
+                _ => return None,
+            };
+            if hours > 23 || minutes > 59 || seconds > 59 {
+                return None;
+            }
+            hours * 3600 + minutes * 60 + seconds
+        }
+    };
+    let days = (1970..year).map(|year| if is_leap(year) { 366 } else { 365 }).sum::<u64>()
+        + (1..month).map(|month| days_in_month(year, month)).sum::<u64>()
+        + day - 1;
+    Some((days * 86400 + seconds) * 1000)
+}
+
+fn is_leap(year: u64) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// States of the aggregates as of the moment sorted by their ids, the
+/// removed ones are absent
+pub fn states_as_of<A, S>(store: &S, as_of: AsOf) -> Result<Vec<A>, EventStoreError>
+where
+    A: Aggregate,
+    A::IdRef: 'static,
+    S: EventStore<A> + ?Sized,
+{
+    let mut states: BTreeMap<String, A> = BTreeMap::new();
+    for entry in store.log()?.into_iter().take_while(|entry| as_of.includes(entry)) {
+        match entry.change {
+            Change::Append(event) => {
+                let aggregate_id: &str = event.aggregate_id().as_ref();
+                states.entry(aggregate_id.to_owned()).or_default().apply(event.event().clone());
+            }
+            Change::Remove(aggregate_id) => {
+                let aggregate_id: &str = (*aggregate_id).as_ref();
+                states.remove(aggregate_id);
+            }
+        }
+    }
+    Ok(states.into_values().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("#12".parse(), Ok(AsOf::Position(12)));
+        assert_eq!("1767225600000".parse(), Ok(AsOf::Time(1767225600000)));
+        assert_eq!("2026-01-01".parse(), Ok(AsOf::Time(1767225600000)));
+        assert_eq!("2026-01-01T00:00".parse(), Ok(AsOf::Time(1767225600000)));
+        assert_eq!("2024-02-29 12:30:15".parse(), Ok(AsOf::Time(1709209815000)));
+        assert_eq!("1970-01-01".parse(), Ok(AsOf::Time(0)));
+        for invalid in ["", "#", "#x", "2023-02-29", "2026-13-01", "2026-01-01T24:00", "2026-01-01T10", "yesterday"] {
+            assert!(invalid.parse::<AsOf>().is_err(), "{invalid}");
+        }
+    }
+}
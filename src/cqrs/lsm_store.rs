@@ -0,0 +1,830 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::json::{self, FromJson, ToJson, Value};
+use super::file_store::Record;
+use super::store::{changes, check_expected_len, Change, EventIndex, EventStore, EventStoreError, KeyMode, StoredEventList, StoredEventRawList};
+use super::{Aggregate, DomainEvent};
+
+/// Event store of the streams of `A` in a directory, a log-structured merge
+/// tree of the records keyed by the stream key and the event index:
+///
+/// ```text
+/// MANIFEST      {"aggregate_type":"short_link","tables":[3,7]}
+/// 000003.sst    {"key":"promo","aggregate_id":"promo","len":2}
+///               {"key":"promo","op":"append","aggregate_type":"short_link","aggregate_id":"promo","index":0,...}
+///               {"key":"promo","op":"append","aggregate_type":"short_link","aggregate_id":"promo","index":1,...}
+///               {"records":3,"bloom":"...","hashes":7,"index":[["promo",null,0]]}
+///               00000000000000000412
+/// 000007.sst    {"key":"promo","aggregate_id":"promo","len":0}
+///               ...
+/// ```
+///
+/// Commits go to the memtable, which is written as a new sorted table when it
+/// has [`LsmOptions::memtable_len`] records, on [`EventStore::sync`] and on
+/// drop. The tables are merged into one when there are more than
+/// [`LsmOptions::max_tables`] of them, the removed streams are dropped then.
+/// A commit is done once it's in the memtable, a failed flush or merge after
+/// it is retried and reported by the next write or [`EventStore::sync`].
+///
+/// Every table has a bloom filter of its stream keys and a sparse index of
+/// its records, so a fetch reads only the tables which may have the stream,
+/// from the newest one until the stream is complete. Only the memtable, the
+/// filters and the indices are kept in memory. The memtable is lost on a
+/// crash, wrap the store in [`WalEventStore`](super::wal::WalEventStore) to
+/// replay its commits.
+pub struct LsmEventStore<A: Aggregate> {
+    dir: PathBuf,
+    key_mode: KeyMode,
+    memtable_len: usize,
+    state: RwLock<State>,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LsmOptions {
+    pub key_mode: KeyMode,
+    /// Records of the memtable written as a table
+    pub memtable_len: usize,
+    /// Tables merged into one when there are more of them
+    pub max_tables: usize,
+}
+
+impl Default for LsmOptions {
+    fn default() -> Self {
+        Self { key_mode: KeyMode::Exact, memtable_len: 4096, max_tables: 8 }
+    }
+}
+
+/// Stream key and the index of the event, `None` of the record of the stream
+/// with its length, 0 if it's removed
+type RecordKey = (String, Option<EventIndex>);
+
+type Records<'a> = Box<dyn Iterator<Item = Result<(RecordKey, Value), EventStoreError>> + 'a>;
+
+struct State {
+    dir: PathBuf,
+    aggregate_type: &'static str,
+    max_tables: usize,
+    memtable: BTreeMap<RecordKey, Value>,
+    /// Newest first
+    tables: Vec<Table>,
+    next_number: u64,
+}
+
+/// Sorted records in a file, with the footer of the filter and the index,
+/// and the trailer of the footer offset
+struct Table {
+    number: u64,
+    path: PathBuf,
+    bloom: Bloom,
+    /// Keys of every [`INDEX_INTERVAL`]-th record with their offsets
+    index: Vec<(RecordKey, u64)>,
+    /// Offset of the footer, the end of the records
+    end: u64,
+}
+
+const INDEX_INTERVAL: usize = 16;
+/// `{offset:020}\n`
+const TRAILER_LEN: u64 = 21;
+const BLOOM_BITS_PER_KEY: usize = 10;
+const BLOOM_HASHES: u32 = 7;
+
+impl<A> LsmEventStore<A>
+where
+    A: Aggregate,
+    A::Event: ToJson + FromJson,
+    A::IdRef: 'static,
+{
+    /// Opens the store in the directory, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, EventStoreError> {
+        Self::open_with_options(dir, LsmOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(dir: P, options: LsmOptions) -> Result<Self, EventStoreError> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir).map_err(storage_err)?;
+        let aggregate_type = A::aggregate_type().as_ref();
+        let manifest_path = dir.join("MANIFEST");
+        let numbers = match fs::read_to_string(&manifest_path) {
+            Ok(manifest) => {
+                let manifest_err = |e: json::Error| storage_err(format!("{}: {e}", manifest_path.display()));
+                let manifest = json::parse(&manifest).map_err(manifest_err)?;
+                let stored_type = manifest.str_field("aggregate_type").map_err(manifest_err)?;
+                if stored_type != aggregate_type {
+                    return Err(storage_err(format!("{} has the streams of `{stored_type}`", dir.display())));
+                }
+                manifest
+                    .field("tables")
+                    .map_err(manifest_err)?
+                    .as_array()
+                    .ok_or_else(|| manifest_err(json::Error::new("tables must be an array")))?
+                    .iter()
+                    .map(|number| number.as_u64().ok_or_else(|| manifest_err(json::Error::new("table numbers must be integers"))))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(storage_err(e)),
+        };
+
+        // the tables of the interrupted flushes and merges aren't in the manifest
+        for entry in fs::read_dir(&dir).map_err(storage_err)? {
+            let path = entry.map_err(storage_err)?.path();
+            let number = path.file_name().and_then(|name| name.to_str()?.strip_suffix(".sst")?.parse::<u64>().ok());
+            if number.is_some_and(|number| !numbers.contains(&number)) {
+                fs::remove_file(&path).map_err(storage_err)?;
+            }
+        }
+
+        let tables = numbers
+            .iter()
+            .rev()
+            .map(|number| Table::open(table_path(&dir, *number), *number))
+            .collect::<Result<Vec<_>, _>>()?;
+        let next_number = numbers.iter().max().map_or(1, |number| number + 1);
+        let state = State {
+            dir: dir.clone(),
+            aggregate_type,
+            max_tables: options.max_tables,
+            memtable: BTreeMap::new(),
+            tables,
+            next_number,
+        };
+        state.write_manifest(&state.tables)?;
+        Ok(Self {
+            dir,
+            key_mode: options.key_mode,
+            memtable_len: options.memtable_len,
+            state: RwLock::new(state),
+            _aggregate: PhantomData,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Count of the tables, which fetches may read
+    pub fn table_count(&self) -> Result<usize, EventStoreError> {
+        Ok(self.read()?.tables.len())
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, State>, EventStoreError> {
+        self.state.read().map_err(map_locking_err)
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, State>, EventStoreError> {
+        self.state.write().map_err(map_locking_err)
+    }
+
+    fn key(&self, aggregate_id: &A::IdRef) -> String {
+        self.key_mode.key(aggregate_id).into_owned()
+    }
+
+    fn is_flush_due(&self, state: &State) -> bool {
+        state.memtable.len() >= self.memtable_len || state.tables.len() > state.max_tables
+    }
+
+    fn commit_checked(&self, event_list: StoredEventList<A>, expected_len: Option<usize>) -> Result<(), EventStoreError> {
+        let key = self.key(event_list.aggregate_id());
+        let mut state = self.write()?;
+        // the flush which failed after the previous write fails this one before it's applied
+        if self.is_flush_due(&state) {
+            state.flush()?;
+        }
+        let stored = self.fetch_stream(&state, &key)?;
+        if let Some(expected_len) = expected_len {
+            check_expected_len(expected_len, stored.as_ref().map_or(0, |stored| stored.len()))?;
+        }
+
+        // the length hides the events of a longer replaced stream, the
+        // records of a replaced one are overwritten
+        let changes = changes(&event_list, stored.as_deref())?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+        for change in changes {
+            if let Change::Append(event) = change {
+                let index = event.index();
+                let mut record = Record::now(Change::Append(event)).to_json();
+                record.insert("key", Value::from(key.as_str()));
+                state.memtable.insert((key.clone(), Some(index)), record);
+            }
+        }
+        state.memtable.insert((key.clone(), None), stream_record(&key, event_list.aggregate_id().as_ref(), event_list.len()));
+        if self.is_flush_due(&state) {
+            // committed anyway, the next write or sync retries it
+            let _ = state.flush();
+        }
+        Ok(())
+    }
+
+    /// Stream of the key, the newest record of every index up to its length
+    fn fetch_stream(&self, state: &State, key: &str) -> Result<Option<StoredEventList<A>>, EventStoreError> {
+        let mut len: Option<u64> = None;
+        let mut records: BTreeMap<EventIndex, Value> = BTreeMap::new();
+        for source in state.sources(key) {
+            // a source with the events of the stream has its length too, before them
+            for ((_, index), record) in source? {
+                match index {
+                    None => {
+                        if len.is_none() {
+                            len = Some(record.u64_field("len").map_err(storage_err)?);
+                        }
+                    }
+                    Some(index) if len.is_some_and(|len| index < len) => {
+                        records.entry(index).or_insert(record);
+                    }
+                    Some(_) => {}
+                }
+            }
+            if len.is_some_and(|len| records.len() as u64 == len) {
+                break;
+            }
+        }
+        let Some(len) = len.filter(|len| *len > 0) else {
+            return Ok(None);
+        };
+        if records.len() as u64 != len {
+            return Err(storage_err(format!("events of `{key}` are missing in {}", state.dir.display())));
+        }
+
+        let upcasters = A::Event::upcasters();
+        let mut events = StoredEventRawList::new();
+        for record in records.into_values() {
+            if let Change::Append(event) = Record::<A>::read(&record, &upcasters).map_err(storage_err)?.change {
+                events.push(event);
+            }
+        }
+        Ok(events.not_empty())
+    }
+}
+
+impl State {
+    /// Records of the key in the memtable and the tables, newest first
+    fn sources<'a>(&'a self, key: &'a str) -> impl Iterator<Item = Result<Vec<(RecordKey, Value)>, EventStoreError>> + 'a {
+        let memtable = self
+            .memtable
+            .range((key.to_owned(), None)..=(key.to_owned(), Some(EventIndex::MAX)))
+            .map(|(record_key, record)| (record_key.clone(), record.clone()))
+            .collect();
+        std::iter::once(Ok(memtable)).chain(self.tables.iter().map(move |table| table.stream(key)))
+    }
+
+    /// Length of the stream of the key, 0 if it doesn't exist
+    fn stream_len(&self, key: &str) -> Result<usize, EventStoreError> {
+        for source in self.sources(key) {
+            if let Some((_, record)) = source?.into_iter().find(|((_, index), _)| index.is_none()) {
+                return Ok(record.u64_field("len").map_err(storage_err)? as usize);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Records of the memtable and the tables sorted by their keys, the
+    /// newest one of every key
+    fn merged(&self) -> Result<Merge<'_>, EventStoreError> {
+        let memtable: Records<'_> = Box::new(self.memtable.iter().map(|(record_key, record)| Ok((record_key.clone(), record.clone()))));
+        let tables = self.tables.iter().map(Table::records).collect::<Result<Vec<_>, _>>()?;
+        Ok(Merge { sources: std::iter::once(memtable).chain(tables).map(Iterator::peekable).collect() })
+    }
+
+    /// Writes the memtable as the newest table, merging the tables if there
+    /// are too many of them
+    fn flush(&mut self) -> Result<(), EventStoreError> {
+        if !self.memtable.is_empty() {
+            let number = self.next_number;
+            let records = self.memtable.iter().map(|(record_key, record)| Ok((record_key.clone(), record.clone())));
+            let table = Table::write(table_path(&self.dir, number), number, records)?;
+            self.next_number += 1;
+            let mut tables = vec![table];
+            tables.append(&mut self.tables);
+            if let Err(e) = self.write_manifest(&tables) {
+                self.tables = tables.split_off(1);
+                return Err(e);
+            }
+            self.tables = tables;
+            self.memtable.clear();
+        }
+        // also retries a failed merge
+        if self.tables.len() > self.max_tables {
+            self.merge()?;
+        }
+        Ok(())
+    }
+
+    /// Merges all the tables into one without the removed streams and the
+    /// events after the ends of the streams
+    fn merge(&mut self) -> Result<(), EventStoreError> {
+        let number = self.next_number;
+        let mut stream: (String, EventIndex) = (String::new(), 0);
+        let records = self.merged()?.filter_map(|record| {
+            let ((key, index), record) = match record {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            let is_live = match index {
+                None => match record.u64_field("len") {
+                    Ok(len) => {
+                        stream = (key.clone(), len);
+                        len > 0
+                    }
+                    Err(e) => return Some(Err(storage_err(e))),
+                },
+                Some(index) => key == stream.0 && index < stream.1,
+            };
+            is_live.then_some(Ok(((key, index), record)))
+        });
+        let table = Table::write(table_path(&self.dir, number), number, records)?;
+        self.next_number += 1;
+        self.write_manifest(std::slice::from_ref(&table))?;
+        for merged in std::mem::replace(&mut self.tables, vec![table]) {
+            fs::remove_file(&merged.path).map_err(storage_err)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the manifest by the one of the tables, atomically
+    fn write_manifest(&self, tables: &[Table]) -> Result<(), EventStoreError> {
+        let numbers = tables.iter().rev().map(|table| Value::from(table.number)).collect();
+        let manifest = Value::object([("aggregate_type", Value::from(self.aggregate_type)), ("tables", Value::Array(numbers))]);
+        let temp_path = self.dir.join("MANIFEST.tmp");
+        let mut temp_file = File::create(&temp_path).map_err(storage_err)?;
+        temp_file.write_all(format!("{manifest}\n").as_bytes()).map_err(storage_err)?;
+        temp_file.sync_all().map_err(storage_err)?;
+        fs::rename(&temp_path, self.dir.join("MANIFEST")).map_err(storage_err)
+    }
+}
+
+impl Table {
+    /// Writes the records, which must be sorted by their keys, and syncs the file
+    fn write<I>(path: PathBuf, number: u64, records: I) -> Result<Self, EventStoreError>
+    where
+        I: Iterator<Item = Result<(RecordKey, Value), EventStoreError>>,
+    {
+        let mut file = BufWriter::new(File::create(&path).map_err(storage_err)?);
+        let mut offset: u64 = 0;
+        let mut count = 0;
+        let mut index = Vec::new();
+        let mut key_hashes = Vec::new();
+        for record in records {
+            let (record_key, record) = record?;
+            if count % INDEX_INTERVAL == 0 {
+                index.push((record_key.clone(), offset));
+            }
+            if record_key.1.is_none() {
+                key_hashes.push(hash_key(&record_key.0));
+            }
+            let line = format!("{record}\n");
+            file.write_all(line.as_bytes()).map_err(storage_err)?;
+            offset += line.len() as u64;
+            count += 1;
+        }
+
+        let bloom = Bloom::new(&key_hashes);
+        let footer = Value::object([
+            ("records", Value::from(count as u64)),
+            ("bloom", Value::from(bloom.to_hex())),
+            ("hashes", Value::from(bloom.hashes as u64)),
+            ("index", Value::Array(index.iter().map(|((key, event_index), offset)| {
+                Value::Array(vec![Value::from(key.as_str()), Value::from(*event_index), Value::from(*offset)])
+            }).collect())),
+        ]);
+        file.write_all(format!("{footer}\n{offset:020}\n").as_bytes()).map_err(storage_err)?;
+        file.flush().map_err(storage_err)?;
+        file.get_ref().sync_all().map_err(storage_err)?;
+        Ok(Table { number, path, bloom, index, end: offset })
+    }
+
+    /// Reads the footer of the table
+    fn open(path: PathBuf, number: u64) -> Result<Self, EventStoreError> {
+        let table_err = |e: json::Error| storage_err(format!("{}: {e}", path.display()));
+        let mut file = File::open(&path).map_err(storage_err)?;
+        let len = file.metadata().map_err(storage_err)?.len();
+        if len < TRAILER_LEN {
+            return Err(table_err(json::Error::new("the table is truncated")));
+        }
+        let mut trailer = String::new();
+        file.seek(SeekFrom::Start(len - TRAILER_LEN)).map_err(storage_err)?;
+        file.read_to_string(&mut trailer).map_err(storage_err)?;
+        let end: u64 = trailer.trim_end().parse().map_err(|_| table_err(json::Error::new("invalid trailer")))?;
+        if end > len - TRAILER_LEN {
+            return Err(table_err(json::Error::new("the footer is out of the table")));
+        }
+
+        let mut footer = String::new();
+        file.seek(SeekFrom::Start(end)).map_err(storage_err)?;
+        file.take(len - TRAILER_LEN - end).read_to_string(&mut footer).map_err(storage_err)?;
+        let footer = json::parse(footer.trim_end()).map_err(table_err)?;
+        let hashes = u32::try_from(footer.u64_field("hashes").map_err(table_err)?)
+            .map_err(|_| table_err(json::Error::new("count of the hashes is out of range")))?;
+        let bloom = Bloom::from_hex(footer.str_field("bloom").map_err(table_err)?, hashes)
+            .ok_or_else(|| table_err(json::Error::new("invalid bloom filter")))?;
+        let index = footer
+            .field("index")
+            .map_err(table_err)?
+            .as_array()
+            .ok_or_else(|| table_err(json::Error::new("index must be an array")))?
+            .iter()
+            .map(|sample| match sample.as_array() {
+                Some([key, event_index, offset]) => Some(((key.as_str()?.to_owned(), event_index.as_u64()), offset.as_u64()?)),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| table_err(json::Error::new("invalid index sample")))?;
+        Ok(Table { number, path, bloom, index, end })
+    }
+
+    /// All the records in the order of their keys
+    fn records(&self) -> Result<Records<'static>, EventStoreError> {
+        let file = File::open(&self.path).map_err(storage_err)?;
+        let path = self.path.clone();
+        Ok(Box::new(BufReader::new(file.take(self.end)).lines().map(move |line| read_record(&path, &line.map_err(storage_err)?))))
+    }
+
+    /// Records of the key, none if the filter doesn't have it
+    fn stream(&self, key: &str) -> Result<Vec<(RecordKey, Value)>, EventStoreError> {
+        if !self.bloom.may_contain(hash_key(key)) {
+            return Ok(Vec::new());
+        }
+        // the last sample before the key
+        let offset = match self.index.partition_point(|((sampled, _), _)| sampled.as_str() < key) {
+            0 => 0,
+            after => self.index[after - 1].1,
+        };
+        let mut file = File::open(&self.path).map_err(storage_err)?;
+        file.seek(SeekFrom::Start(offset)).map_err(storage_err)?;
+        let mut records = Vec::new();
+        for line in BufReader::new(file.take(self.end - offset)).lines() {
+            let (record_key, record) = read_record(&self.path, &line.map_err(storage_err)?)?;
+            match record_key.0.as_str().cmp(key) {
+                std::cmp::Ordering::Less => {}
+                std::cmp::Ordering::Equal => records.push((record_key, record)),
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Records of the sources sorted by their keys, of the same keys only the one
+/// of the first source
+struct Merge<'a> {
+    sources: Vec<Peekable<Records<'a>>>,
+}
+
+impl Iterator for Merge<'_> {
+    type Item = Result<(RecordKey, Value), EventStoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut first: Option<(usize, RecordKey)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Err(_)) => return source.next(),
+                Some(Ok((record_key, _))) if first.as_ref().is_none_or(|(_, first)| record_key < first) => {
+                    first = Some((i, record_key.clone()));
+                }
+                _ => {}
+            }
+        }
+        let (i, record_key) = first?;
+        for source in &mut self.sources[i + 1..] {
+            source.next_if(|record| matches!(record, Ok((other, _)) if *other == record_key));
+        }
+        self.sources[i].next()
+    }
+}
+
+/// Bloom filter of the stream keys of a table
+struct Bloom {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl Bloom {
+    fn new(key_hashes: &[u64]) -> Self {
+        let words = (key_hashes.len() * BLOOM_BITS_PER_KEY).div_ceil(64).max(1);
+        let mut bloom = Bloom { bits: vec![0; words], hashes: BLOOM_HASHES };
+        for hash in key_hashes {
+            for bit in bloom.bits_of(*hash).collect::<Vec<_>>() {
+                bloom.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        bloom
+    }
+
+    fn may_contain(&self, hash: u64) -> bool {
+        self.bits_of(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Double hashing of the key hash
+    fn bits_of(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let len = self.bits.len() as u64 * 64;
+        let step = hash.rotate_left(32) | 1;
+        (0..self.hashes as u64).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % len) as usize)
+    }
+
+    fn to_hex(&self) -> String {
+        self.bits.iter().map(|word| format!("{word:016x}")).collect()
+    }
+
+    fn from_hex(hex: &str, hashes: u32) -> Option<Self> {
+        if hex.is_empty() || !hex.len().is_multiple_of(16) || !hex.is_ascii() {
+            return None;
+        }
+        let bits = (0..hex.len()).step_by(16).map(|i| u64::from_str_radix(&hex[i..i + 16], 16).ok()).collect::<Option<_>>()?;
+        Some(Bloom { bits, hashes })
+    }
+}
+
+/// FNV-1a with the final mix of MurmurHash3, stable unlike the hashers of
+/// std, as the filters are stored
+fn hash_key(key: &str) -> u64 {
+    let mut hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+fn stream_record(key: &str, aggregate_id: &str, len: usize) -> Value {
+    Value::object([
+        ("key", Value::from(key)),
+        ("aggregate_id", Value::from(aggregate_id)),
+        ("len", Value::from(len as u64)),
+    ])
+}
+
+fn read_record(path: &Path, line: &str) -> Result<(RecordKey, Value), EventStoreError> {
+    let record_err = |e: json::Error| storage_err(format!("{}: {e}", path.display()));
+    let record = json::parse(line).map_err(record_err)?;
+    let key = record.str_field("key").map_err(record_err)?.to_owned();
+    let index = match record.opt_field("index") {
+        Some(_) => Some(record.u64_field("index").map_err(record_err)?),
+        None => None,
+    };
+    Ok(((key, index), record))
+}
+
+fn table_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{number:06}.sst"))
+}
+
+impl<A> EventStore<A> for LsmEventStore<A>
+where
+    A: Aggregate,
+    A::Event: ToJson + FromJson,
+    A::IdRef: 'static,
+{
+    fn fetch(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        let state = self.read()?;
+        self.fetch_stream(&state, &self.key(aggregate_id))?.ok_or(EventStoreError::AggregateIsNotExist)
+    }
+
+    fn is_exist(&self, aggregate_id: &A::IdRef) -> Result<bool, EventStoreError> {
+        Ok(self.read()?.stream_len(&self.key(aggregate_id))? > 0)
+    }
+
+    /// Writes only the events after the stored ones, a list which is shorter
+    /// or differs from the stored events replaces them
+    fn commit(&self, event_list: StoredEventList<A>) -> Result<(), EventStoreError> {
+        self.commit_checked(event_list, None)
+    }
+
+    fn commit_expected(&self, event_list: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError> {
+        self.commit_checked(event_list, Some(expected_len))
+    }
+
+    fn remove(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        let key = self.key(aggregate_id);
+        let mut state = self.write()?;
+        if self.is_flush_due(&state) {
+            state.flush()?;
+        }
+        let event_list = self.fetch_stream(&state, &key)?.ok_or(EventStoreError::AggregateIsNotExist)?;
+        state.memtable.insert((key.clone(), None), stream_record(&key, event_list.aggregate_id().as_ref(), 0));
+        if self.is_flush_due(&state) {
+            let _ = state.flush();
+        }
+        Ok(event_list)
+    }
+
+    /// Scans the records of all the tables
+    fn aggregate_ids(&self) -> Result<Vec<A::Id>, EventStoreError> {
+        let state = self.read()?;
+        let mut aggregate_ids = Vec::new();
+        for record in state.merged()? {
+            let ((_, index), record) = record?;
+            if index.is_none() && record.u64_field("len").map_err(storage_err)? > 0 {
+                aggregate_ids.push(A::Id::from(record.str_field("aggregate_id").map_err(storage_err)?.to_owned()));
+            }
+        }
+        Ok(aggregate_ids)
+    }
+
+    /// Writes the memtable as a table
+    fn sync(&self) -> Result<(), EventStoreError> {
+        self.write()?.flush()
+    }
+}
+
+impl<A: Aggregate> Drop for LsmEventStore<A> {
+    fn drop(&mut self) {
+        if let Ok(state) = self.state.get_mut() {
+            let _ = state.flush();
+        }
+    }
+}
+
+fn storage_err<E: Into<Box<dyn core::error::Error + Send + Sync>>>(e: E) -> EventStoreError {
+    EventStoreError::StorageError(e.into())
+}
+
+fn map_locking_err<E>(_: E) -> EventStoreError {
+    EventStoreError::StorageError("LsmEventStore RwLock had been poisoned".into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clicks::Clicks;
+    use crate::cqrs::file_store::test::TempFile;
+    use crate::cqrs::store::test::{commit_differing_lists, stored_events};
+    use crate::cqrs::wal::{Durability, WalEventStore};
+    use crate::{ShortLinkStatEvent, ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    /// Directory in the temp dir, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{name}-{}-{:?}", std::process::id(), std::thread::current().id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn create(slug: &str) -> StoredEventList<Stats> {
+        StoredEventList::new(&[ShortenerEvent::Create(Slug::from(slug), Url::from("https://example.com"))]).unwrap()
+    }
+
+    fn redirect(slug: &str) -> ShortenerEvent {
+        ShortenerEvent::ShortLinkStatEvent(Slug::from(slug), ShortLinkStatEvent::Redirect)
+    }
+
+    fn redirects(store: &impl EventStore<Stats>, slug: &str) -> Option<u64> {
+        store.fetch(SlugRef::new(slug)).ok().map(|events| events.snapshot().aggregate().redirects)
+    }
+
+    fn open(dir: &TempDir, memtable_len: usize, max_tables: usize) -> LsmEventStore<Stats> {
+        LsmEventStore::open_with_options(&dir.0, LsmOptions { memtable_len, max_tables, ..LsmOptions::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = TempDir::new("lsm_store_persistence");
+        {
+            let store = open(&dir, 3, 100);
+            store.commit(create("a")).unwrap();
+            store.commit(create("b")).unwrap();
+            for _ in 0..5 {
+                store.commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a")])).unwrap();
+            }
+            store.commit(store.fetch(SlugRef::new("b")).unwrap().append_all(&[redirect("b"), redirect("b")])).unwrap();
+            store.remove(SlugRef::new("b")).unwrap();
+            assert!(!store.is_exist(SlugRef::new("b")).unwrap());
+            store.commit(create("b")).unwrap();
+            store.commit(create("c")).unwrap();
+            // the replaced stream is shorter than its events in the older tables
+            store.commit(create("c").append_all(&[redirect("c"), redirect("c"), redirect("c")])).unwrap();
+            let mut replacement = store.fetch(SlugRef::new("c")).unwrap().raw();
+            replacement = StoredEventRawList::from_events(replacement.events()[..2].to_vec());
+            store.commit(replacement.not_empty().unwrap()).unwrap();
+            assert!(store.table_count().unwrap() > 3);
+        }
+
+        let store = open(&dir, 3, 100);
+        assert_eq!(redirects(&store, "a"), Some(5));
+        assert_eq!(redirects(&store, "b"), Some(0));
+        assert_eq!(redirects(&store, "c"), Some(1));
+        assert_eq!(store.fetch(SlugRef::new("c")).unwrap().len(), 2);
+        assert_eq!(redirects(&store, "d"), None);
+        // in the order of the keys
+        assert_eq!(store.aggregate_ids().unwrap(), [Slug::from("a"), Slug::from("b"), Slug::from("c")]);
+    }
+
+    #[test]
+    fn test_differing_commit() {
+        let dir = TempDir::new("lsm_store_differing_commit");
+        // the replacements overwrite the records of the flushed tables
+        let events = commit_differing_lists(&open(&dir, 2, 100));
+        assert_eq!(stored_events(&open(&dir, 2, 100)), events);
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = TempDir::new("lsm_store_merge");
+        let store = open(&dir, 2, 2);
+        for slug in ["a", "gone", "b"] {
+            store.commit(create(slug)).unwrap();
+            store.commit(store.fetch(SlugRef::new(slug)).unwrap().append_all(&[redirect(slug)])).unwrap();
+        }
+        store.remove(SlugRef::new("gone")).unwrap();
+        store.sync().unwrap();
+        store.commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a")])).unwrap();
+        store.sync().unwrap();
+        assert!(store.table_count().unwrap() <= 2);
+        assert_eq!((redirects(&store, "a"), redirects(&store, "gone"), redirects(&store, "b")), (Some(2), None, Some(1)));
+
+        // the merges have dropped the removed stream
+        let tables: Vec<PathBuf> = fs::read_dir(&dir.0).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(tables.iter().filter(|path| path.extension().is_some_and(|ext| ext == "sst")).count(), store.table_count().unwrap());
+        let contents: String = tables.iter().map(|path| fs::read_to_string(path).unwrap()).collect();
+        assert!(!contents.contains(r#""key":"gone""#));
+    }
+
+    #[test]
+    fn test_failed_flush() {
+        let (dir, wal_file) = (TempDir::new("lsm_store_failed_flush"), TempFile::new("lsm_store_failed_flush_log"));
+        let store = WalEventStore::open(&wal_file.0, open(&dir, 2, 100), Durability::EveryCommit).unwrap();
+        // the manifest can't be replaced
+        fs::create_dir(dir.0.join("MANIFEST.tmp")).unwrap();
+
+        // the memtable is full after the commit, which is done anyway
+        store.commit(create("a")).unwrap();
+        assert_eq!(redirects(&store, "a"), Some(0));
+        // the flush is retried before the next commit, which is rejected
+        assert!(matches!(store.commit(create("b")), Err(EventStoreError::StorageError(_))));
+        assert!(matches!(store.store().sync(), Err(EventStoreError::StorageError(_))));
+        assert_eq!(redirects(&store, "b"), None);
+
+        fs::remove_dir(dir.0.join("MANIFEST.tmp")).unwrap();
+        store.commit(create("b")).unwrap();
+        store.store().sync().unwrap();
+        assert_eq!(store.store().table_count().unwrap(), 2);
+        // only the rejected commit is aborted in the log
+        let log = fs::read_to_string(&wal_file.0).unwrap();
+        assert_eq!(log.lines().filter(|line| line.contains(r#""op":"abort""#)).count(), 1);
+        std::mem::forget(store);
+        let store = WalEventStore::open(&wal_file.0, open(&dir, 2, 100), Durability::EveryCommit).unwrap();
+        assert_eq!((redirects(&store, "a"), redirects(&store, "b")), (Some(0), Some(0)));
+        drop(store);
+        let store = open(&dir, 2, 100);
+        assert_eq!((redirects(&store, "a"), redirects(&store, "b")), (Some(0), Some(0)));
+    }
+
+    #[test]
+    fn test_bloom() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("slug{i}")).collect();
+        let bloom = Bloom::new(&keys.iter().map(|key| hash_key(key)).collect::<Vec<_>>());
+        let bloom = Bloom::from_hex(&bloom.to_hex(), bloom.hashes).unwrap();
+        assert!(keys.iter().all(|key| bloom.may_contain(hash_key(key))));
+        let false_positives = (0..1000).filter(|i| bloom.may_contain(hash_key(&format!("other{i}")))).count();
+        assert!(false_positives < 30, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn test_leftover_tables() {
+        let dir = TempDir::new("lsm_store_leftover");
+        drop(open(&dir, 100, 100));
+        {
+            let store = open(&dir, 100, 100);
+            store.commit(create("a")).unwrap();
+        }
+        // a table of an interrupted flush
+        fs::write(dir.0.join("000099.sst"), "{\"key\":\"a\"").unwrap();
+
+        let store = open(&dir, 100, 100);
+        assert!(!dir.0.join("000099.sst").exists());
+        assert_eq!(redirects(&store, "a"), Some(0));
+        assert!(matches!(LsmEventStore::<Clicks>::open(&dir.0), Err(EventStoreError::StorageError(_))));
+    }
+
+    #[test]
+    fn test_write_ahead_log() {
+        let (dir, wal_file) = (TempDir::new("lsm_store_wal"), TempFile::new("lsm_store_wal_log"));
+        let store = WalEventStore::open(&wal_file.0, open(&dir, 100, 100), Durability::EveryCommit).unwrap();
+        store.commit(create("a")).unwrap();
+        store.commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a")])).unwrap();
+        store.checkpoint().unwrap();
+        store.commit(create("b")).unwrap();
+        store.commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a")])).unwrap();
+        // a crash loses the memtable
+        std::mem::forget(store);
+
+        let store = WalEventStore::open(&wal_file.0, open(&dir, 100, 100), Durability::EveryCommit).unwrap();
+        assert_eq!((redirects(&store, "a"), redirects(&store, "b")), (Some(2), Some(0)));
+    }
+}
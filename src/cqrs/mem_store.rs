@@ -1,19 +1,193 @@
+use std::any::Any;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::error::Error;
-use std::sync::{Arc, RwLock};
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
 use crate::cqrs::store::StoredEventList;
-use super::{Aggregate, store::{EventStore, EventStoreError}};
+use super::{Aggregate, store::{changes, check_expected_len, now_millis, EventStore, EventStoreError, LogEntry, LogId, LogPosition, Change}};
 
-pub struct MemEventStore<A: Aggregate> {
+pub use super::store::KeyMode;
+
+/// Streams of the aggregates of any types, namespaced by
+/// [`Aggregate::aggregate_type`], so the ids of different types never clash.
+/// Clones share the streams, so one backend serves the [`MemEventStore`]s of
+/// several aggregates, see [`MemEventStore::with_backend`], and they may
+/// commit their streams together, see [`MemBackend::transaction`]. The log
+/// of the changes is kept along with the streams, see [`EventStore::log`].
+#[derive(Clone, Default)]
+pub struct MemBackend {
     // it's not necessary to use RwLock and Arc instead on Rc,
     // but let's imagine we are working in async/multithreading environment
-    evs: Arc<RwLock<HashMap<A::Id, StoredEventList<A>>>>,
+    state: Arc<RwLock<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    streams: TypedStreams,
+    next_position: LogPosition,
+}
+
+/// Values are `Streams<A>` of the aggregate type
+type TypedStreams = HashMap<&'static str, Box<dyn Any + Send + Sync>>;
+
+/// Restores a stream changed by a transaction
+type Undo = Box<dyn FnOnce(&mut TypedStreams)>;
+
+/// Streams of an aggregate type with the log of their changes
+struct Streams<A: Aggregate> {
+    lists: HashMap<String, StoredEventList<A>>,
+    log: Vec<LogEntry<A>>,
+}
+
+pub struct MemEventStore<A: Aggregate> {
+    backend: MemBackend,
+    key_mode: KeyMode,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+/// Commits of the streams of several aggregates of a [`MemBackend`], visible
+/// all at once by [`MemTransaction::commit`]. The backend stays locked, so its
+/// stores must not be used until the transaction is committed or dropped,
+/// which rolls it back.
+pub struct MemTransaction<'a> {
+    backend: &'a MemBackend,
+    state: RwLockWriteGuard<'a, State>,
+    start_position: LogPosition,
+    undo: Vec<Undo>,
+}
+
+impl MemBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks the backend for the commits of several streams, see [`MemTransaction`]
+    pub fn transaction(&self) -> Result<MemTransaction<'_>, EventStoreError> {
+        let state = self.state.write().map_err(map_locking_err)?;
+        let start_position = state.next_position;
+        Ok(MemTransaction { backend: self, state, start_position, undo: Vec::new() })
+    }
+
+    fn read<A, R, F>(&self, f: F) -> Result<R, EventStoreError>
+    where
+        A: Aggregate + Send + Sync + 'static,
+        A::Id: Send + Sync,
+        F: FnOnce(Option<&Streams<A>>) -> R,
+    {
+        let state = self.state.read().map_err(map_locking_err)?;
+        match state.streams.get(A::aggregate_type().as_ref()) {
+            Some(typed) => Ok(f(Some(downcast_ref::<A>(&**typed)?))),
+            None => Ok(f(None)),
+        }
+    }
+
+    fn write<A, R, F>(&self, f: F) -> Result<R, EventStoreError>
+    where
+        A: Aggregate + Send + Sync + 'static,
+        A::Id: Send + Sync,
+        F: FnOnce(&mut Streams<A>, &mut LogPosition) -> Result<R, EventStoreError>,
+    {
+        let mut state = self.state.write().map_err(map_locking_err)?;
+        let State { streams, next_position } = &mut *state;
+        f(typed_mut::<A>(streams)?, next_position)
+    }
+}
+
+impl<A: Aggregate> Streams<A> {
+    fn len(&self, key: &str) -> usize {
+        self.lists.get(key).map_or(0, |events| events.len())
+    }
+
+    /// Replaces the stream by the list, returns the previous one
+    fn commit(&mut self, key: String, event_list: StoredEventList<A>, next_position: &mut LogPosition) -> Result<Option<StoredEventList<A>>, EventStoreError> {
+        let changes = changes(&event_list, self.lists.get(&key).map(|stored| &**stored))?;
+        self.log_changes(changes, next_position);
+        Ok(self.lists.insert(key, event_list))
+    }
+
+    fn remove(&mut self, key: &str, next_position: &mut LogPosition) -> Option<StoredEventList<A>> {
+        let removed = self.lists.remove(key)?;
+        self.log_changes(vec![Change::Remove(removed.aggregate_id().to_owned())], next_position);
+        Some(removed)
+    }
+
+    fn log_changes(&mut self, changes: Vec<Change<A>>, next_position: &mut LogPosition) {
+        let recorded_at = Some(now_millis());
+        for change in changes {
+            self.log.push(LogEntry { position: *next_position, recorded_at, change });
+            *next_position += 1;
+        }
+    }
+}
+
+impl<A: Aggregate> Default for Streams<A> {
+    fn default() -> Self {
+        Self { lists: HashMap::new(), log: Vec::new() }
+    }
+}
+
+fn typed_mut<A>(streams: &mut TypedStreams) -> Result<&mut Streams<A>, EventStoreError>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send + Sync,
+{
+    let typed = streams
+        .entry(A::aggregate_type().as_ref())
+        .or_insert_with(|| Box::new(Streams::<A>::default()));
+    downcast_mut::<A>(&mut **typed)
+}
+
+fn downcast_ref<A>(typed: &(dyn Any + Send + Sync)) -> Result<&Streams<A>, EventStoreError>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send + Sync,
+{
+    typed.downcast_ref().ok_or_else(|| type_clash_err::<A>())
+}
+
+fn downcast_mut<A>(typed: &mut (dyn Any + Send + Sync)) -> Result<&mut Streams<A>, EventStoreError>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send + Sync,
+{
+    typed.downcast_mut().ok_or_else(|| type_clash_err::<A>())
+}
+
+fn type_clash_err<A: Aggregate + 'static>() -> EventStoreError {
+    let aggregate_type = A::aggregate_type().as_ref();
+    EventStoreError::StorageError(format!("aggregate type `{aggregate_type}` is used by another aggregate").into())
 }
 
 impl<A: Aggregate> MemEventStore<A> {
     #[allow(dead_code)]
     pub fn new() -> Self {
-        Self { evs: Arc::new(RwLock::new(HashMap::new())) }
+        Self::with_key_mode(KeyMode::Exact)
+    }
+
+    #[allow(dead_code)]
+    pub fn with_key_mode(key_mode: KeyMode) -> Self {
+        Self::with_backend(MemBackend::new(), key_mode)
+    }
+
+    /// Store of the streams of `A` in the shared backend. The stores of the
+    /// same aggregate type in a backend must use the same key mode.
+    pub fn with_backend(backend: MemBackend, key_mode: KeyMode) -> Self {
+        Self { backend, key_mode, _aggregate: PhantomData }
+    }
+
+    pub fn backend(&self) -> &MemBackend {
+        &self.backend
+    }
+
+    fn key<'a>(&self, aggregate_id: &'a A::IdRef) -> Cow<'a, str> {
+        self.key_mode.key(aggregate_id)
+    }
+}
+
+impl<A: Aggregate> Default for MemEventStore<A> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -21,40 +195,327 @@ fn map_locking_err<E: Error>(_: E) -> EventStoreError {
     EventStoreError::StorageError("MemStorage RwLock had been poisoned".into())
 }
 
-impl<A: Aggregate> EventStore<A> for MemEventStore<A> {
-
+impl<A> EventStore<A> for MemEventStore<A>
+where
+    A: Aggregate + Send + Sync + 'static,
+    A::Id: Send + Sync,
+{
     fn fetch(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
-        let events_map = self.evs.read().map_err(map_locking_err)?;
-        let events = match events_map.get(aggregate_id) {
-            Some(v) => v.clone(),
-            None => return Err(EventStoreError::AggregateIsNotExist),
-        };
-        if events.is_empty() {
-            return Err(EventStoreError::AggregateIsNotExist)
-        }
-        Ok(events)
+        let key = self.key(aggregate_id);
+        self.backend
+            .read::<A, _, _>(|streams| streams.and_then(|streams| streams.lists.get(key.as_ref())).cloned())?
+            .filter(|events| !events.is_empty())
+            .ok_or(EventStoreError::AggregateIsNotExist)
     }
 
     fn is_exist(&self, aggregate_id: &<A as Aggregate>::IdRef) -> Result<bool, EventStoreError> {
-        let events_map = self.evs.read().map_err(map_locking_err)?;
-        Ok(events_map.contains_key(aggregate_id))
+        let key = self.key(aggregate_id);
+        self.backend.read::<A, _, _>(|streams| streams.is_some_and(|streams| streams.lists.contains_key(key.as_ref())))
     }
 
     fn commit(&self, event_list: StoredEventList<A>) -> Result<(), EventStoreError> {
-        let mut events_map = self.evs.write().map_err(map_locking_err)?;
-        events_map.insert(event_list.aggregate_id().to_owned(), event_list);
-        Ok(())
+        let key = self.key(event_list.aggregate_id()).into_owned();
+        self.backend.write(|streams, next_position| {
+            streams.commit(key, event_list, next_position)?;
+            Ok(())
+        })
+    }
+
+    fn commit_expected(&self, event_list: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError> {
+        let key = self.key(event_list.aggregate_id()).into_owned();
+        self.backend.write(|streams, next_position| {
+            check_expected_len(expected_len, streams.len(&key))?;
+            streams.commit(key, event_list, next_position)?;
+            Ok(())
+        })
     }
 
     fn remove(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
-        let events_map_read = self.evs.read().map_err(map_locking_err)?;
-        let event_list  = match events_map_read.get(aggregate_id) {
-            Some(x) => x.clone(),
-            None => return Err(EventStoreError::AggregateIsNotExist),
-        };
-        drop(events_map_read);
-        let mut events_map_write = self.evs.write().map_err(map_locking_err)?;
-        events_map_write.remove(aggregate_id);
-        Ok(event_list)
+        let key = self.key(aggregate_id);
+        self.backend.write(|streams, next_position| {
+            streams.remove(key.as_ref(), next_position).ok_or(EventStoreError::AggregateIsNotExist)
+        })
+    }
+
+    fn aggregate_ids(&self) -> Result<Vec<A::Id>, EventStoreError> {
+        self.backend.read::<A, _, _>(|streams| {
+            streams
+                .into_iter()
+                .flat_map(|streams| streams.lists.values())
+                .filter(|events| !events.is_empty())
+                .map(|events| events.aggregate_id().to_owned())
+                .collect()
+        })
+    }
+
+    fn log(&self) -> Result<Vec<LogEntry<A>>, EventStoreError> {
+        self.backend.read::<A, _, _>(|streams| streams.map_or_else(Vec::new, |streams| streams.log.clone()))
+    }
+
+    /// The log of the backend, shared by the stores of all the aggregate types
+    fn log_id(&self) -> Option<LogId> {
+        Some(LogId::of(&self.backend.state))
+    }
+}
+
+impl MemTransaction<'_> {
+    /// Commits the list if its stream has the expected length, as
+    /// [`EventStore::commit_expected`] does. The following commits of the
+    /// transaction see the list as stored.
+    pub fn commit_expected<A>(&mut self, store: &MemEventStore<A>, event_list: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError>
+    where
+        A: Aggregate + Send + Sync + 'static,
+        A::Id: Send + Sync,
+    {
+        if !Arc::ptr_eq(&store.backend.state, &self.backend.state) {
+            return Err(EventStoreError::StorageError("the store is of another backend".into()));
+        }
+        let key = store.key(event_list.aggregate_id()).into_owned();
+        let State { streams, next_position } = &mut *self.state;
+        let streams = typed_mut::<A>(streams)?;
+        check_expected_len(expected_len, streams.len(&key))?;
+
+        let log_len = streams.log.len();
+        let previous = streams.commit(key.clone(), event_list, next_position)?;
+        self.undo.push(Box::new(move |streams| {
+            if let Ok(streams) = typed_mut::<A>(streams) {
+                streams.log.truncate(log_len);
+                match previous {
+                    Some(previous) => streams.lists.insert(key, previous),
+                    None => streams.lists.remove(&key),
+                };
+            }
+        }));
+        Ok(())
+    }
+
+    /// Commits the list of a new stream, as [`EventStore::commit_new`] does
+    pub fn commit_new<A>(&mut self, store: &MemEventStore<A>, event_list: StoredEventList<A>) -> Result<(), EventStoreError>
+    where
+        A: Aggregate + Send + Sync + 'static,
+        A::Id: Send + Sync,
+    {
+        match self.commit_expected(store, event_list, 0) {
+            Err(EventStoreError::ConcurrencyConflict { .. }) => Err(EventStoreError::AggregateAlreadyExists),
+            result => result,
+        }
+    }
+
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+}
+
+impl Drop for MemTransaction<'_> {
+    fn drop(&mut self) {
+        if self.undo.is_empty() {
+            return;
+        }
+        while let Some(undo) = self.undo.pop() {
+            undo(&mut self.state.streams);
+        }
+        self.state.next_position = self.start_position;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clicks::{ClickEvent, Clicks};
+    use crate::cqrs::store::test::commit_differing_lists;
+    use crate::{ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    fn create(slug: &str) -> StoredEventList<Stats> {
+        StoredEventList::new(&[ShortenerEvent::Create(Slug::from(slug), Url::from("https://example.com"))]).unwrap()
+    }
+
+    fn redirected(slug: &str) -> StoredEventList<Clicks> {
+        StoredEventList::new(&[ClickEvent::Redirected(Slug::from(slug))]).unwrap()
+    }
+
+    #[test]
+    fn test_shared_backend() {
+        let backend = MemBackend::new();
+        let links = MemEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact);
+        let clicks = MemEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact);
+
+        // the same ids of different types are different streams
+        links.commit(create("a")).unwrap();
+        assert!(!clicks.is_exist(SlugRef::new("a")).unwrap());
+        clicks.commit(redirected("a")).unwrap();
+        assert_eq!(links.fetch(SlugRef::new("a")).unwrap().snapshot().aggregate().link.url.as_str(), "https://example.com");
+        assert_eq!(clicks.fetch(SlugRef::new("a")).unwrap().snapshot().aggregate().redirects, 1);
+
+        // other stores of the backend see the streams
+        let other_links = MemEventStore::<Stats>::with_backend(backend, KeyMode::Exact);
+        assert!(other_links.is_exist(SlugRef::new("a")).unwrap());
+        clicks.remove(SlugRef::new("a")).unwrap();
+        assert!(other_links.is_exist(SlugRef::new("a")).unwrap());
+        assert!(matches!(clicks.remove(SlugRef::new("a")), Err(EventStoreError::AggregateIsNotExist)));
+    }
+
+    #[test]
+    fn test_commit_new() {
+        let links = MemEventStore::<Stats>::with_key_mode(KeyMode::Normalized);
+        links.commit_new(create("Promo")).unwrap();
+        assert!(matches!(links.commit_new(create("promo")), Err(EventStoreError::AggregateAlreadyExists)));
+        assert_eq!(links.fetch(SlugRef::new("PROMO")).unwrap().aggregate_id(), SlugRef::new("Promo"));
+    }
+
+    #[test]
+    fn test_compacted_list() {
+        let clicks = MemEventStore::<Clicks>::new();
+        let mut events = redirected("a").append_all(&[ClickEvent::Redirected(Slug::from("a"))]).raw();
+        assert_eq!(events.compact(0).len(), 1);
+        let events = events.not_empty().unwrap();
+        assert_eq!((events.len(), events.snapshot().aggregate().redirects), (2, 2));
+        // the archived events of a new stream can't be stored
+        assert!(matches!(clicks.commit(events.clone()), Err(EventStoreError::InconsistentEventIndex)));
+
+        clicks.commit(redirected("a").append_all(&[ClickEvent::Redirected(Slug::from("a"))])).unwrap();
+        clicks.commit(events.append_all(&[ClickEvent::Redirected(Slug::from("a"))])).unwrap();
+        assert_eq!(clicks.fetch(SlugRef::new("a")).unwrap().snapshot().aggregate().redirects, 3);
+        assert_eq!(clicks.log().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_differing_commit() {
+        let links = MemEventStore::<Stats>::new();
+        commit_differing_lists(&links);
+        // the replacements are logged as removals and full appends
+        assert_eq!(links.log().unwrap().len(), 2 + 3 + 4);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let backend = MemBackend::new();
+        let links = MemEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact);
+        let clicks = MemEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact);
+
+        let mut transaction = backend.transaction().unwrap();
+        transaction.commit_expected(&links, create("a"), 0).unwrap();
+        transaction.commit_expected(&clicks, redirected("a"), 0).unwrap();
+        // the commits see the previous ones
+        let clicked = redirected("a").append_all(&[ClickEvent::Redirected(Slug::from("a"))]);
+        transaction.commit_expected(&clicks, clicked, 1).unwrap();
+        transaction.commit();
+        assert!(links.is_exist(SlugRef::new("a")).unwrap());
+        assert_eq!(clicks.fetch(SlugRef::new("a")).unwrap().len(), 2);
+
+        // a conflict of any stream rolls back the whole transaction
+        let mut transaction = backend.transaction().unwrap();
+        transaction.commit_expected(&links, create("b"), 0).unwrap();
+        transaction.commit_expected(&clicks, redirected("a"), 2).unwrap();
+        assert!(matches!(
+            transaction.commit_expected(&links, create("a"), 0),
+            Err(EventStoreError::ConcurrencyConflict { expected: 0, actual: 1 }),
+        ));
+        assert!(matches!(transaction.commit_new(&links, create("b")), Err(EventStoreError::AggregateAlreadyExists)));
+        drop(transaction);
+        assert!(!links.is_exist(SlugRef::new("b")).unwrap());
+        assert_eq!(clicks.fetch(SlugRef::new("a")).unwrap().len(), 2);
+
+        let other = MemEventStore::<Stats>::new();
+        let mut transaction = backend.transaction().unwrap();
+        assert!(matches!(transaction.commit_expected(&other, create("c"), 0), Err(EventStoreError::StorageError(_))));
+    }
+
+    #[test]
+    fn test_log() {
+        let backend = MemBackend::new();
+        let links = MemEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact);
+        let clicks = MemEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact);
+        let redirect = ShortenerEvent::ShortLinkStatEvent(Slug::from("a"), crate::ShortLinkStatEvent::Redirect);
+        links.commit(create("a").append_all(&[redirect])).unwrap();
+        clicks.commit(redirected("a")).unwrap();
+        links.commit(create("a")).unwrap();
+
+        // a rolled back transaction takes no positions
+        let mut transaction = backend.transaction().unwrap();
+        transaction.commit_expected(&links, create("b"), 0).unwrap();
+        drop(transaction);
+        links.commit(create("c")).unwrap();
+
+        // the positions are shared by the types, a replacing commit removes the stream first
+        let log = links.log().unwrap();
+        assert_eq!(log.iter().map(|entry| entry.position).collect::<Vec<_>>(), [0, 1, 3, 4, 5]);
+        assert!(matches!(&log[2].change, Change::Remove(slug) if slug.as_str() == "a"));
+        assert!(matches!(&log[4].change, Change::Append(event) if event.aggregate_id().as_str() == "c"));
+        assert!(log.iter().all(|entry| entry.recorded_at.is_some()));
+        assert_eq!(clicks.log().unwrap()[0].position, 2);
+    }
+
+    #[test]
+    fn test_concurrent_transactions() {
+        use std::sync::Barrier;
+        let backend = MemBackend::new();
+        let links = MemEventStore::<Stats>::with_backend(backend.clone(), KeyMode::Exact);
+        // counts the links created by the owner
+        let quota = MemEventStore::<Clicks>::with_backend(backend.clone(), KeyMode::Exact);
+        let barrier = Barrier::new(8);
+
+        let created = std::thread::scope(|s| {
+            let handles = (0..8)
+                .map(|_| s.spawn(|| {
+                    barrier.wait();
+                    loop {
+                        let (events, expected_len) = match quota.fetch(SlugRef::new("owner")) {
+                            Ok(events) => {
+                                let len = events.len();
+                                (events.append_all(&[ClickEvent::Redirected(Slug::from("owner"))]), len)
+                            }
+                            Err(_) => (redirected("owner"), 0),
+                        };
+                        let mut transaction = backend.transaction().unwrap();
+                        if transaction.commit_expected(&links, create("a"), 0).is_err() {
+                            return false;
+                        }
+                        match transaction.commit_expected(&quota, events, expected_len) {
+                            Ok(()) => {
+                                transaction.commit();
+                                return true;
+                            }
+                            Err(EventStoreError::ConcurrencyConflict { .. }) => continue,
+                            Err(e) => panic!("{e}"),
+                        }
+                    }
+                }))
+                .collect::<Vec<_>>();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|created| *created)
+                .count()
+        });
+        assert_eq!(created, 1);
+        assert_eq!(quota.fetch(SlugRef::new("owner")).unwrap().len(), 1);
+    }
+
+    /// Aggregate claiming the type of [`Stats`]
+    #[derive(Clone, Default)]
+    struct Impostor(Stats);
+
+    impl Aggregate for Impostor {
+        type Event = ShortenerEvent;
+        type Id = Slug;
+        type IdRef = SlugRef;
+        fn aggregate_type() -> &'static SlugRef {
+            Stats::aggregate_type()
+        }
+        fn aggregate_id(&self) -> &SlugRef {
+            self.0.aggregate_id()
+        }
+        fn apply(&mut self, event: ShortenerEvent) {
+            self.0.apply(event)
+        }
+    }
+
+    #[test]
+    fn test_aggregate_type_clash() {
+        let links = MemEventStore::<Stats>::new();
+        links.commit(create("a")).unwrap();
+        let impostor = MemEventStore::<Impostor>::with_backend(links.backend().clone(), KeyMode::Exact);
+        assert!(matches!(impostor.fetch(SlugRef::new("a")), Err(EventStoreError::StorageError(_))));
+        let event_list = StoredEventList::<Impostor>::new(&[ShortenerEvent::Create(Slug::from("b"), Url::from("https://example.com"))]).unwrap();
+        assert!(matches!(impostor.commit(event_list), Err(EventStoreError::StorageError(_))));
     }
 }
@@ -1,9 +1,16 @@
+use std::borrow::Cow;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use crate::OwnedContract;
 
-use super::{Aggregate, IsEmptyAggregateId};
+use super::{Aggregate, AggregateIdRefContract, IsEmptyAggregateId};
 
 pub type EventIndex = u64;
 
+/// Position of an entry in the log of a store backend, i.e. the number of the
+/// entries before it, across the streams of all the aggregate types
+pub type LogPosition = u64;
+
 #[derive(Debug)]
 pub struct StoredEvent<A: Aggregate> {
     aggregate_id: A::Id,
@@ -11,8 +18,14 @@ pub struct StoredEvent<A: Aggregate> {
     event: A::Event,
 }
 
+/// Events of a stream in the order of their indices. A compacted stream
+/// starts with the `base` state after its archived events, see
+/// [`StoredEventRawList::compact`].
 #[derive(Clone, Default)]
-pub struct StoredEventRawList<A: Aggregate>(Vec<StoredEvent<A>>);
+pub struct StoredEventRawList<A: Aggregate> {
+    base: Option<Snapshot<A>>,
+    events: Vec<StoredEvent<A>>,
+}
 pub struct StoredEventRefList<A: Aggregate>([StoredEvent<A>]);
 
 #[derive(Clone)]
@@ -24,20 +37,202 @@ pub struct Snapshot<A: Aggregate> {
     index: EventIndex,
 }
 
+/// Change of a stream in the log of the commits, see [`EventStore::log`]
+pub struct LogEntry<A: Aggregate> {
+    pub position: LogPosition,
+    /// Milliseconds since the unix epoch, `None` for the entries written
+    /// before the times were recorded
+    pub recorded_at: Option<u64>,
+    pub change: Change<A>,
+}
+
+/// Identity of the log of a backend, the stores of one backend share it and
+/// their positions, see [`EventStore::log_id`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogId(usize);
+
+impl LogId {
+    /// Identity of the shared state of a backend
+    pub(crate) fn of<T>(backend: &std::sync::Arc<T>) -> Self {
+        Self(std::sync::Arc::as_ptr(backend) as *const () as usize)
+    }
+}
+
+pub enum Change<A: Aggregate> {
+    Append(StoredEvent<A>),
+    /// Removal of the stream, or its replacement by the following appends
+    Remove(A::Id),
+}
+
+/// How aggregate ids are turned into the keys of the streams
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyMode {
+    /// Streams are keyed by the aggregate id as is
+    #[default]
+    Exact,
+    /// Streams are keyed by [`AggregateIdRefContract::normalized_key`], so
+    /// e.g. `Promo` and `promo` are the same aggregate. The stored events keep
+    /// the id they were created with.
+    Normalized,
+}
+
+impl KeyMode {
+    pub fn key<'a, I: AggregateIdRefContract + ?Sized>(self, aggregate_id: &'a I) -> Cow<'a, str> {
+        match self {
+            KeyMode::Exact => Cow::Borrowed(aggregate_id.as_ref()),
+            KeyMode::Normalized => aggregate_id.normalized_key(),
+        }
+    }
+}
+
 pub trait EventStore<A: Aggregate> {
     fn fetch(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError>;
     fn is_exist(&self, aggregate_id: &A::IdRef) -> Result<bool, EventStoreError>;
     fn commit(&self, state: StoredEventList<A>) -> Result<(), EventStoreError>;
     fn remove(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError>;
+
+    /// Commits the list only if the stored stream still has `expected_len`
+    /// events (0 if it doesn't exist), i.e. nobody has committed to it since
+    /// it was fetched. Fails with [`EventStoreError::ConcurrencyConflict`]
+    /// otherwise.
+    ///
+    /// The default implementation isn't atomic, stores should override it.
+    fn commit_expected(&self, state: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError> {
+        let actual_len = match self.fetch(state.aggregate_id()) {
+            Ok(stored) => stored.len(),
+            Err(EventStoreError::AggregateIsNotExist) => 0,
+            Err(e) => return Err(e),
+        };
+        check_expected_len(expected_len, actual_len)?;
+        self.commit(state)
+    }
+
+    /// Ids of all the stored aggregates, e.g. for the export. Stores which
+    /// can't list their streams fail by default.
+    fn aggregate_ids(&self) -> Result<Vec<A::Id>, EventStoreError> {
+        Err(EventStoreError::StorageError("the store can't list its streams".into()))
+    }
+
+    /// Fetches the stream with the events from the index on, loading the
+    /// archived ones of a compacted stream if needed, e.g. for
+    /// [`StoredEventList::snapshot_at`] of an old event. Stores without the
+    /// archive fetch the stream as is by default.
+    fn fetch_from(&self, aggregate_id: &A::IdRef, _index: EventIndex) -> Result<StoredEventList<A>, EventStoreError> {
+        self.fetch(aggregate_id)
+    }
+
+    /// Changes of the streams in the order of the commits, for the queries of
+    /// the past states, see [`history`](super::history). Stores without the
+    /// log fail by default.
+    fn log(&self) -> Result<Vec<LogEntry<A>>, EventStoreError> {
+        Err(EventStoreError::StorageError("the store doesn't keep the log".into()))
+    }
+
+    /// Identity of the log of [`EventStore::log`], the same for the stores
+    /// of one backend. Stores without a shared log have none by default.
+    fn log_id(&self) -> Option<LogId> {
+        None
+    }
+
+    /// Commits the list of a new stream, fails with
+    /// [`EventStoreError::AggregateAlreadyExists`] if the stream exists, so of
+    /// the concurrent creators of a stream only one succeeds.
+    ///
+    /// The default implementation is as atomic as [`EventStore::commit_expected`].
+    fn commit_new(&self, state: StoredEventList<A>) -> Result<(), EventStoreError> {
+        match self.commit_expected(state, 0) {
+            Err(EventStoreError::ConcurrencyConflict { .. }) => Err(EventStoreError::AggregateAlreadyExists),
+            result => result,
+        }
+    }
+
+    /// Makes the commits durable, e.g. syncs the file to the disk, so a crash
+    /// of the machine doesn't lose them. Stores which keep nothing on disk
+    /// have nothing to sync by default.
+    fn sync(&self) -> Result<(), EventStoreError> {
+        Ok(())
+    }
+}
+
+/// Store shared by several owners, e.g. services and tools over the same data
+impl<A: Aggregate, S: EventStore<A> + ?Sized> EventStore<A> for std::sync::Arc<S> {
+    fn fetch(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        (**self).fetch(aggregate_id)
+    }
+
+    fn is_exist(&self, aggregate_id: &A::IdRef) -> Result<bool, EventStoreError> {
+        (**self).is_exist(aggregate_id)
+    }
+
+    fn commit(&self, state: StoredEventList<A>) -> Result<(), EventStoreError> {
+        (**self).commit(state)
+    }
+
+    fn fetch_from(&self, aggregate_id: &A::IdRef, index: EventIndex) -> Result<StoredEventList<A>, EventStoreError> {
+        (**self).fetch_from(aggregate_id, index)
+    }
+
+    fn remove(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        (**self).remove(aggregate_id)
+    }
+
+    fn commit_expected(&self, state: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError> {
+        (**self).commit_expected(state, expected_len)
+    }
+
+    fn commit_new(&self, state: StoredEventList<A>) -> Result<(), EventStoreError> {
+        (**self).commit_new(state)
+    }
+
+    fn aggregate_ids(&self) -> Result<Vec<A::Id>, EventStoreError> {
+        (**self).aggregate_ids()
+    }
+
+    fn log(&self) -> Result<Vec<LogEntry<A>>, EventStoreError> {
+        (**self).log()
+    }
+
+    fn log_id(&self) -> Option<LogId> {
+        (**self).log_id()
+    }
+
+    fn sync(&self) -> Result<(), EventStoreError> {
+        (**self).sync()
+    }
+}
+
+pub(crate) fn check_expected_len(expected: usize, actual: usize) -> Result<(), EventStoreError> {
+    match expected == actual {
+        true => Ok(()),
+        false => Err(EventStoreError::ConcurrencyConflict { expected, actual }),
+    }
 }
 
 impl<A: Aggregate> StoredEvent<A> {
+    pub fn new(aggregate_id: A::Id, index: EventIndex, event: A::Event) -> Self {
+        Self { aggregate_id, index, event }
+    }
+
     pub fn aggregate_id(&self) -> &A::IdRef {
         &self.aggregate_id
     }
+
+    pub fn index(&self) -> EventIndex {
+        self.index
+    }
+
+    pub fn event(&self) -> &A::Event {
+        &self.event
+    }
 }
 
 impl<A: Aggregate> Snapshot<A> {
+    /// State after the event at the index, e.g. loaded from a file
+    pub fn new(aggregate: A, index: EventIndex) -> Self {
+        Self { aggregate, index }
+    }
+
     pub fn aggregate(&self) -> &A {
         &self.aggregate
     }
@@ -55,9 +250,65 @@ impl<A: Aggregate> Snapshot<A> {
     }
 }
 
+/// Changes of the stored stream committing the list. Lists are expected to
+/// be fetched from the store and extended, so only the events after the
+/// stored ones are appended. A list which is shorter or differs from the
+/// stored events replaces the stream. A compacted list must extend the stored
+/// stream, its archived events can't be written, the error is
+/// [`EventStoreError::InconsistentEventIndex`] otherwise. The events archived
+/// on either side aren't compared.
+pub(crate) fn changes<A: Aggregate>(event_list: &StoredEventList<A>, stored: Option<&StoredEventRawList<A>>) -> Result<Vec<Change<A>>, EventStoreError> {
+    let first_index = event_list.first_index() as usize;
+    let stored_len = stored.map_or(0, |stored| stored.len());
+    let mut changes = Vec::new();
+    let new_events = match stored.is_none_or(|stored| extends(event_list, stored)) {
+        true if first_index > stored_len => return Err(EventStoreError::InconsistentEventIndex),
+        true => &event_list.events()[stored_len - first_index..],
+        false if first_index > 0 => return Err(EventStoreError::InconsistentEventIndex),
+        false => {
+            changes.push(Change::Remove(event_list.aggregate_id().to_owned()));
+            event_list.events()
+        }
+    };
+    changes.extend(new_events.iter().cloned().map(Change::Append));
+    Ok(changes)
+}
+
+/// Whether the list keeps the stored events, which both have, and adds its
+/// own after them
+fn extends<A: Aggregate>(event_list: &StoredEventRawList<A>, stored: &StoredEventRawList<A>) -> bool {
+    if event_list.len() < stored.len() {
+        return false;
+    }
+    let first_index = event_list.first_index().max(stored.first_index());
+    let events = event_list.events().iter().skip((first_index - event_list.first_index()) as usize);
+    let stored_events = stored.events().iter().skip((first_index - stored.first_index()) as usize);
+    events.zip(stored_events).all(|(event, stored_event)| event.event == stored_event.event)
+}
+
+/// Time of the log entries
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+impl<A: Aggregate> Clone for LogEntry<A> {
+    fn clone(&self) -> Self {
+        Self { position: self.position, recorded_at: self.recorded_at, change: self.change.clone() }
+    }
+}
+
+impl<A: Aggregate> Clone for Change<A> {
+    fn clone(&self) -> Self {
+        match self {
+            Change::Append(event) => Change::Append(event.clone()),
+            Change::Remove(aggregate_id) => Change::Remove(aggregate_id.clone()),
+        }
+    }
+}
+
 impl<A: Aggregate> Clone for StoredEvent<A> {
     fn clone(&self) -> Self {
-        return Self {
+        Self {
             aggregate_id: self.aggregate_id.clone(),
             index: self.index,
             event: self.event.clone(),
@@ -73,24 +324,66 @@ impl<A: Aggregate> StoredEventRefList<A> {
 }
 
 impl<A: Aggregate> StoredEventRawList<A> {
-    pub fn new() -> Self { Self(Vec::new()) }
-    
+    pub fn new() -> Self { Self::from_events(Vec::new()) }
+
+    /// Wraps already stored events (e.g. loaded from a file) as is, see
+    /// [`StoredEventRawList::check_consistency`] to validate them
+    pub fn from_events(events: Vec<StoredEvent<A>>) -> Self { Self { base: None, events } }
+
+    /// Compacted stream without the events after its archived ones yet
+    pub fn from_snapshot(base: Snapshot<A>) -> Self { Self { base: Some(base), events: Vec::new() } }
+
+    /// Events after the archived ones, all of them if the stream isn't compacted
+    pub fn events(&self) -> &[StoredEvent<A>] {
+        &self.events
+    }
+
+    /// State after the archived events of a compacted stream
+    pub fn base(&self) -> Option<&Snapshot<A>> {
+        self.base.as_ref()
+    }
+
+    /// Index of the first event of [`StoredEventRawList::events`]
+    pub fn first_index(&self) -> EventIndex {
+        self.base.as_ref().map_or(0, |base| base.index + 1)
+    }
+
+    /// Adds already stored event as is, without any checks
+    pub fn push(&mut self, event: StoredEvent<A>) {
+        self.events.push(event)
+    }
+
+    /// Moves the events up to the index out of the list, keeping the state
+    /// after them as its base. Returns the moved events, none if the index
+    /// is out of the list or already compacted.
+    pub fn compact(&mut self, index: EventIndex) -> Vec<StoredEvent<A>> {
+        if index < self.first_index() {
+            return Vec::new();
+        }
+        let Some(base) = self.snapshot_at(index) else {
+            return Vec::new();
+        };
+        let archived = self.events.drain(..=(index - self.first_index()) as usize).collect();
+        self.base = Some(base);
+        archived
+    }
+
     fn as_slice(&self) -> &StoredEventRefList<A> {
-        StoredEventRefList::<A>::new(self.0.as_ref())
+        StoredEventRefList::<A>::new(self.events.as_ref())
     }
 
     fn append_unchecked(&mut self, aggregate_id: A::Id, event: A::Event) -> StoredEvent<A> {
         let stored_event = StoredEvent {
-            aggregate_id, index: self.0.len() as u64, event,
+            aggregate_id, index: self.len() as u64, event,
         };
-        self.0.push(stored_event.clone());
+        self.events.push(stored_event.clone());
 
         stored_event
     }
 
     fn initial_aggregate_id(&self, maybe_initial_event: &A::Event) -> A::Id {
-        if !self.0.is_empty() {
-            return self.0[0].aggregate_id.clone();
+        if !self.is_empty() {
+            return self.aggregate_id_unchecked().to_owned();
         }
         let mut created_aggregate = A::default();
         created_aggregate.apply(maybe_initial_event.clone());
@@ -98,14 +391,18 @@ impl<A: Aggregate> StoredEventRawList<A> {
     }
 
     pub fn aggregate_id(&self) -> Option<&A::IdRef> {
-        match self.0.is_empty() {
+        match self.is_empty() {
             true => None,
             false => Some(self.aggregate_id_unchecked()),
         }
     }
 
     fn aggregate_id_unchecked(&self) -> &A::IdRef {
-        &self.0[0].aggregate_id
+        match (self.events.first(), &self.base) {
+            (Some(event), _) => &event.aggregate_id,
+            (None, Some(base)) => base.aggregate.aggregate_id(),
+            (None, None) => panic!("the list is empty"),
+        }
     }
 
     pub fn append(&mut self, event: A::Event) -> Result<StoredEvent<A>, EventStoreError> {
@@ -133,7 +430,7 @@ impl<A: Aggregate> StoredEventRawList<A> {
     }
 
     pub fn snapshot(&self) -> Option<Snapshot<A>> {
-        let events_count = self.0.len();
+        let events_count = self.len();
         if events_count < 1 {
             return None;
         }
@@ -141,58 +438,61 @@ impl<A: Aggregate> StoredEventRawList<A> {
     }
 
     fn snapshot_unchecked(&self) -> Snapshot<A> {
-        let mut aggregate = A::default();
-        for event in &self.0 {
+        let mut aggregate = self.base.as_ref().map(Snapshot::to_aggregate).unwrap_or_default();
+        for event in &self.events {
             aggregate.apply(event.event.clone());
         }
-        Snapshot { aggregate, index: (self.0.len() as EventIndex) - 1 }
+        Snapshot { aggregate, index: (self.len() as EventIndex) - 1 }
     }
 
+    /// State after the event at the index, `None` if it's out of the list or
+    /// archived, see [`EventStore::fetch_from`]
     pub fn snapshot_at(&self, index: EventIndex) -> Option<Snapshot<A>> {
-        let events_count = self.0.len();
-        if events_count < 1 || (events_count as EventIndex - 1) < index {
+        let events_count = self.len();
+        if events_count < 1 || (events_count as EventIndex - 1) < index || index + 1 < self.first_index() {
             return None;
         }
         Some(self.snapshot_at_unchecked(index))
     }
 
     fn snapshot_at_unchecked(&self, index: EventIndex) -> Snapshot<A> {
-        let mut aggregate = A::default();
-        for event in &self.0 {
-            aggregate.apply(event.event.clone());
-            if event.index == index { break }
+        let mut aggregate = self.base.as_ref().map(Snapshot::to_aggregate).unwrap_or_default();
+        if index >= self.first_index() {
+            for event in &self.events {
+                aggregate.apply(event.event.clone());
+                if event.index == index { break }
+            }
         }
         Snapshot { aggregate, index }
     }
 
     pub fn check_consistency(&self) -> Result<(), EventStoreError> {
-        if self.0.is_empty() {
+        if self.events.is_empty() {
             return Ok(())
         }
-        let aggregate_id = self.0[0].aggregate_id.as_ref();
-        let mut event_index: EventIndex = 0;
-        for event in self.0.iter() {
-            if event.aggregate_id.eq(aggregate_id) {
+        let aggregate_id = self.events[0].aggregate_id.as_ref();
+        for (event_index, event) in (self.first_index()..).zip(self.events.iter()) {
+            if event.aggregate_id.ne(aggregate_id) {
                 return Err(EventStoreError::InconsistentEventAggregateId)
             }
             if event.index != event_index {
                  return Err(EventStoreError::InconsistentEventIndex)
             }
-            event_index += 1;
         }
         Ok(())
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.base.is_none() && self.events.is_empty()
     }
 
+    /// Count of the events, including the archived ones
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.first_index() as usize + self.events.len()
     }
 
     pub fn not_empty(self) -> Option<StoredEventList<A>> {
-        match self.0.is_empty() {
+        match self.is_empty() {
             true => None,
             false => Some(StoredEventList(self)),
         }
@@ -210,8 +510,10 @@ impl<A: Aggregate> StoredEventList<A> {
     pub fn snapshot(&self) -> Snapshot<A> {
         self.0.snapshot_unchecked()
     }
-    pub fn snapshot_at(&self, index: EventIndex) -> Snapshot<A> {
-        self.0.snapshot_at_unchecked(index)
+    /// State after the event at the index, `None` if it's out of the list or
+    /// archived, see [`EventStore::fetch_from`]
+    pub fn snapshot_at(&self, index: EventIndex) -> Option<Snapshot<A>> {
+        self.0.snapshot_at(index)
     }
 
     pub fn append(&mut self, event: A::Event) -> StoredEvent<A> {
@@ -270,7 +572,7 @@ impl<A: Aggregate> core::borrow::Borrow<StoredEventRefList<A>> for StoredEventLi
 impl<A: Aggregate> ToOwned for StoredEventRefList<A> {
     type Owned = StoredEventRawList<A>;
     fn to_owned(&self) -> Self::Owned {
-        StoredEventRawList::<A>(self.0.to_owned())
+        StoredEventRawList::<A>::from_events(self.0.to_owned())
     }
 }
 
@@ -283,9 +585,13 @@ impl<A: Aggregate> AsRef<[StoredEvent<A>]> for StoredEventRefList<A> {
 pub enum EventStoreError {
     InvalidInitialEvent,
     AggregateIsNotExist,
+    /// The stream committed as a new one exists
+    AggregateAlreadyExists,
     InconsistentEventAggregateId,
     InconsistentEventIndex,
     EmptyEventList,
+    /// The stream has been changed since it was fetched, lengths of the stream
+    ConcurrencyConflict { expected: usize, actual: usize },
     StorageError(Box<dyn core::error::Error + Send + Sync + 'static>)
 }
 
@@ -294,9 +600,13 @@ impl core::fmt::Display for EventStoreError {
         match self {
             Self::InvalidInitialEvent => write!(f, "invalid initial event (aggregate_id is empty after applying it)"),
             Self::AggregateIsNotExist => write!(f, "aggregate does not exist"),
+            Self::AggregateAlreadyExists => write!(f, "aggregate already exists"),
             Self::InconsistentEventAggregateId => write!(f, "inconsistent event aggregate id"),
             Self::InconsistentEventIndex => write!(f, "inconsistent event index number"),
             Self::EmptyEventList => write!(f, "empty event list"),
+            Self::ConcurrencyConflict { expected, actual } => {
+                write!(f, "concurrency conflict (expected {expected} events in the stream, found {actual})")
+            }
             Self::StorageError(e) => write!(f, "event storage error: {}", e),
         }
     }
@@ -312,3 +622,68 @@ impl core::fmt::Debug for EventStoreError {
 }
 
 impl core::error::Error for EventStoreError {}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{ShortLinkStatEvent, ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    fn created(url: &str) -> ShortenerEvent {
+        ShortenerEvent::Create(Slug::from("a"), Url::from(url))
+    }
+
+    fn redirect() -> ShortenerEvent {
+        ShortenerEvent::ShortLinkStatEvent(Slug::from("a"), ShortLinkStatEvent::Redirect)
+    }
+
+    /// Events of the stream "a" of the store
+    pub(crate) fn stored_events(store: &impl EventStore<Stats>) -> Vec<ShortenerEvent> {
+        store.fetch(SlugRef::new("a")).unwrap().events().iter().map(|event| event.event().clone()).collect()
+    }
+
+    /// Commits lists which differ from the stored events, one of the same
+    /// length and a longer one, returns the events the store ends up with
+    pub(crate) fn commit_differing_lists(store: &impl EventStore<Stats>) -> Vec<ShortenerEvent> {
+        store.commit(StoredEventList::new(&[created("https://a.example"), redirect()]).unwrap()).unwrap();
+        let same_len = [created("https://b.example"), redirect()];
+        store.commit_expected(StoredEventList::new(&same_len).unwrap(), 2).unwrap();
+        assert_eq!(stored_events(store), same_len);
+
+        let longer = vec![created("https://c.example"), redirect(), redirect()];
+        store.commit(StoredEventList::new(&longer).unwrap()).unwrap();
+        assert_eq!(stored_events(store), longer);
+        longer
+    }
+
+    /// Indices of the appended events, `None` for a removal
+    fn indices(changes: Result<Vec<Change<Stats>>, EventStoreError>) -> Vec<Option<EventIndex>> {
+        changes.unwrap().iter().map(|change| match change {
+            Change::Append(event) => Some(event.index()),
+            Change::Remove(_) => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_changes() {
+        let stored = StoredEventList::<Stats>::new(&[created("https://a.example"), redirect()]).unwrap();
+        assert_eq!(indices(changes(&stored, None)), [Some(0), Some(1)]);
+        assert!(indices(changes(&stored, Some(&stored))).is_empty());
+        assert_eq!(indices(changes(&stored.clone().append_all(&[redirect()]), Some(&stored))), [Some(2)]);
+
+        // shorter or differing lists replace the stream
+        let shorter = StoredEventList::<Stats>::new(&[created("https://a.example")]).unwrap();
+        assert_eq!(indices(changes(&shorter, Some(&stored))), [None, Some(0)]);
+        let differing = StoredEventList::<Stats>::new(&[created("https://b.example"), redirect(), redirect()]).unwrap();
+        assert_eq!(indices(changes(&differing, Some(&stored))), [None, Some(0), Some(1), Some(2)]);
+
+        // the archived events aren't compared, a compacted list can't replace the stream
+        let mut compacted = differing.clone().raw();
+        compacted.compact(0);
+        let compacted = compacted.not_empty().unwrap();
+        assert_eq!(indices(changes(&compacted, Some(&stored))), [Some(2)]);
+        let longer = differing.clone().append_all(&[redirect()]);
+        assert!(matches!(changes(&compacted, Some(&longer)), Err(EventStoreError::InconsistentEventIndex)));
+        let other = StoredEventList::<Stats>::new(&[created("https://b.example"), redirect(), created("https://b.example")]).unwrap();
+        assert!(matches!(changes(&compacted.append_all(&[redirect()]), Some(&other)), Err(EventStoreError::InconsistentEventIndex)));
+    }
+}
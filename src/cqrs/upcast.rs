@@ -0,0 +1,118 @@
+//! Reading of the events serialized by the older versions of the code. Each
+//! change of the serialized shape of the events bumps
+//! [`DomainEvent::EVENT_VERSION`] and registers an upcaster in
+//! [`DomainEvent::upcasters`], which transforms the events of the previous
+//! version into the shape of the next one:
+//!
+//! ```
+//! use intl_svc_test_task::cqrs::upcast::Upcasters;
+//! use intl_svc_test_task::json::Value;
+//!
+//! // version 2 added the owner of the links
+//! let upcasters = Upcasters::new().with(1, |mut event| {
+//!     if event.str_field("name")? == "Create" {
+//!         event.insert("owner", Value::Null);
+//!     }
+//!     Ok(event)
+//! });
+//! ```
+//!
+//! On reading, the upcasters from the stored version up to the current one
+//! are applied in order, so the events are deserialized in the current shape
+//! only.
+
+use std::collections::BTreeMap;
+
+use crate::json::{self, Value};
+use super::DomainEvent;
+
+pub type EventVersion = u32;
+
+/// Version of the events stored without one, i.e. before the versioning
+pub const INITIAL_EVENT_VERSION: EventVersion = 1;
+
+/// Transforms a serialized event into the shape of the next version, events
+/// which hadn't changed are returned as is
+pub type Upcaster = fn(Value) -> Result<Value, json::Error>;
+
+#[derive(Clone, Default)]
+pub struct Upcasters {
+    steps: BTreeMap<EventVersion, Vec<Upcaster>>,
+}
+
+impl Upcasters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the upcaster of the events of `version`, several ones are
+    /// applied in the order of registration
+    pub fn with(mut self, version: EventVersion, upcaster: Upcaster) -> Self {
+        self.steps.entry(version).or_default().push(upcaster);
+        self
+    }
+
+    /// Transforms the event of `version` into the shape of the current version of `E`
+    pub fn upcast<E: DomainEvent>(&self, version: EventVersion, event: Value) -> Result<Value, json::Error> {
+        if version > E::EVENT_VERSION {
+            return Err(json::Error::new(format!(
+                "{} of version {version} is newer than the supported version {}",
+                E::EVENT_TYPE,
+                E::EVENT_VERSION,
+            )));
+        }
+        self.steps
+            .range(version..E::EVENT_VERSION)
+            .flat_map(|(_, upcasters)| upcasters)
+            .try_fold(event, |event, upcaster| upcaster(event))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::json::parse;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Event;
+
+    impl DomainEvent for Event {
+        const EVENT_TYPE: &'static str = "Event";
+        const EVENT_VERSION: EventVersion = 3;
+        fn event_name(&self) -> &'static str {
+            "Event"
+        }
+    }
+
+    fn upcasters() -> Upcasters {
+        Upcasters::new()
+            .with(1, |mut event| {
+                event.insert("v2", Value::from(true));
+                Ok(event)
+            })
+            .with(2, |mut event| {
+                let v2 = event.bool_field("v2")?;
+                event.insert("v3", Value::from(v2));
+                Ok(event)
+            })
+    }
+
+    #[test]
+    fn test_upcast() {
+        let upcasters = upcasters();
+        let event = upcasters.upcast::<Event>(1, parse("{}").unwrap()).unwrap();
+        assert_eq!(event.to_string(), r#"{"v2":true,"v3":true}"#);
+        let event = upcasters.upcast::<Event>(2, parse(r#"{"v2":false}"#).unwrap()).unwrap();
+        assert_eq!(event.to_string(), r#"{"v2":false,"v3":false}"#);
+        let event = upcasters.upcast::<Event>(3, parse("{}").unwrap()).unwrap();
+        assert_eq!(event.to_string(), "{}");
+    }
+
+    #[test]
+    fn test_upcast_errors() {
+        let upcasters = upcasters();
+        assert!(upcasters.upcast::<Event>(2, parse("{}").unwrap()).is_err());
+        let e = upcasters.upcast::<Event>(4, parse("{}").unwrap()).unwrap_err();
+        assert_eq!(e.message, "Event of version 4 is newer than the supported version 3");
+    }
+}
@@ -0,0 +1,669 @@
+//! Write-ahead log of the commits of a store, so the commits survive the
+//! crashes of the machine without syncing the store on every commit, see
+//! [`WalEventStore`]. The log is a file of JSON lines:
+//!
+//! ```text
+//! {"op":"commit","seq":1,"stored_len":0,"records":[{"op":"append","aggregate_type":"short_link",...}]}
+//! {"op":"abort","seq":1}
+//! ```
+//!
+//! Records of a commit are the ones of [`FileEventStore`](super::file_store::FileEventStore)
+//! committing the list over the stream of `stored_len` events. A commit the
+//! store has failed is aborted, it isn't replayed.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::json::{self, FromJson, ToJson, Value};
+use super::file_store::Record;
+use super::store::{changes, check_expected_len, Change, EventIndex, EventStore, EventStoreError, LogEntry, LogId, StoredEventList, StoredEventRawList};
+use super::{Aggregate, DomainEvent};
+
+/// When the commits reach the disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    /// Every commit is synced before it's applied to the store
+    EveryCommit,
+    /// Commits wait for a sync shared by the ones of the interval, the syncs
+    /// are at least the interval apart
+    Group(Duration),
+    /// Commits are left in the buffers of the OS, a crash of the process
+    /// doesn't lose them, a crash of the machine may lose the last ones
+    Buffered,
+}
+
+/// Store logging its commits before applying them to the wrapped one. On
+/// opening, the commits the store has lost in a crash are replayed from the
+/// log, then the store is synced and the log is emptied, as
+/// [`WalEventStore::checkpoint`] does.
+///
+/// The store must keep its commits in their order, so a crash loses only the
+/// last ones, and keep them all on [`EventStore::sync`], e.g.
+/// [`FileEventStore`](super::file_store::FileEventStore). All the commits of
+/// its streams must go through the log, e.g. the transactions of a file
+/// backend don't.
+///
+/// A commit is applied to the store before the group sync, so the others may
+/// see it before it's durable. If the log has lost the commits the store
+/// has, after a crash of the machine with [`Durability::Group`] or
+/// [`Durability::Buffered`], the recovery fails.
+pub struct WalEventStore<A: Aggregate, S> {
+    store: S,
+    path: PathBuf,
+    durability: Durability,
+    wal: Mutex<Wal>,
+    group: Mutex<Group>,
+    group_synced: Condvar,
+    /// Handle of the log to sync it without locking the writers
+    sync_file: File,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+struct Wal {
+    file: File,
+    len: u64,
+    /// Sequence number of the last written commit
+    written: u64,
+}
+
+struct Group {
+    /// Sequence number of the last synced commit
+    synced: u64,
+    /// Whether a commit is syncing the log for the others
+    syncing: bool,
+    last_sync: Instant,
+}
+
+/// Commit read from the log
+struct Logged<A: Aggregate> {
+    seq: u64,
+    stored_len: usize,
+    changes: Vec<Change<A>>,
+}
+
+impl<A: Aggregate> Logged<A> {
+    fn aggregate_id(&self) -> A::Id {
+        match &self.changes[0] {
+            Change::Append(event) => event.aggregate_id().to_owned(),
+            Change::Remove(aggregate_id) => aggregate_id.clone(),
+        }
+    }
+
+    /// Length of the stream after the commit
+    fn len_after(&self) -> usize {
+        match &self.changes[..] {
+            [Change::Remove(_), appends @ ..] => appends.len(),
+            [.., Change::Append(event)] => event.index() as usize + 1,
+            _ => self.stored_len,
+        }
+    }
+}
+
+impl<A, S> WalEventStore<A, S>
+where
+    A: Aggregate,
+    A::Event: ToJson + FromJson,
+    A::IdRef: 'static,
+    S: EventStore<A>,
+{
+    /// Opens the log, creating it if it doesn't exist, and recovers the store
+    pub fn open<P: AsRef<Path>>(path: P, store: S, durability: Durability) -> Result<Self, EventStoreError> {
+        let path = path.as_ref().to_owned();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(storage_err)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).map_err(storage_err)?;
+        replay(&store, read_commits(&path, &contents)?, &path)?;
+
+        let sync_file = file.try_clone().map_err(storage_err)?;
+        let wal = Wal { file, len: contents.len() as u64, written: 0 };
+        let group = Group { synced: 0, syncing: false, last_sync: Instant::now() };
+        let store = Self {
+            store,
+            path,
+            durability,
+            wal: Mutex::new(wal),
+            group: Mutex::new(group),
+            group_synced: Condvar::new(),
+            sync_file,
+            _aggregate: PhantomData,
+        };
+        store.checkpoint()?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Syncs the store and empties the log, its commits don't need the replay
+    /// anymore
+    pub fn checkpoint(&self) -> Result<(), EventStoreError> {
+        let mut wal = self.lock()?;
+        self.store.sync()?;
+        wal.file.set_len(0).map_err(storage_err)?;
+        wal.file.sync_all().map_err(storage_err)?;
+        wal.len = 0;
+        self.mark_synced(wal.written)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Wal>, EventStoreError> {
+        self.wal.lock().map_err(map_locking_err)
+    }
+
+    fn commit_checked(&self, event_list: StoredEventList<A>, expected_len: Option<usize>) -> Result<(), EventStoreError> {
+        let mut wal = self.lock()?;
+        let stored = fetch_stored(&self.store, event_list.aggregate_id())?;
+        let stored_len = stored.as_ref().map_or(0, |stored| stored.len());
+        if let Some(expected_len) = expected_len {
+            check_expected_len(expected_len, stored_len)?;
+        }
+        let changes = changes(&event_list, stored.as_deref())?;
+        if changes.is_empty() {
+            return self.store.commit_expected(event_list, stored_len);
+        }
+
+        let seq = self.write_commit(&mut wal, stored_len, changes)?;
+        let result = self.store.commit_expected(event_list, stored_len);
+        self.finish(wal, seq, result)
+    }
+
+    fn write_commit(&self, wal: &mut Wal, stored_len: usize, changes: Vec<Change<A>>) -> Result<u64, EventStoreError> {
+        let seq = wal.written + 1;
+        let records = changes.into_iter().map(|change| Record::now(change).to_json()).collect();
+        let commit = Value::object([
+            ("op", Value::from("commit")),
+            ("seq", Value::from(seq)),
+            ("stored_len", Value::from(stored_len as u64)),
+            ("records", Value::Array(records)),
+        ]);
+        wal.write(commit, self.durability == Durability::EveryCommit)?;
+        wal.written = seq;
+        Ok(seq)
+    }
+
+    /// Aborts the commit the store has failed, or waits for it to be durable
+    fn finish<T>(&self, mut wal: MutexGuard<'_, Wal>, seq: u64, result: Result<T, EventStoreError>) -> Result<T, EventStoreError> {
+        if result.is_err() {
+            // synced as the replay of the commit would apply it
+            wal.write(Value::object([("op", Value::from("abort")), ("seq", Value::from(seq))]), true)?;
+            return result;
+        }
+        drop(wal);
+        if let Durability::Group(interval) = self.durability {
+            self.wait_group_sync(seq, interval)?;
+        }
+        result
+    }
+
+    /// Waits until the commit is synced, syncing the log if no other commit
+    /// does it
+    fn wait_group_sync(&self, seq: u64, interval: Duration) -> Result<(), EventStoreError> {
+        let mut group = self.group.lock().map_err(map_locking_err)?;
+        while group.synced < seq {
+            if group.syncing {
+                group = self.group_synced.wait(group).map_err(map_locking_err)?;
+                continue;
+            }
+            group.syncing = true;
+            let wait = (group.last_sync + interval).saturating_duration_since(Instant::now());
+            drop(group);
+            std::thread::sleep(wait);
+
+            // the commits written until now are synced together
+            let synced = self
+                .lock()
+                .map(|wal| wal.written)
+                .and_then(|written| self.sync_file.sync_data().map(|_| written).map_err(storage_err));
+            group = self.group.lock().map_err(map_locking_err)?;
+            group.syncing = false;
+            group.last_sync = Instant::now();
+            if let Ok(written) = synced {
+                group.synced = group.synced.max(written);
+            }
+            self.group_synced.notify_all();
+            synced?;
+        }
+        Ok(())
+    }
+
+    fn mark_synced(&self, seq: u64) -> Result<(), EventStoreError> {
+        let mut group = self.group.lock().map_err(map_locking_err)?;
+        group.synced = group.synced.max(seq);
+        self.group_synced.notify_all();
+        Ok(())
+    }
+}
+
+impl Wal {
+    /// Appends the record, cutting it off on a failure, so the following
+    /// ones don't follow a broken line
+    fn write(&mut self, record: Value, sync: bool) -> Result<(), EventStoreError> {
+        let mut line = record.to_string();
+        line.push('\n');
+        let written = self.file.write_all(line.as_bytes()).and_then(|_| match sync {
+            true => self.file.sync_data(),
+            false => Ok(()),
+        });
+        if let Err(e) = written {
+            let _ = self.file.set_len(self.len);
+            return Err(storage_err(e));
+        }
+        self.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Stored stream, `None` if it doesn't exist
+fn fetch_stored<A: Aggregate, S: EventStore<A>>(store: &S, aggregate_id: &A::IdRef) -> Result<Option<StoredEventList<A>>, EventStoreError> {
+    match store.fetch(aggregate_id) {
+        Ok(stored) => Ok(Some(stored)),
+        Err(EventStoreError::AggregateIsNotExist) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Length of the stored stream, 0 if it doesn't exist
+fn stored_len<A: Aggregate, S: EventStore<A>>(store: &S, aggregate_id: &A::IdRef) -> Result<usize, EventStoreError> {
+    Ok(fetch_stored(store, aggregate_id)?.map_or(0, |stored| stored.len()))
+}
+
+/// Commits of the log which weren't aborted. The last line is dropped if a
+/// crash has left it incomplete, its commit wasn't applied to the store.
+fn read_commits<A>(path: &Path, contents: &[u8]) -> Result<Vec<Logged<A>>, EventStoreError>
+where
+    A: Aggregate,
+    A::Event: FromJson,
+    A::IdRef: 'static,
+{
+    let complete_len = contents.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    let text = std::str::from_utf8(&contents[..complete_len]).map_err(storage_err)?;
+    let upcasters = A::Event::upcasters();
+    let mut commits: Vec<Logged<A>> = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_err = |e: json::Error| storage_err(format!("{}:{}: {e}", path.display(), line_no + 1));
+        let record = json::parse(line).map_err(line_err)?;
+        let seq = record.u64_field("seq").map_err(line_err)?;
+        match record.str_field("op").map_err(line_err)? {
+            "commit" => {
+                let changes = record
+                    .field("records")
+                    .map_err(line_err)?
+                    .as_array()
+                    .ok_or_else(|| line_err(json::Error::new("records of the commit must be an array")))?
+                    .iter()
+                    .map(|record| Record::<A>::read(record, &upcasters).map(|record| record.change))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(line_err)?;
+                if changes.is_empty() {
+                    return Err(line_err(json::Error::new("commit without records")));
+                }
+                let stored_len = record.u64_field("stored_len").map_err(line_err)? as usize;
+                commits.push(Logged { seq, stored_len, changes });
+            }
+            "abort" => commits.retain(|commit| commit.seq != seq),
+            op => return Err(line_err(json::Error::new(format!("unknown op `{op}`")))),
+        }
+    }
+    Ok(commits)
+}
+
+/// Applies the commits the store doesn't have. The store has kept the
+/// commits up to some point of the log, the earliest point where the
+/// lengths of the streams are the ones of the store is taken. Lengths
+/// repeat only after the removals and the replacements, so if the store is
+/// actually further, the replay repeats them and ends with the same streams.
+fn replay<A, S>(store: &S, commits: Vec<Logged<A>>, path: &Path) -> Result<(), EventStoreError>
+where
+    A: Aggregate,
+    S: EventStore<A>,
+{
+    // lengths of the streams as of the point, and in the store
+    let mut replayed: HashMap<A::Id, usize> = HashMap::new();
+    let mut stored: HashMap<A::Id, usize> = HashMap::new();
+    for commit in &commits {
+        let aggregate_id = commit.aggregate_id();
+        if !stored.contains_key(&aggregate_id) {
+            stored.insert(aggregate_id.clone(), stored_len(store, &aggregate_id)?);
+            replayed.insert(aggregate_id, commit.stored_len);
+        }
+    }
+    let mut mismatched = replayed.iter().filter(|(aggregate_id, len)| stored[*aggregate_id] != **len).count();
+    let mut point = 0;
+    while mismatched > 0 {
+        let Some(commit) = commits.get(point) else {
+            return Err(storage_err(format!("the store doesn't match the write-ahead log {}", path.display())));
+        };
+        let aggregate_id = commit.aggregate_id();
+        let stored_len = stored[&aggregate_id];
+        let len = replayed.entry(aggregate_id).or_default();
+        mismatched -= (*len != stored_len) as usize;
+        *len = commit.len_after();
+        mismatched += (*len != stored_len) as usize;
+        point += 1;
+    }
+
+    for commit in commits.into_iter().skip(point) {
+        apply(store, commit)?;
+    }
+    Ok(())
+}
+
+fn apply<A: Aggregate, S: EventStore<A>>(store: &S, commit: Logged<A>) -> Result<(), EventStoreError> {
+    let aggregate_id = commit.aggregate_id();
+    let mut changes = commit.changes.into_iter().peekable();
+    let mut events = match changes.next_if(|change| matches!(change, Change::Remove(_))) {
+        Some(_) if changes.peek().is_none() => return store.remove(&aggregate_id).map(drop),
+        Some(_) => StoredEventRawList::new(),
+        None => match store.fetch(&aggregate_id) {
+            Ok(stored) => stored.raw(),
+            Err(EventStoreError::AggregateIsNotExist) => StoredEventRawList::new(),
+            Err(e) => return Err(e),
+        },
+    };
+    for change in changes {
+        if let Change::Append(event) = change {
+            events.push(event);
+        }
+    }
+    let event_list = events.not_empty().ok_or(EventStoreError::EmptyEventList)?;
+    store.commit_expected(event_list, commit.stored_len)
+}
+
+impl<A, S> EventStore<A> for WalEventStore<A, S>
+where
+    A: Aggregate,
+    A::Event: ToJson + FromJson,
+    A::IdRef: 'static,
+    S: EventStore<A>,
+{
+    fn fetch(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        self.store.fetch(aggregate_id)
+    }
+
+    fn is_exist(&self, aggregate_id: &A::IdRef) -> Result<bool, EventStoreError> {
+        self.store.is_exist(aggregate_id)
+    }
+
+    fn commit(&self, event_list: StoredEventList<A>) -> Result<(), EventStoreError> {
+        self.commit_checked(event_list, None)
+    }
+
+    fn commit_expected(&self, event_list: StoredEventList<A>, expected_len: usize) -> Result<(), EventStoreError> {
+        self.commit_checked(event_list, Some(expected_len))
+    }
+
+    fn remove(&self, aggregate_id: &A::IdRef) -> Result<StoredEventList<A>, EventStoreError> {
+        let mut wal = self.lock()?;
+        let stored = self.store.fetch(aggregate_id)?;
+        let seq = self.write_commit(&mut wal, stored.len(), vec![Change::Remove(stored.aggregate_id().to_owned())])?;
+        let result = self.store.remove(aggregate_id);
+        self.finish(wal, seq, result)
+    }
+
+    fn aggregate_ids(&self) -> Result<Vec<A::Id>, EventStoreError> {
+        self.store.aggregate_ids()
+    }
+
+    fn fetch_from(&self, aggregate_id: &A::IdRef, index: EventIndex) -> Result<StoredEventList<A>, EventStoreError> {
+        self.store.fetch_from(aggregate_id, index)
+    }
+
+    fn log(&self) -> Result<Vec<LogEntry<A>>, EventStoreError> {
+        self.store.log()
+    }
+
+    fn log_id(&self) -> Option<LogId> {
+        self.store.log_id()
+    }
+
+    /// Syncs the log, its commits are durable then, the store is synced by
+    /// [`WalEventStore::checkpoint`]
+    fn sync(&self) -> Result<(), EventStoreError> {
+        let wal = self.lock()?;
+        wal.file.sync_data().map_err(storage_err)?;
+        self.mark_synced(wal.written)
+    }
+}
+
+fn storage_err<E: Into<Box<dyn core::error::Error + Send + Sync>>>(e: E) -> EventStoreError {
+    EventStoreError::StorageError(e.into())
+}
+
+fn map_locking_err<E>(_: E) -> EventStoreError {
+    EventStoreError::StorageError("WalEventStore Mutex had been poisoned".into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cqrs::file_store::test::TempFile;
+    use crate::cqrs::file_store::FileEventStore;
+    use crate::cqrs::store::test::{commit_differing_lists, stored_events};
+    use crate::{ShortLinkStatEvent, ShortenerEvent, Slug, SlugRef, Stats, Url};
+
+    fn create(slug: &str) -> StoredEventList<Stats> {
+        StoredEventList::new(&[ShortenerEvent::Create(Slug::from(slug), Url::from("https://example.com"))]).unwrap()
+    }
+
+    fn redirect(slug: &str) -> ShortenerEvent {
+        ShortenerEvent::ShortLinkStatEvent(Slug::from(slug), ShortLinkStatEvent::Redirect)
+    }
+
+    /// Store of the file with the log, recovered as after a restart
+    fn open(store: &TempFile, wal: &TempFile) -> WalEventStore<Stats, FileEventStore<Stats>> {
+        WalEventStore::open(&wal.0, FileEventStore::open(&store.0).unwrap(), Durability::EveryCommit).unwrap()
+    }
+
+    fn lens(store: &impl EventStore<Stats>) -> Vec<Option<usize>> {
+        ["a", "b", "c"]
+            .into_iter()
+            .map(|slug| store.fetch(SlugRef::new(slug)).ok().map(|events| events.len()))
+            .collect()
+    }
+
+    /// Fails or crashes on the next commit
+    #[derive(Clone, Copy)]
+    enum Fault {
+        Fail,
+        Crash,
+    }
+
+    struct Faulty<S> {
+        store: S,
+        fault: Cell<Option<Fault>>,
+    }
+
+    impl<S: EventStore<Stats>> EventStore<Stats> for Faulty<S> {
+        fn fetch(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Stats>, EventStoreError> {
+            self.store.fetch(aggregate_id)
+        }
+
+        fn is_exist(&self, aggregate_id: &SlugRef) -> Result<bool, EventStoreError> {
+            self.store.is_exist(aggregate_id)
+        }
+
+        fn commit(&self, event_list: StoredEventList<Stats>) -> Result<(), EventStoreError> {
+            self.store.commit(event_list)
+        }
+
+        fn commit_expected(&self, event_list: StoredEventList<Stats>, expected_len: usize) -> Result<(), EventStoreError> {
+            match self.fault.take() {
+                Some(Fault::Fail) => Err(EventStoreError::StorageError("disk is full".into())),
+                Some(Fault::Crash) => panic!("crash"),
+                None => self.store.commit_expected(event_list, expected_len),
+            }
+        }
+
+        fn remove(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Stats>, EventStoreError> {
+            self.store.remove(aggregate_id)
+        }
+
+        fn sync(&self) -> Result<(), EventStoreError> {
+            self.store.sync()
+        }
+    }
+
+    #[test]
+    fn test_lost_store_writes() {
+        let (store_file, wal_file) = (TempFile::new("wal_lost_store"), TempFile::new("wal_lost_store_log"));
+        {
+            let store = open(&store_file, &wal_file);
+            store.commit(create("a")).unwrap();
+            store.checkpoint().unwrap();
+            store.commit(create("b")).unwrap();
+            store.commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a"), redirect("a")])).unwrap();
+            store.remove(SlugRef::new("b")).unwrap();
+            store.commit(create("b")).unwrap();
+            store.commit(create("c")).unwrap();
+            store.commit(store.fetch(SlugRef::new("c")).unwrap().append_all(&[redirect("c")])).unwrap();
+        }
+        let store_contents = std::fs::read(&store_file.0).unwrap();
+        let wal_contents = std::fs::read(&wal_file.0).unwrap();
+        let checkpointed = store_contents.iter().position(|b| *b == b'\n').unwrap() + 1;
+
+        // the machine crashed with any part of the store after the checkpoint unwritten
+        for len in checkpointed..=store_contents.len() {
+            std::fs::write(&store_file.0, &store_contents[..len]).unwrap();
+            std::fs::write(&wal_file.0, &wal_contents).unwrap();
+            let store = open(&store_file, &wal_file);
+            assert_eq!(lens(&store), [Some(3), Some(1), Some(2)], "store of {len} bytes");
+            assert_eq!(std::fs::metadata(&wal_file.0).unwrap().len(), 0);
+        }
+        drop(open(&store_file, &wal_file));
+        assert_eq!(lens(&FileEventStore::<Stats>::open(&store_file.0).unwrap()), [Some(3), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_differing_commit() {
+        let (store_file, wal_file) = (TempFile::new("wal_differing_commit"), TempFile::new("wal_differing_commit_log"));
+        let events = {
+            let store = open(&store_file, &wal_file);
+            store.commit(create("b")).unwrap();
+            store.checkpoint().unwrap();
+            commit_differing_lists(&store)
+        };
+
+        // the store lost the writes after the checkpoint, the log has the replacements
+        let store_contents = std::fs::read(&store_file.0).unwrap();
+        let checkpointed = store_contents.iter().position(|b| *b == b'\n').unwrap() + 1;
+        std::fs::write(&store_file.0, &store_contents[..checkpointed]).unwrap();
+        assert_eq!(stored_events(&open(&store_file, &wal_file)), events);
+    }
+
+    #[test]
+    fn test_torn_log_tail() {
+        let (store_file, wal_file) = (TempFile::new("wal_torn_tail"), TempFile::new("wal_torn_tail_log"));
+        {
+            let store = open(&store_file, &wal_file);
+            store.commit(create("a")).unwrap();
+            store.commit(create("b")).unwrap();
+            store.commit(create("c")).unwrap();
+        }
+        let wal_contents = std::fs::read(&wal_file.0).unwrap();
+        let last_line = wal_contents[..wal_contents.len() - 1].iter().rposition(|b| *b == b'\n').unwrap() + 1;
+
+        // the crash tore the last commit before it was applied to the store
+        for len in last_line..wal_contents.len() {
+            std::fs::write(&store_file.0, "").unwrap();
+            std::fs::write(&wal_file.0, &wal_contents[..len]).unwrap();
+            let store = open(&store_file, &wal_file);
+            assert_eq!(lens(&store), [Some(1), Some(1), None], "log of {len} bytes");
+            store.commit(create("c")).unwrap();
+            drop(store);
+            std::fs::write(&store_file.0, "").unwrap();
+            assert_eq!(lens(&open(&store_file, &wal_file)), [None, None, Some(1)]);
+        }
+    }
+
+    #[test]
+    fn test_crash_before_store_commit() {
+        let (store_file, wal_file) = (TempFile::new("wal_crash"), TempFile::new("wal_crash_log"));
+        let faulty = Faulty { store: FileEventStore::<Stats>::open(&store_file.0).unwrap(), fault: Cell::new(None) };
+        let store = WalEventStore::open(&wal_file.0, faulty, Durability::EveryCommit).unwrap();
+        store.commit(create("a")).unwrap();
+        store.store().fault.set(Some(Fault::Crash));
+        assert!(catch_unwind(AssertUnwindSafe(|| store.commit(create("b")))).is_err());
+        drop(store);
+
+        assert_eq!(lens(&open(&store_file, &wal_file)), [Some(1), Some(1), None]);
+    }
+
+    #[test]
+    fn test_failed_commit_is_aborted() {
+        let (store_file, wal_file) = (TempFile::new("wal_abort"), TempFile::new("wal_abort_log"));
+        let faulty = Faulty { store: FileEventStore::<Stats>::open(&store_file.0).unwrap(), fault: Cell::new(None) };
+        let store = WalEventStore::open(&wal_file.0, faulty, Durability::Buffered).unwrap();
+        store.commit(create("a")).unwrap();
+        store.store().fault.set(Some(Fault::Fail));
+        assert!(matches!(store.commit(create("b")), Err(EventStoreError::StorageError(_))));
+        store.commit(create("c")).unwrap();
+        drop(store);
+
+        std::fs::write(&store_file.0, "").unwrap();
+        assert_eq!(lens(&open(&store_file, &wal_file)), [Some(1), None, Some(1)]);
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let (store_file, wal_file) = (TempFile::new("wal_group"), TempFile::new("wal_group_log"));
+        let slugs: Vec<String> = (0..16).map(|i| format!("slug{i}")).collect();
+        {
+            let file_store = FileEventStore::<Stats>::open(&store_file.0).unwrap();
+            let store = Arc::new(WalEventStore::open(&wal_file.0, file_store, Durability::Group(Duration::from_millis(5))).unwrap());
+            let threads: Vec<_> = slugs
+                .iter()
+                .cloned()
+                .map(|slug| {
+                    let store = store.clone();
+                    std::thread::spawn(move || store.commit(create(&slug)))
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap().unwrap();
+            }
+        }
+
+        std::fs::write(&store_file.0, "").unwrap();
+        let store = open(&store_file, &wal_file);
+        for slug in &slugs {
+            assert!(store.is_exist(SlugRef::new(slug)).unwrap(), "{slug}");
+        }
+    }
+
+    #[test]
+    fn test_store_ahead_of_log() {
+        let (store_file, wal_file) = (TempFile::new("wal_ahead"), TempFile::new("wal_ahead_log"));
+        let store = open(&store_file, &wal_file);
+        store.commit(create("a")).unwrap();
+        // the log lost the commit the store has
+        store.store().commit(store.fetch(SlugRef::new("a")).unwrap().append_all(&[redirect("a")])).unwrap();
+        drop(store);
+
+        let store = FileEventStore::<Stats>::open(&store_file.0).unwrap();
+        let Err(EventStoreError::StorageError(e)) = WalEventStore::open(&wal_file.0, store, Durability::EveryCommit) else {
+            panic!("the store must not be recovered");
+        };
+        assert!(e.to_string().starts_with("the store doesn't match the write-ahead log"));
+    }
+}
@@ -8,35 +8,136 @@ pub trait SlugGenerator {
     fn generate(&self, input: &str, bump: u16) -> Slug;
 }
 
-///
+/// Generates slugs from the current time nanoseconds and the bump value
+#[allow(dead_code)]
 pub struct SimplestSlugGenerator;
 
 impl SlugGenerator for SimplestSlugGenerator {
     fn generate(&self, _input: &str, bump: u16) -> Slug {
-        SimplestSlugGenerator::generate(&self, bump)
+        SimplestSlugGenerator::generate(self, bump)
     }
 }
+#[allow(dead_code)]
 impl SimplestSlugGenerator {
     fn generate(&self, bump: u16) -> Slug {
-        // pseudo-random without using 'rand' crate
-        let rand_bytes: [u8; 4] = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .subsec_nanos()
-            .to_be_bytes();
-        let mut result_bytes: [u8; 6] = [0, 0, 0, 0, 0, 0];
-        result_bytes[..4].clone_from_slice(&rand_bytes);
-        result_bytes[4..6].clone_from_slice(&bump.to_be_bytes());
-        Slug::from(base64::Url::encode(&result_bytes))
+        let result_bytes = slug_bytes(bump);
+        let mut slug_buf = [0u8; base64::encoded_len(6, base64::Padding::Unpadded)];
+        // unwrap: the buffer has exactly the encoded length
+        Slug::from(base64::encode_to_slice::<base64::Url>(&result_bytes, base64::Padding::Unpadded, &mut slug_buf).unwrap())
+    }
+
+    /// Decodes a slug generated by [`SimplestSlugGenerator`] back into its
+    /// components, for diagnostics.
+    pub fn decompose(slug: &SlugRef) -> Result<SlugComponents, base64::DecodeError> {
+        let bytes: [u8; 6] = base64::Url::decode(slug.as_str())?
+            .try_into()
+            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        Ok(SlugComponents::from_bytes(bytes))
+    }
+}
+
+/// The same slugs as [`SimplestSlugGenerator`] generates, in the selected
+/// [`radix::Encoding`], e.g. [`radix::Encoding::Base58`] to avoid `-`, `_`
+/// and confusable symbols in the slugs
+#[allow(dead_code)]
+pub struct EncodedSlugGenerator {
+    pub encoding: radix::Encoding,
+}
+
+impl SlugGenerator for EncodedSlugGenerator {
+    fn generate(&self, _input: &str, bump: u16) -> Slug {
+        Slug::from(self.encoding.encode(&slug_bytes(bump)))
+    }
+}
+
+#[allow(dead_code)]
+impl EncodedSlugGenerator {
+    pub fn new(encoding: radix::Encoding) -> Self {
+        Self { encoding }
+    }
+
+    /// Decodes a generated slug back into its components, for diagnostics.
+    pub fn decompose(&self, slug: &SlugRef) -> Result<SlugComponents, radix::DecodeError> {
+        let bytes: [u8; 6] = self.encoding.decode(slug.as_str())?
+            .try_into()
+            .map_err(|_| radix::DecodeError::InvalidLength)?;
+        Ok(SlugComponents::from_bytes(bytes))
+    }
+}
+
+fn slug_bytes(bump: u16) -> [u8; 6] {
+    // pseudo-random without using 'rand' crate
+    let rand_bytes: [u8; 4] = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos()
+        .to_be_bytes();
+    let mut result_bytes: [u8; 6] = [0, 0, 0, 0, 0, 0];
+    result_bytes[..4].clone_from_slice(&rand_bytes);
+    result_bytes[4..6].clone_from_slice(&bump.to_be_bytes());
+    result_bytes
+}
+
+/// Components a generated slug is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlugComponents {
+    /// hash or random part
+    pub random: u32,
+    /// the bump value used to resolve collisions
+    pub bump: u16,
+}
+
+impl SlugComponents {
+    fn from_bytes(bytes: [u8; 6]) -> Self {
+        Self {
+            random: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            bump: u16::from_be_bytes([bytes[4], bytes[5]]),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::gen::SimplestSlugGenerator;
+    use crate::base64::DecodeError;
+    use crate::gen::{EncodedSlugGenerator, SimplestSlugGenerator, SlugComponents, SlugGenerator};
+    use crate::radix::Encoding;
+    use crate::SlugRef;
 
     #[test]
     fn test_generated_slug_len() {
         assert_eq!(SimplestSlugGenerator.generate(128).len(), 8)
     }
+
+    #[test]
+    fn test_decompose_generated_slug() {
+        let slug = SimplestSlugGenerator.generate(0x1234);
+        let components = SimplestSlugGenerator::decompose(&slug).unwrap();
+        assert_eq!(components.bump, 0x1234);
+        assert!(components.random < 1_000_000_000); // subsec nanos
+
+        assert_eq!(
+            SimplestSlugGenerator::decompose(SlugRef::new("AAAAAQAC")),
+            Ok(SlugComponents { random: 1, bump: 2 }),
+        );
+        assert_eq!(
+            SimplestSlugGenerator::decompose(SlugRef::new("AAAAAQ")),
+            Err(DecodeError::InvalidLength),
+        );
+    }
+
+    #[test]
+    fn test_encoded_slug_generator() {
+        for (encoding, len) in [
+            (Encoding::Base64Url, 8),
+            (Encoding::Base62, 9),
+            (Encoding::Base58, 9),
+            (Encoding::Crockford32, 10),
+        ] {
+            let generator = EncodedSlugGenerator::new(encoding);
+            let slug = generator.generate("", 0xbeef);
+            assert_eq!(slug.len(), len, "{encoding:?}");
+            assert!(slug.as_str().chars().all(|c| c.is_ascii_alphanumeric()) || encoding == Encoding::Base64Url);
+            assert_eq!(generator.decompose(&slug).unwrap().bump, 0xbeef, "{encoding:?}");
+        }
+    }
 }
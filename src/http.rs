@@ -0,0 +1,413 @@
+//! Minimal HTTP/1.1 frontend of the shortener, std only.
+//!
+//! - `GET /{slug}` redirects to the url of the short link, as configured by
+//!   its [`RedirectConfig`], or shows the preview page of it
+//! - `POST /shorten` with the form `url=...&slug=...` (slug is optional)
+//!   creates a short link
+//! - `GET /stats/{slug}` returns the statistics of the short link
+//!
+//! and the JSON management API, see [`api`]. Slugs matched by these routes
+//! first, see [`RESERVED_SLUGS`], can't be used as custom slugs.
+
+pub mod api;
+pub mod message;
+pub mod pool;
+
+pub use message::{Request, RequestError, Response};
+pub use pool::ThreadPool;
+
+use std::io::{self, BufReader};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::commands::SyncCommandHandler;
+use crate::queries::{QueryHandler, RedirectConfigQueryHandler};
+use crate::redirect::{RedirectConfig, RedirectType};
+use crate::{ShortLink, ShortenerError, Slug, Url};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Custom slugs which the links could never be redirected by, as their
+/// routes are taken by the frontend
+pub const RESERVED_SLUGS: [&str; 4] = ["links", "openapi.json", "shorten", "stats"];
+
+/// Status codes of the redirect responses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedirectStatus {
+    MovedPermanently,
+    #[default]
+    Found,
+    TemporaryRedirect,
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            301 => Some(Self::MovedPermanently),
+            302 => Some(Self::Found),
+            307 => Some(Self::TemporaryRedirect),
+            308 => Some(Self::PermanentRedirect),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u16 {
+        match self {
+            Self::MovedPermanently => 301,
+            Self::Found => 302,
+            Self::TemporaryRedirect => 307,
+            Self::PermanentRedirect => 308,
+        }
+    }
+}
+
+impl From<RedirectType> for RedirectStatus {
+    fn from(redirect_type: RedirectType) -> Self {
+        // unwrap: redirect types have only the codes above
+        Self::from_code(redirect_type.status_code()).unwrap()
+    }
+}
+
+/// Handlers the frontend needs from the service. The workers share the
+/// service, so it's `Send + Sync` with `&self` commands, e.g.
+/// [`SyncUrlShortenerService`](crate::sync::SyncUrlShortenerService).
+pub trait Service: SyncCommandHandler + QueryHandler + RedirectConfigQueryHandler + Send + Sync {}
+
+impl<S: SyncCommandHandler + QueryHandler + RedirectConfigQueryHandler + Send + Sync> Service for S {}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Status of the redirects of the links without a configured [`RedirectType`]
+    pub redirect_status: RedirectStatus,
+    /// Base of the short urls returned on creation, e.g. `https://sho.rt`
+    pub base_url: String,
+}
+
+/// Maps requests to the service calls
+pub struct Router<S> {
+    service: Arc<S>,
+    config: Config,
+}
+
+impl<S: Service + 'static> Router<S> {
+    pub fn new(service: Arc<S>, config: Config) -> Self {
+        Self { service, config }
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        let segments = match request.path_segments() {
+            Some(segments) => segments,
+            None => return Response::text(400, "invalid path"),
+        };
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+        if let Some(response) = self.handle_api(request, &segments) {
+            return response;
+        }
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["shorten"]) => self.create(request),
+            ("GET", ["stats", slug]) => self.stats(slug),
+            ("GET", [slug]) => self.redirect(slug),
+            (_, ["shorten"] | ["stats", _] | [_]) => Response::text(405, "method not allowed"),
+            _ => Response::text(404, "not found"),
+        }
+    }
+
+    fn create(&self, request: &Request) -> Response {
+        let url = match request.form_param("url") {
+            Some(url) => Url::from(url),
+            None => return Response::text(400, "url is required"),
+        };
+        let slug = request.form_param("slug").filter(|s| !s.is_empty()).map(Slug::from);
+        match self.create_link(url, slug) {
+            Ok(link) => {
+                let short_url = self.short_url(&link.slug);
+                Response::text(201, format!("{short_url}\n")).with_header("Location", short_url)
+            }
+            Err(e) => error_response(e),
+        }
+    }
+
+    /// Creates the link, rejecting the reserved slugs as already in use
+    fn create_link(&self, url: Url, slug: Option<Slug>) -> Result<ShortLink, ShortenerError> {
+        if slug.as_ref().is_some_and(|slug| RESERVED_SLUGS.contains(&slug.as_str())) {
+            return Err(ShortenerError::SlugAlreadyInUse);
+        }
+        self.service.handle_create_short_link(url, slug)
+    }
+
+    fn redirect(&self, slug: &str) -> Response {
+        let slug = Slug::from(slug);
+        let redirect = match self.service.handle_redirect_with_config(slug) {
+            Ok(redirect) => redirect,
+            Err(e) => return error_response(e),
+        };
+        let RedirectConfig { redirect_type, cache_control, preview } = redirect.config;
+        let url = redirect.link.url.as_str();
+        let response = match preview {
+            true => preview_response(url),
+            false => redirect_response(redirect_type.map_or(self.config.redirect_status, RedirectStatus::from), url),
+        };
+        match cache_control {
+            Some(cache_control) => response.with_header("Cache-Control", cache_control.header_value()),
+            None => response,
+        }
+    }
+
+    fn stats(&self, slug: &str) -> Response {
+        let slug = Slug::from(slug);
+        match self.service.get_stats(slug) {
+            Ok(stats) => Response::text(200, format!(
+                "slug: {}\nurl: {}\nredirects: {}\n",
+                stats.link.slug, stats.link.url, stats.redirects,
+            )),
+            Err(e) => error_response(e),
+        }
+    }
+
+    fn short_url(&self, slug: &Slug) -> String {
+        format!("{}/{}", self.config.base_url.trim_end_matches('/'), message::percent_encode(slug.as_str()))
+    }
+}
+
+pub fn error_status(e: &ShortenerError) -> u16 {
+    match e {
+        ShortenerError::InvalidUrl => 400,
+        ShortenerError::SlugAlreadyInUse => 409,
+        ShortenerError::SlugNotFound => 404,
+        ShortenerError::StorageError(_) => 500,
+    }
+}
+
+fn error_response(e: ShortenerError) -> Response {
+    Response::text(error_status(&e), e.to_string())
+}
+
+fn redirect_response(status: RedirectStatus, url: &str) -> Response {
+    // the url is valid, but it's not necessarily ascii-only as the header must be
+    let location = match crate::url_parser::Url::parse(url) {
+        Ok(url) => url.to_string(),
+        Err(_) => return Response::text(500, "invalid url"),
+    };
+    Response::new(status.code()).with_header("Location", location)
+}
+
+/// Interstitial page showing the url instead of redirecting to it
+fn preview_response(url: &str) -> Response {
+    let href = match crate::url_parser::Url::parse(url) {
+        Ok(url) => html_escape(url.as_str()),
+        Err(_) => return Response::text(500, "invalid url"),
+    };
+    let text = html_escape(url);
+    let page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Redirect</title></head>\n\
+         <body><p>This link leads to <a href=\"{href}\" rel=\"noreferrer\">{text}</a></p></body></html>\n",
+    );
+    Response::new(200).with_body("text/html; charset=utf-8", page.into_bytes())
+}
+
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reads a request from the connection, handles it and writes the response
+pub fn handle_connection<S: Service + 'static>(stream: TcpStream, router: &Router<S>) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match Request::read_from(&mut reader) {
+        Ok(request) => router.handle(&request),
+        Err(RequestError::ConnectionClosed) => return Ok(()),
+        Err(RequestError::Io(e)) => return Err(e),
+        Err(RequestError::TooLarge) => Response::text(413, "request is too large"),
+        Err(RequestError::UnsupportedTransferEncoding) => Response::text(501, "transfer encoding is not supported"),
+        Err(e @ RequestError::Malformed(_)) => Response::text(400, e.to_string()),
+    };
+    let mut stream = stream;
+    response.write_to(&mut stream)
+}
+
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self { listener: TcpListener::bind(addr)? })
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections and handles them in the pool, forever
+    pub fn run<S: Service + 'static>(self, router: Router<S>, pool: ThreadPool) {
+        let router = Arc::new(router);
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("failed to accept a connection: {e}");
+                    continue;
+                }
+            };
+            let router = Arc::clone(&router);
+            pool.execute(move || {
+                if let Err(e) = handle_connection(stream, &router) {
+                    eprintln!("failed to handle a connection: {e}");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::cqrs::mem_store::MemEventStore;
+    use crate::sync::SyncUrlShortenerService;
+    use crate::{gen, Stats};
+    use std::io::{Read, Write};
+    use std::thread;
+
+    pub(crate) fn create_router(redirect_status: RedirectStatus) -> Router<SyncUrlShortenerService> {
+        let service = SyncUrlShortenerService::new(
+            Box::new(MemEventStore::<Stats>::new()),
+            Box::new(MemEventStore::new()),
+            Box::new(gen::SimplestSlugGenerator),
+        );
+        Router::new(Arc::new(service), Config { redirect_status, base_url: "http://sho.rt/".into() })
+    }
+
+    pub(crate) fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.into(),
+            path: path.into(),
+            query: None,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_create_redirect_stats() {
+        let router = create_router(RedirectStatus::TemporaryRedirect);
+
+        let response = router.handle(&request("POST", "/shorten", "url=https%3A%2F%2Fexample.com%2F%D0%B0&slug=promo"));
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("Location"), Some("http://sho.rt/promo"));
+
+        let response = router.handle(&request("GET", "/promo", ""));
+        assert_eq!(response.status, 307);
+        assert_eq!(response.header("Location"), Some("https://example.com/%D0%B0"));
+
+        let response = router.handle(&request("GET", "/stats/promo", ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"slug: promo\nurl: https://example.com/\xD0\xB0\nredirects: 1\n");
+    }
+
+    #[test]
+    fn test_generated_slug() {
+        let router = create_router(RedirectStatus::default());
+        let response = router.handle(&request("POST", "/shorten", "url=https://example.com"));
+        assert_eq!(response.status, 201);
+        let short_url = response.header("Location").unwrap();
+        let slug = short_url.strip_prefix("http://sho.rt/").unwrap();
+        assert_eq!(router.handle(&request("GET", &format!("/{slug}"), "")).status, 302);
+    }
+
+    #[test]
+    fn test_errors() {
+        let router = create_router(RedirectStatus::MovedPermanently);
+        assert_eq!(router.handle(&request("GET", "/missing", "")).status, 404);
+        assert_eq!(router.handle(&request("GET", "/stats/missing", "")).status, 404);
+        assert_eq!(router.handle(&request("POST", "/shorten", "url=http://[:::1]")).status, 400);
+        assert_eq!(router.handle(&request("POST", "/shorten", "slug=x")).status, 400);
+        assert_eq!(router.handle(&request("POST", "/shorten", "url=https://a.b&slug=x")).status, 201);
+        assert_eq!(router.handle(&request("POST", "/shorten", "url=https://a.b&slug=x")).status, 409);
+        assert_eq!(router.handle(&request("GET", "/x", "")).status, 301);
+        assert_eq!(router.handle(&request("DELETE", "/x", "")).status, 405);
+        assert_eq!(router.handle(&request("GET", "/a/b/c", "")).status, 404);
+        assert_eq!(router.handle(&request("GET", "/%FF", "")).status, 400);
+    }
+
+    #[test]
+    fn test_reserved_slugs() {
+        let router = create_router(RedirectStatus::default());
+        for slug in RESERVED_SLUGS {
+            let response = router.handle(&request("POST", "/shorten", &format!("url=https://a.b&slug={slug}")));
+            assert_eq!(response.status, 409, "{slug}");
+            let response = router.handle(&request("POST", "/links", &format!(r#"{{"url": "https://a.b", "slug": "{slug}"}}"#)));
+            assert_eq!(response.status, 409, "{slug}");
+        }
+        // only the exact routes are reserved
+        assert_eq!(router.handle(&request("POST", "/shorten", "url=https://a.b&slug=links2")).status, 201);
+        assert_eq!(router.handle(&request("GET", "/links2", "")).status, 302);
+    }
+
+    #[test]
+    fn test_redirect_config() {
+        use crate::redirect::{CacheControl, RedirectKind, RedirectType};
+        let router = create_router(RedirectStatus::Found);
+        router.handle(&request("POST", "/shorten", "url=https://example.com/?a=1%26b=%3C2%3E&slug=promo"));
+        let configure = |config: RedirectConfig| {
+            router.service.handle_configure_redirect(Slug::from("promo"), config).unwrap()
+        };
+
+        let response = router.handle(&request("GET", "/promo", ""));
+        assert_eq!((response.status, response.header("Cache-Control")), (302, None));
+
+        configure(RedirectConfig {
+            redirect_type: Some(RedirectType { kind: RedirectKind::Temporary, preserve_method: true }),
+            cache_control: Some(CacheControl::Public { max_age: 60 }),
+            preview: false,
+        });
+        let response = router.handle(&request("GET", "/promo", ""));
+        assert_eq!(response.status, 307);
+        assert_eq!(response.header("Cache-Control"), Some("public, max-age=60"));
+
+        configure(RedirectConfig { preview: true, ..Default::default() });
+        let response = router.handle(&request("GET", "/promo", ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Location"), None);
+        let page = String::from_utf8(response.body).unwrap();
+        assert!(page.contains(r#"<a href="https://example.com/?a=1&amp;b=%3C2%3E" rel="noreferrer">https://example.com/?a=1&amp;b=&lt;2&gt;</a>"#), "{page}");
+
+        // previews are counted as redirects too
+        let response = router.handle(&request("GET", "/stats/promo", ""));
+        assert!(String::from_utf8(response.body).unwrap().ends_with("redirects: 3\n"));
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        let router = create_router(RedirectStatus::PermanentRedirect);
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let body = "url=https://example.com&slug=abc";
+            write!(stream, "POST /shorten HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = server.listener.accept().unwrap();
+        handle_connection(stream, &router).unwrap();
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\nhttp://sho.rt/abc\n"), "{response}");
+    }
+}
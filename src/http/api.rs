@@ -0,0 +1,626 @@
+//! JSON management API:
+//!
+//! - `POST /links` with [`CreateLinkRequest`] creates a [`ShortLink`]
+//! - `GET /links/{slug}` returns the [`ShortLink`]
+//! - `GET /links/{slug}/stats` returns the [`Stats`]
+//! - `GET /links/{slug}/redirect` returns the [`RedirectConfig`]
+//! - `PUT /links/{slug}/redirect` with [`RedirectConfig`] replaces it
+//! - `GET /openapi.json` returns the [`openapi`] document
+//!
+//! Errors are returned as [`ApiError`] bodies.
+
+use crate::json::{self, FromJson, JsonSchema, ToJson, Value};
+use crate::redirect::RedirectConfig;
+use crate::{ShortLink, ShortenerError, Slug, Stats, Url};
+
+use super::{error_status, message::percent_encode, Request, Response, Router, Service};
+
+const CONTENT_TYPE: &str = "application/json";
+
+/// All the errors of the service, used to document the API
+pub const SHORTENER_ERRORS: [ShortenerError; 4] = [
+    ShortenerError::InvalidUrl,
+    ShortenerError::SlugAlreadyInUse,
+    ShortenerError::SlugNotFound,
+    ShortenerError::StorageError(String::new()),
+];
+
+/// Body of `POST /links`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateLinkRequest {
+    pub url: Url,
+    /// Custom slug, generated if absent
+    pub slug: Option<Slug>,
+}
+
+/// Body of the error responses
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Code of malformed requests, e.g. of invalid JSON
+pub const INVALID_REQUEST: &str = "invalid_request";
+
+pub fn error_code(e: &ShortenerError) -> &'static str {
+    match e {
+        ShortenerError::InvalidUrl => "invalid_url",
+        ShortenerError::SlugAlreadyInUse => "slug_already_in_use",
+        ShortenerError::SlugNotFound => "slug_not_found",
+        ShortenerError::StorageError(_) => "storage_error",
+    }
+}
+
+impl From<ShortenerError> for ApiError {
+    fn from(e: ShortenerError) -> Self {
+        Self { code: error_code(&e), message: e.to_string() }
+    }
+}
+
+impl ToJson for ShortLink {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("slug", Value::from(self.slug.as_str())),
+            ("url", Value::from(self.url.as_str())),
+        ])
+    }
+}
+
+impl FromJson for ShortLink {
+    fn from_json(value: &Value) -> Result<Self, json::Error> {
+        Ok(ShortLink {
+            slug: Slug::from(value.str_field("slug")?),
+            url: Url::from(value.str_field("url")?),
+        })
+    }
+}
+
+impl JsonSchema for ShortLink {
+    const SCHEMA_NAME: &'static str = "ShortLink";
+    fn schema() -> Value {
+        object_schema("Shortened URL representation.", [
+            ("slug", string_schema("A unique string (or alias) that represents the shortened version of the URL."), true),
+            ("url", string_schema("The original URL that the short link points to."), true),
+        ])
+    }
+}
+
+impl ToJson for Stats {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("link", self.link.to_json()),
+            ("redirects", Value::from(self.redirects)),
+            ("redirect_config", self.redirect_config.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Stats {
+    fn from_json(value: &Value) -> Result<Self, json::Error> {
+        Ok(Stats {
+            link: ShortLink::from_json(value.field("link")?)?,
+            redirects: value.u64_field("redirects")?,
+            // absent in the snapshots written before it was kept
+            redirect_config: value.get("redirect_config").map(RedirectConfig::from_json).transpose()?.unwrap_or_default(),
+        })
+    }
+}
+
+impl JsonSchema for Stats {
+    const SCHEMA_NAME: &'static str = "Stats";
+    fn schema() -> Value {
+        object_schema("Statistics of the short link.", [
+            ("link", schema_ref::<ShortLink>(), true),
+            ("redirects", Value::object([
+                ("type", Value::from("integer")),
+                ("format", Value::from("int64")),
+                ("minimum", Value::from(0u64)),
+                ("description", Value::from("Count of redirects of the short link.")),
+            ]), true),
+            ("redirect_config", schema_ref::<RedirectConfig>(), false),
+        ])
+    }
+}
+
+impl ToJson for CreateLinkRequest {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("url", Value::from(self.url.as_str())),
+            ("slug", Value::from(self.slug.as_ref().map(Slug::as_str))),
+        ])
+    }
+}
+
+impl FromJson for CreateLinkRequest {
+    fn from_json(value: &Value) -> Result<Self, json::Error> {
+        Ok(CreateLinkRequest {
+            url: Url::from(value.str_field("url")?),
+            slug: value.opt_str_field("slug")?.map(Slug::from),
+        })
+    }
+}
+
+impl JsonSchema for CreateLinkRequest {
+    const SCHEMA_NAME: &'static str = "CreateLinkRequest";
+    fn schema() -> Value {
+        object_schema("Request to create a short link.", [
+            ("url", string_schema("The original URL to shorten."), true),
+            ("slug", Value::object([
+                ("type", Value::from("string")),
+                ("nullable", Value::from(true)),
+                ("description", Value::from("Custom slug. A random one is generated if it's absent.")),
+            ]), false),
+        ])
+    }
+}
+
+impl JsonSchema for RedirectConfig {
+    const SCHEMA_NAME: &'static str = "RedirectConfig";
+    fn schema() -> Value {
+        object_schema("Redirect settings of the short link.", [
+            ("redirect_type", nullable(object_schema(
+                "How clients are redirected (301, 302, 307 or 308). The server default is used if it's absent.",
+                [
+                    ("permanent", boolean_schema("Whether the redirect is permanent."), true),
+                    ("preserve_method", boolean_schema("Whether the client must keep the method and the body of the request."), true),
+                ],
+            )), false),
+            ("cache_control", nullable(object_schema("Caching hint of the redirects, no hint if it's absent.", [
+                ("mode", Value::object([
+                    ("type", Value::from("string")),
+                    ("enum", Value::from(vec![Value::from("no-store"), Value::from("private"), Value::from("public")])),
+                ]), true),
+                ("max_age", Value::object([
+                    ("type", Value::from("integer")),
+                    ("format", Value::from("int64")),
+                    ("minimum", Value::from(0u64)),
+                    ("description", Value::from("Seconds, required by the private and public modes.")),
+                ]), false),
+            ])), false),
+            ("preview", boolean_schema("Show an interstitial page with the target url instead of redirecting."), false),
+        ])
+    }
+}
+
+impl ToJson for ApiError {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("code", Value::from(self.code)),
+            ("message", Value::from(self.message.as_str())),
+        ])
+    }
+}
+
+impl JsonSchema for ApiError {
+    const SCHEMA_NAME: &'static str = "Error";
+    fn schema() -> Value {
+        let codes = SHORTENER_ERRORS
+            .iter()
+            .map(error_code)
+            .chain([INVALID_REQUEST])
+            .map(Value::from)
+            .collect::<Vec<_>>();
+        object_schema("Error of a request.", [
+            ("code", Value::object([
+                ("type", Value::from("string")),
+                ("enum", Value::from(codes)),
+            ]), true),
+            ("message", string_schema("Human readable description of the error."), true),
+        ])
+    }
+}
+
+fn string_schema(description: &str) -> Value {
+    Value::object([("type", Value::from("string")), ("description", Value::from(description))])
+}
+
+fn boolean_schema(description: &str) -> Value {
+    Value::object([("type", Value::from("boolean")), ("description", Value::from(description))])
+}
+
+fn nullable(mut schema: Value) -> Value {
+    schema.insert("nullable", Value::from(true));
+    schema
+}
+
+fn schema_ref<T: JsonSchema>() -> Value {
+    Value::object([("$ref", Value::from(format!("#/components/schemas/{}", T::SCHEMA_NAME)))])
+}
+
+fn object_schema<const N: usize>(description: &str, properties: [(&str, Value, bool); N]) -> Value {
+    let required = properties
+        .iter()
+        .filter(|(_, _, required)| *required)
+        .map(|(name, _, _)| Value::from(*name))
+        .collect::<Vec<_>>();
+    Value::object([
+        ("type", Value::from("object")),
+        ("description", Value::from(description)),
+        ("required", Value::from(required)),
+        ("properties", Value::object(properties.into_iter().map(|(name, schema, _)| (name, schema)))),
+    ])
+}
+
+fn json_content<T: JsonSchema>(description: &str) -> Value {
+    Value::object([
+        ("description", Value::from(description)),
+        ("content", Value::object([(CONTENT_TYPE, Value::object([("schema", schema_ref::<T>())]))])),
+    ])
+}
+
+/// Responses of the operation: the successful one and the ones of the errors
+/// it could return, storage errors included. Errors with the same status share
+/// the response.
+fn responses(status: u16, success: Value, errors: &[ShortenerError], validates_body: bool) -> Value {
+    let mut responses = vec![(status.to_string(), success)];
+    let storage_error = ShortenerError::StorageError(String::new());
+    let errors = errors
+        .iter()
+        .map(|e| (error_status(e), error_code(e)))
+        .chain(validates_body.then_some((400, INVALID_REQUEST)))
+        .chain([(error_status(&storage_error), error_code(&storage_error))]);
+    for (status, code) in errors {
+        let status = status.to_string();
+        match responses.iter_mut().find(|(s, _)| *s == status) {
+            Some((_, response)) => {
+                let description = response.get("description").and_then(Value::as_str).unwrap_or_default();
+                let description = format!("{description}, {code}");
+                response.insert("description", Value::from(description));
+            }
+            None => responses.push((status, json_content::<ApiError>(code))),
+        }
+    }
+    Value::Object(responses)
+}
+
+fn operation(summary: &str, request: Option<Value>, responses: Value) -> Value {
+    let mut operation = Value::object([("summary", Value::from(summary))]);
+    if let Some(request) = request {
+        operation.insert("requestBody", request);
+    }
+    operation.insert("responses", responses);
+    operation
+}
+
+fn slug_parameter() -> Value {
+    Value::from(vec![Value::object([
+        ("name", Value::from("slug")),
+        ("in", Value::from("path")),
+        ("required", Value::from(true)),
+        ("schema", Value::object([("type", Value::from("string"))])),
+    ])])
+}
+
+/// OpenAPI 3.0 document of the API, generated from the [`JsonSchema`] of the
+/// bodies and from the errors the operations return
+pub fn openapi() -> Value {
+    let create = operation(
+        "Create a short link",
+        Some(Value::object([
+            ("required", Value::from(true)),
+            ("content", Value::object([(CONTENT_TYPE, Value::object([("schema", schema_ref::<CreateLinkRequest>())]))])),
+        ])),
+        responses(
+            201,
+            json_content::<ShortLink>("The created short link"),
+            &[ShortenerError::InvalidUrl, ShortenerError::SlugAlreadyInUse],
+            true,
+        ),
+    );
+    let get = operation(
+        "Get a short link",
+        None,
+        responses(200, json_content::<ShortLink>("The short link"), &[ShortenerError::SlugNotFound], false),
+    );
+    let stats = operation(
+        "Get statistics of a short link",
+        None,
+        responses(200, json_content::<Stats>("The statistics"), &[ShortenerError::SlugNotFound], false),
+    );
+    let get_redirect = operation(
+        "Get redirect settings of a short link",
+        None,
+        responses(200, json_content::<RedirectConfig>("The redirect settings"), &[ShortenerError::SlugNotFound], false),
+    );
+    let put_redirect = operation(
+        "Replace redirect settings of a short link",
+        Some(Value::object([
+            ("required", Value::from(true)),
+            ("content", Value::object([(CONTENT_TYPE, Value::object([("schema", schema_ref::<RedirectConfig>())]))])),
+        ])),
+        responses(200, json_content::<RedirectConfig>("The new redirect settings"), &[ShortenerError::SlugNotFound], true),
+    );
+
+    Value::object([
+        ("openapi", Value::from("3.0.3")),
+        ("info", Value::object([
+            ("title", Value::from("URL shortener")),
+            ("version", Value::from(env!("CARGO_PKG_VERSION"))),
+        ])),
+        ("paths", Value::object([
+            ("/links", Value::object([("post", create)])),
+            ("/links/{slug}", Value::object([("parameters", slug_parameter()), ("get", get)])),
+            ("/links/{slug}/stats", Value::object([("parameters", slug_parameter()), ("get", stats)])),
+            ("/links/{slug}/redirect", Value::object([
+                ("parameters", slug_parameter()),
+                ("get", get_redirect),
+                ("put", put_redirect),
+            ])),
+        ])),
+        ("components", Value::object([
+            ("schemas", Value::object([
+                (ShortLink::SCHEMA_NAME, ShortLink::schema()),
+                (Stats::SCHEMA_NAME, Stats::schema()),
+                (CreateLinkRequest::SCHEMA_NAME, CreateLinkRequest::schema()),
+                (RedirectConfig::SCHEMA_NAME, RedirectConfig::schema()),
+                (ApiError::SCHEMA_NAME, ApiError::schema()),
+            ])),
+        ])),
+    ])
+}
+
+pub fn json_response(status: u16, body: &impl ToJson) -> Response {
+    Response::new(status).with_body(CONTENT_TYPE, body.to_json().to_string().into_bytes())
+}
+
+fn error_response(e: ShortenerError) -> Response {
+    json_response(error_status(&e), &ApiError::from(e))
+}
+
+fn invalid_request(message: String) -> Response {
+    json_response(400, &ApiError { code: INVALID_REQUEST, message })
+}
+
+fn parse_body<T: FromJson>(request: &Request) -> Result<T, Response> {
+    let body = std::str::from_utf8(&request.body).map_err(|_| invalid_request("body is not utf-8".into()))?;
+    json::parse(body)
+        .and_then(|v| T::from_json(&v))
+        .map_err(|e| invalid_request(e.to_string()))
+}
+
+impl<S: Service + 'static> Router<S> {
+    /// Handles the API request, `None` if the path is not a part of the API
+    pub(super) fn handle_api(&self, request: &Request, segments: &[&str]) -> Option<Response> {
+        let response = match (request.method.as_str(), segments) {
+            ("POST", ["links"]) => self.api_create(request),
+            ("GET", ["links", slug]) => self.api_get(slug, |stats| json_response(200, &stats.link)),
+            ("GET", ["links", slug, "stats"]) => self.api_get(slug, |stats| json_response(200, &stats)),
+            ("GET", ["links", slug, "redirect"]) => self.api_get_redirect(slug),
+            ("PUT", ["links", slug, "redirect"]) => self.api_put_redirect(slug, request),
+            ("GET", ["openapi.json"]) => json_response(200, &openapi()),
+            (_, ["links"] | ["links", _] | ["links", _, "stats" | "redirect"]) => {
+                json_response(405, &ApiError { code: INVALID_REQUEST, message: "method not allowed".into() })
+            }
+            _ => return None,
+        };
+        Some(response)
+    }
+
+    fn api_create(&self, request: &Request) -> Response {
+        let create = match parse_body::<CreateLinkRequest>(request) {
+            Ok(create) => create,
+            Err(response) => return response,
+        };
+        match self.create_link(create.url, create.slug) {
+            Ok(link) => json_response(201, &link)
+                .with_header("Location", format!("/links/{}", percent_encode(link.slug.as_str()))),
+            Err(e) => error_response(e),
+        }
+    }
+
+    fn api_get_redirect(&self, slug: &str) -> Response {
+        let slug = Slug::from(slug);
+        match self.service.get_redirect_config(slug) {
+            Ok(config) => json_response(200, &config),
+            Err(e) => error_response(e),
+        }
+    }
+
+    fn api_put_redirect(&self, slug: &str, request: &Request) -> Response {
+        let config = match parse_body::<RedirectConfig>(request) {
+            Ok(config) => config,
+            Err(response) => return response,
+        };
+        let slug = Slug::from(slug);
+        match self.service.handle_configure_redirect(slug, config) {
+            Ok(()) => json_response(200, &config),
+            Err(e) => error_response(e),
+        }
+    }
+
+    fn api_get(&self, slug: &str, respond: impl FnOnce(Stats) -> Response) -> Response {
+        let slug = Slug::from(slug);
+        match self.service.get_stats(slug) {
+            Ok(stats) => respond(stats),
+            Err(e) => error_response(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::test::{create_router, request};
+    use crate::http::RedirectStatus;
+    use crate::redirect::{CacheControl, RedirectKind, RedirectType};
+
+    fn body(response: &Response) -> Value {
+        json::parse(std::str::from_utf8(&response.body).unwrap()).unwrap()
+    }
+
+    /// Checks the value against the schema: types, required and known properties
+    fn validate(value: &Value, schema: &Value, document: &Value) -> Result<(), String> {
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            let schema = document.get("components").unwrap().get("schemas").unwrap().get(name).unwrap();
+            return validate(value, schema, document);
+        }
+        let expected_type = schema.str_field("type").unwrap();
+        match (expected_type, value) {
+            (_, Value::Null) if schema.get("nullable") == Some(&Value::Bool(true)) => Ok(()),
+            ("object", Value::Object(members)) => {
+                for required in schema.get("required").unwrap().as_array().unwrap() {
+                    let required = required.as_str().unwrap();
+                    if value.get(required).is_none() {
+                        return Err(format!("missing property {required}"));
+                    }
+                }
+                let properties = schema.get("properties").unwrap();
+                for (name, member) in members {
+                    let property = properties.get(name).ok_or(format!("unknown property {name}"))?;
+                    validate(member, property, document)?;
+                }
+                Ok(())
+            }
+            ("string", Value::String(s)) => match schema.get("enum").and_then(Value::as_array) {
+                Some(variants) if !variants.contains(&Value::from(s.as_str())) => Err(format!("{s} is not in enum")),
+                _ => Ok(()),
+            },
+            ("integer", Value::Number(_)) if value.as_u64().is_some() => Ok(()),
+            ("boolean", Value::Bool(_)) => Ok(()),
+            (expected, value) => Err(format!("expected {expected}, got {}", value.type_name())),
+        }
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let link = ShortLink { slug: Slug::from("s"), url: Url::from("u") };
+        let stats = Stats { link, redirects: u64::MAX, redirect_config: RedirectConfig { preview: true, ..Default::default() } };
+        let text = stats.to_json().to_string();
+        assert_eq!(
+            text,
+            r#"{"link":{"slug":"s","url":"u"},"redirects":18446744073709551615,"redirect_config":{"redirect_type":null,"cache_control":null,"preview":true}}"#,
+        );
+        assert_eq!(Stats::from_json(&json::parse(&text).unwrap()).unwrap(), stats);
+        // the snapshots written before the settings were kept
+        let old = Stats::from_json(&json::parse(r#"{"link":{"slug":"s","url":"u"},"redirects":1}"#).unwrap()).unwrap();
+        assert_eq!(old.redirect_config, RedirectConfig::default());
+
+        let create = CreateLinkRequest::from_json(&json::parse(r#"{"url":"u"}"#).unwrap()).unwrap();
+        assert_eq!(create, CreateLinkRequest { url: Url::from("u"), slug: None });
+        assert_eq!(CreateLinkRequest::from_json(&create.to_json()).unwrap(), create);
+    }
+
+    #[test]
+    fn test_bodies_match_openapi_schemas() {
+        let document = openapi();
+        let link = ShortLink { slug: Slug::from("s"), url: Url::from("u") };
+        let stats = Stats { link: link.clone(), redirects: 3, redirect_config: RedirectConfig::default() };
+        let create = CreateLinkRequest { url: Url::from("u"), slug: Some(Slug::from("s")) };
+        let schema = |name: &str| Value::object([("$ref", Value::from(format!("#/components/schemas/{name}")))]);
+
+        validate(&link.to_json(), &schema(ShortLink::SCHEMA_NAME), &document).unwrap();
+        validate(&stats.to_json(), &schema(Stats::SCHEMA_NAME), &document).unwrap();
+        validate(&create.to_json(), &schema(CreateLinkRequest::SCHEMA_NAME), &document).unwrap();
+        validate(&CreateLinkRequest { slug: None, ..create }.to_json(), &schema(CreateLinkRequest::SCHEMA_NAME), &document).unwrap();
+        let config = RedirectConfig {
+            redirect_type: Some(RedirectType { kind: RedirectKind::Permanent, preserve_method: false }),
+            cache_control: Some(CacheControl::Private { max_age: 60 }),
+            preview: true,
+        };
+        validate(&config.to_json(), &schema(RedirectConfig::SCHEMA_NAME), &document).unwrap();
+        validate(&RedirectConfig::default().to_json(), &schema(RedirectConfig::SCHEMA_NAME), &document).unwrap();
+        for e in SHORTENER_ERRORS {
+            validate(&ApiError::from(e).to_json(), &schema(ApiError::SCHEMA_NAME), &document).unwrap();
+        }
+        assert!(validate(&Value::object([("slug", Value::from("s"))]), &schema(ShortLink::SCHEMA_NAME), &document).is_err());
+    }
+
+    #[test]
+    fn test_openapi_documents_error_statuses() {
+        let document = openapi();
+        let responses = |path: &str, method: &str| {
+            let operation = document.get("paths").unwrap().get(path).unwrap().get(method).unwrap();
+            match operation.get("responses").unwrap() {
+                Value::Object(members) => members.iter().map(|(status, _)| status.clone()).collect::<Vec<_>>(),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(responses("/links", "post"), ["201", "400", "409", "500"]);
+        assert_eq!(responses("/links/{slug}", "get"), ["200", "404", "500"]);
+        assert_eq!(responses("/links/{slug}/stats", "get"), ["200", "404", "500"]);
+        assert_eq!(responses("/links/{slug}/redirect", "get"), ["200", "404", "500"]);
+        assert_eq!(responses("/links/{slug}/redirect", "put"), ["200", "404", "400", "500"]);
+        // the document is a valid JSON
+        assert_eq!(json::parse(&document.to_string()).unwrap(), document);
+    }
+
+    #[test]
+    fn test_api() {
+        let router = create_router(RedirectStatus::default());
+
+        let response = router.handle(&request("POST", "/links", r#"{"url": "https://example.com", "slug": "promo"}"#));
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("Content-Type"), Some(CONTENT_TYPE));
+        assert_eq!(response.header("Location"), Some("/links/promo"));
+        assert_eq!(body(&response), json::parse(r#"{"slug":"promo","url":"https://example.com"}"#).unwrap());
+
+        router.handle(&request("GET", "/promo", ""));
+
+        let response = router.handle(&request("GET", "/links/promo", ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response).str_field("url"), Ok("https://example.com"));
+
+        let response = router.handle(&request("GET", "/links/promo/stats", ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response).u64_field("redirects"), Ok(1));
+
+        let response = router.handle(&request("POST", "/links", r#"{"url": "https://example.com"}"#));
+        assert_eq!(response.status, 201);
+        let slug = body(&response).str_field("slug").unwrap().to_owned();
+        assert_eq!(router.handle(&request("GET", &format!("/links/{slug}"), "")).status, 200);
+    }
+
+    #[test]
+    fn test_api_redirect_config() {
+        let router = create_router(RedirectStatus::default());
+        router.handle(&request("POST", "/links", r#"{"url": "https://example.com", "slug": "promo"}"#));
+
+        let response = router.handle(&request("GET", "/links/promo/redirect", ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response), RedirectConfig::default().to_json());
+
+        let config = r#"{"redirect_type": {"permanent": true, "preserve_method": true}, "cache_control": {"mode": "no-store"}}"#;
+        let response = router.handle(&request("PUT", "/links/promo/redirect", config));
+        assert_eq!(response.status, 200);
+        let expected = RedirectConfig {
+            redirect_type: Some(RedirectType { kind: RedirectKind::Permanent, preserve_method: true }),
+            cache_control: Some(CacheControl::NoStore),
+            preview: false,
+        };
+        assert_eq!(body(&response), expected.to_json());
+        assert_eq!(body(&router.handle(&request("GET", "/links/promo/redirect", ""))), expected.to_json());
+
+        let error = |response: Response| (response.status, body(&response).str_field("code").unwrap().to_owned());
+        assert_eq!(error(router.handle(&request("PUT", "/links/missing/redirect", "{}"))), (404, "slug_not_found".to_owned()));
+        assert_eq!(error(router.handle(&request("GET", "/links/missing/redirect", ""))), (404, "slug_not_found".to_owned()));
+        assert_eq!(
+            error(router.handle(&request("PUT", "/links/promo/redirect", r#"{"preview": 1}"#))),
+            (400, INVALID_REQUEST.to_owned()),
+        );
+        assert_eq!(error(router.handle(&request("POST", "/links/promo/redirect", "{}"))), (405, INVALID_REQUEST.to_owned()));
+    }
+
+    #[test]
+    fn test_api_errors() {
+        let router = create_router(RedirectStatus::default());
+        let error = |response: Response| (response.status, body(&response).str_field("code").unwrap().to_owned());
+
+        router.handle(&request("POST", "/links", r#"{"url": "https://example.com", "slug": "promo"}"#));
+        assert_eq!(
+            error(router.handle(&request("POST", "/links", r#"{"url": "https://example.com", "slug": "promo"}"#))),
+            (409, "slug_already_in_use".to_owned()),
+        );
+        assert_eq!(
+            error(router.handle(&request("POST", "/links", r#"{"url": "http://[:::1]"}"#))),
+            (400, "invalid_url".to_owned()),
+        );
+        assert_eq!(error(router.handle(&request("GET", "/links/missing", ""))), (404, "slug_not_found".to_owned()));
+        assert_eq!(error(router.handle(&request("GET", "/links/missing/stats", ""))), (404, "slug_not_found".to_owned()));
+        assert_eq!(error(router.handle(&request("POST", "/links", "{"))), (400, INVALID_REQUEST.to_owned()));
+        assert_eq!(error(router.handle(&request("POST", "/links", r#"{"slug": "x"}"#))), (400, INVALID_REQUEST.to_owned()));
+        assert_eq!(error(router.handle(&request("POST", "/links", r#"{"url": 1}"#))), (400, INVALID_REQUEST.to_owned()));
+        assert_eq!(error(router.handle(&request("DELETE", "/links/promo", ""))), (405, INVALID_REQUEST.to_owned()));
+    }
+}
@@ -0,0 +1,299 @@
+use std::io::{self, BufRead, Read, Write};
+
+/// Limit of the request line and of every header line
+const MAX_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// HTTP/1.1 request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// percent-encoded path, without the query
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// HTTP/1.1 response
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    /// The connection is closed before the request line
+    ConnectionClosed,
+    Malformed(&'static str),
+    TooLarge,
+    /// `Transfer-Encoding` is not supported, only `Content-Length`
+    UnsupportedTransferEncoding,
+    Io(io::Error),
+}
+
+impl Request {
+    /// Reads a request. The body is read according to `Content-Length`.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Request, RequestError> {
+        let request_line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Err(RequestError::ConnectionClosed),
+        };
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version), None) => (method, target, version),
+            _ => return Err(RequestError::Malformed("invalid request line")),
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Err(RequestError::Malformed("unsupported http version"));
+        }
+        if method.is_empty() || !target.starts_with('/') {
+            return Err(RequestError::Malformed("invalid request line"));
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_owned(), Some(query.to_owned())),
+            None => (target.to_owned(), None),
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader)?.ok_or(RequestError::Malformed("unexpected end of headers"))?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() >= MAX_HEADERS {
+                return Err(RequestError::TooLarge);
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(RequestError::Malformed("invalid header"))?;
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+
+        let mut request = Request { method: method.to_owned(), path, query, headers, body: Vec::new() };
+        if request.header("Transfer-Encoding").is_some() {
+            return Err(RequestError::UnsupportedTransferEncoding);
+        }
+        if let Some(content_length) = request.header("Content-Length") {
+            let content_length: usize = content_length
+                .parse()
+                .map_err(|_| RequestError::Malformed("invalid content length"))?;
+            if content_length > MAX_BODY_LEN {
+                return Err(RequestError::TooLarge);
+            }
+            request.body = vec![0; content_length];
+            reader.read_exact(&mut request.body).map_err(RequestError::Io)?;
+        }
+        Ok(request)
+    }
+
+    /// Returns the value of the first header with the name (case insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Percent-decoded segments of the path, without the empty ones
+    pub fn path_segments(&self) -> Option<Vec<String>> {
+        self.path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(percent_decode)
+            .collect()
+    }
+
+    /// Parameters of the `application/x-www-form-urlencoded` body
+    pub fn form_param(&self, name: &str) -> Option<String> {
+        crate::url_parser::form_urlencoded::parse(&self.body)
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.into_owned())
+    }
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status).with_body("text/plain; charset=utf-8", body.into().into_bytes())
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_body(self, content_type: &str, body: Vec<u8>) -> Self {
+        Self { body, ..self.with_header("Content-Type", content_type) }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Writes the response. Connections are not reused, so it's always
+    /// `Connection: close`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status))?;
+        for (name, value) in &self.headers {
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+        write!(writer, "Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len())?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, RequestError> {
+    let mut line = Vec::new();
+    let read = reader
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .map_err(RequestError::Io)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        return Err(match line.len() > MAX_LINE_LEN {
+            true => RequestError::TooLarge,
+            false => RequestError::Malformed("unexpected end of line"),
+        });
+    }
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| RequestError::Malformed("non utf-8 line"))
+}
+
+/// Decodes `%XX` sequences, returns `None` on invalid sequences or non utf-8 result
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Encodes everything except unreserved symbols (RFC 3986) as `%XX`
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+impl core::error::Error for RequestError {}
+impl core::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ConnectionClosed => write!(f, "connection closed"),
+            Self::Malformed(reason) => write!(f, "malformed request: {reason}"),
+            Self::TooLarge => write!(f, "request is too large"),
+            Self::UnsupportedTransferEncoding => write!(f, "unsupported transfer encoding"),
+            Self::Io(e) => write!(f, "i/o error: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /shorten?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 9\r\n\r\nurl=a%20bEXTRA";
+        let request = Request::read_from(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/shorten");
+        assert_eq!(request.query.as_deref(), Some("x=1"));
+        assert_eq!(request.header("Content-Length"), Some("9"));
+        assert_eq!(request.form_param("url").as_deref(), Some("a b"));
+        assert_eq!(request.form_param("slug"), None);
+    }
+
+    #[test]
+    fn test_read_invalid_request() {
+        assert!(matches!(Request::read_from(&mut "".as_bytes()), Err(RequestError::ConnectionClosed)));
+        assert!(matches!(Request::read_from(&mut "GET\r\n\r\n".as_bytes()), Err(RequestError::Malformed(_))));
+        assert!(matches!(Request::read_from(&mut "GET / HTTP/1.1\r\nHost".as_bytes()), Err(RequestError::Malformed(_))));
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(matches!(Request::read_from(&mut chunked.as_bytes()), Err(RequestError::UnsupportedTransferEncoding)));
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+        assert!(matches!(Request::read_from(&mut long.as_bytes()), Err(RequestError::TooLarge)));
+    }
+
+    #[test]
+    fn test_path_segments() {
+        let request = Request::read_from(&mut "GET /stats/%D0%BF%D1%80%D0%BE%D0%BC%D0%BE/ HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(request.path_segments().unwrap(), ["stats", "промо"]);
+        let request = Request::read_from(&mut "GET /%ZZ HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(request.path_segments(), None);
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("промо a"), "%D0%BF%D1%80%D0%BE%D0%BC%D0%BE%20a");
+        assert_eq!(percent_decode(&percent_encode("a/b?c")).unwrap(), "a/b?c");
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut out = Vec::new();
+        Response::text(404, "slug not found").write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 14\r\nConnection: close\r\n\r\nslug not found",
+        );
+    }
+}
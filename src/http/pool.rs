@@ -0,0 +1,80 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Fixed size pool of threads executing jobs in the order they are submitted
+pub struct ThreadPool {
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+    /// Creates a pool with `size` threads (at least one)
+    pub fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size.max(1))
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("http-worker-{i}"))
+                    .spawn(move || loop {
+                        // the lock is released right after a job is received
+                        let job = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => return,
+                        };
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => return, // the pool is dropped
+                        }
+                    })
+                    // unwrap: fails only if the OS refuses to create a thread
+                    .unwrap()
+            })
+            .collect();
+        Self { workers, sender: Some(sender) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if let Some(sender) = &self.sender {
+            // all workers can't be gone while the pool holds the sender,
+            // except when they had panicked, then the job is dropped
+            let _ = sender.send(Box::new(job));
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Waits for the submitted jobs to be finished
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_pool_executes_all_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(4);
+        assert_eq!(pool.size(), 4);
+        for _ in 0..100 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || { counter.fetch_add(1, Ordering::SeqCst); });
+        }
+        drop(pool);
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+}
@@ -0,0 +1,570 @@
+//! Std-only JSON: a [`Value`] tree, a strict parser and a compact writer.
+
+use std::fmt::{self, Write};
+
+/// Nesting limit of arrays and objects on parsing
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in the order of insertion (or appearance in the source)
+    Object(Vec<(String, Value)>),
+}
+
+/// A number kept as its JSON text, so integers don't lose precision
+#[derive(Debug, Clone, PartialEq)]
+pub struct Number(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// Byte offset in the source, `None` for errors not related to parsing
+    pub position: Option<usize>,
+    pub message: String,
+}
+
+/// Conversion into a JSON [`Value`]
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+/// Conversion from a JSON [`Value`]
+pub trait FromJson: Sized {
+    fn from_json(value: &Value) -> Result<Self, Error>;
+}
+
+/// JSON Schema (OpenAPI flavour) of a type implementing [`ToJson`] or [`FromJson`]
+pub trait JsonSchema {
+    /// Name of the schema in the OpenAPI components
+    const SCHEMA_NAME: &'static str;
+    fn schema() -> Value;
+}
+
+impl Value {
+    pub fn object<K: Into<String>, I: IntoIterator<Item = (K, Value)>>(members: I) -> Value {
+        Value::Object(members.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Sets the member of the object, replacing the existing one with the
+    /// same key. Does nothing if the value is not an object.
+    pub fn insert(&mut self, key: impl Into<String>, value: Value) {
+        if let Value::Object(members) = self {
+            let key = key.into();
+            match members.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = value,
+                None => members.push((key, value)),
+            }
+        }
+    }
+
+    /// Takes the member of the object out, `None` if it's absent or the value
+    /// is not an object
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        match self {
+            Value::Object(members) => {
+                let position = members.iter().position(|(k, _)| k == key)?;
+                Some(members.remove(position).1)
+            }
+            _ => None,
+        }
+    }
+
+    /// Member of the object by the key
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) => n.0.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => n.0.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Name of the JSON type, as in JSON Schema
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(n) if n.0.parse::<i64>().is_ok() => "integer",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        }
+    }
+
+    /// Required string member of the object
+    pub fn str_field(&self, key: &str) -> Result<&str, Error> {
+        match self.get(key) {
+            Some(Value::String(s)) => Ok(s),
+            Some(v) => Err(Error::new(format!("field `{key}` must be a string, not {}", v.type_name()))),
+            None => Err(Error::new(format!("missing field `{key}`"))),
+        }
+    }
+
+    /// Optional string member of the object, `null` is the same as absence
+    pub fn opt_str_field(&self, key: &str) -> Result<Option<&str>, Error> {
+        match self.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(_) => self.str_field(key).map(Some),
+        }
+    }
+
+    /// Required unsigned integer member of the object
+    pub fn u64_field(&self, key: &str) -> Result<u64, Error> {
+        match self.get(key) {
+            Some(v) => v
+                .as_u64()
+                .ok_or_else(|| Error::new(format!("field `{key}` must be an unsigned integer"))),
+            None => Err(Error::new(format!("missing field `{key}`"))),
+        }
+    }
+
+    /// Required boolean member of the object
+    pub fn bool_field(&self, key: &str) -> Result<bool, Error> {
+        match self.get(key) {
+            Some(v) => v
+                .as_bool()
+                .ok_or_else(|| Error::new(format!("field `{key}` must be a boolean, not {}", v.type_name()))),
+            None => Err(Error::new(format!("missing field `{key}`"))),
+        }
+    }
+
+    /// Required member of the object
+    pub fn field(&self, key: &str) -> Result<&Value, Error> {
+        self.get(key).ok_or_else(|| Error::new(format!("missing field `{key}`")))
+    }
+
+    /// Optional member of the object, `null` is the same as absence
+    pub fn opt_field(&self, key: &str) -> Option<&Value> {
+        self.get(key).filter(|v| !v.is_null())
+    }
+}
+
+impl ToJson for Value {
+    fn to_json(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl Error {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self { position: None, message: message.into() }
+    }
+
+    fn at(position: usize, message: impl Into<String>) -> Self {
+        Self { position: Some(position), message: message.into() }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_owned())
+    }
+}
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+impl From<u64> for Value {
+    fn from(n: u64) -> Value {
+        Value::Number(Number(n.to_string()))
+    }
+}
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Number(Number(n.to_string()))
+    }
+}
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Value {
+        Value::Array(items)
+    }
+}
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Parses a JSON text, which must contain exactly one value
+pub fn parse(source: &str) -> Result<Value, Error> {
+    let mut parser = Parser { source: source.as_bytes(), position: 0 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.position != parser.source.len() {
+        return Err(Error::at(parser.position, "unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    source: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        match self.peek() {
+            Some(b) if b == byte => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(Error::at(self.position, format!("expected `{}`", byte as char))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, Error> {
+        match self.source[self.position..].starts_with(keyword.as_bytes()) {
+            true => {
+                self.position += keyword.len();
+                Ok(value)
+            }
+            false => Err(Error::at(self.position, "unexpected character")),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::at(self.position, "too deep nesting"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Value::Null),
+            Some(b't') => self.keyword("true", Value::Bool(true)),
+            Some(b'f') => self.keyword("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.array(depth),
+            Some(b'{') => self.object(depth),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(Error::at(self.position, "unexpected character")),
+            None => Err(Error::at(self.position, "unexpected end of input")),
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, Error> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(Error::at(self.position, "expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, Error> {
+        self.expect(b'{')?;
+        let mut members: Vec<(String, Value)> = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key_position = self.position;
+            let key = self.string()?;
+            if members.iter().any(|(k, _)| *k == key) {
+                return Err(Error::at(key_position, format!("duplicate key `{key}`")));
+            }
+            self.skip_whitespace();
+            self.expect(b':')?;
+            let value = self.value(depth + 1)?;
+            members.push((key, value));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(Error::at(self.position, "expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.position;
+        let digits = |parser: &mut Self| {
+            let from = parser.position;
+            while let Some(b'0'..=b'9') = parser.peek() {
+                parser.position += 1;
+            }
+            parser.position - from
+        };
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        match (self.peek(), digits(self)) {
+            (_, 0) => return Err(Error::at(self.position, "expected a digit")),
+            (Some(b'0'), len) if len > 1 => return Err(Error::at(start, "leading zeros are not allowed")),
+            _ => {}
+        }
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            if digits(self) == 0 {
+                return Err(Error::at(self.position, "expected a digit"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.position += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.position += 1;
+            }
+            if digits(self) == 0 {
+                return Err(Error::at(self.position, "expected a digit"));
+            }
+        }
+        // unwrap: the number is ascii
+        let text = std::str::from_utf8(&self.source[start..self.position]).unwrap();
+        Ok(Value::Number(Number(text.to_owned())))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let byte = self.peek().ok_or_else(|| Error::at(self.position, "unterminated string"))?;
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = self.peek().ok_or_else(|| Error::at(self.position, "unterminated string"))?;
+                    self.position += 1;
+                    match escaped {
+                        b'"' => out.push(b'"'),
+                        b'\\' => out.push(b'\\'),
+                        b'/' => out.push(b'/'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'u' => {
+                            let c = self.unicode_escape()?;
+                            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        _ => return Err(Error::at(self.position - 1, "invalid escape")),
+                    }
+                }
+                0x00..=0x1f => return Err(Error::at(self.position - 1, "control character in string")),
+                _ => out.push(byte),
+            }
+        }
+        // the source is a str, and escapes produce valid utf-8
+        String::from_utf8(out).map_err(|_| Error::at(self.position, "invalid utf-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let hex = self
+            .source
+            .get(self.position..self.position + 4)
+            // from_str_radix accepts a sign too
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| Error::at(self.position, "invalid unicode escape"))?;
+        self.position += 4;
+        Ok(hex)
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, Error> {
+        let position = self.position;
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if !self.source[self.position..].starts_with(b"\\u") {
+                    return Err(Error::at(position, "unpaired surrogate"));
+                }
+                self.position += 2;
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(Error::at(position, "unpaired surrogate"));
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            0xDC00..=0xDFFF => return Err(Error::at(position, "unpaired surrogate")),
+            _ => high,
+        };
+        char::from_u32(code).ok_or_else(|| Error::at(position, "invalid unicode escape"))
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\u{00}'..='\u{1f}' => write!(f, "\\u{:04x}", c as u32)?,
+            _ => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Compact JSON text
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) => f.write_str(&n.0),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{} at {position}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = parse(r#" {"a": [1, -2.5e3, true, null], "b": "x\"\u00e9\ud83d\ude00", "c": {}} "#).unwrap();
+        assert_eq!(value.get("a").unwrap().as_array().unwrap()[0].as_u64(), Some(1));
+        assert_eq!(value.get("a").unwrap().as_array().unwrap()[1].as_f64(), Some(-2500.0));
+        assert_eq!(value.get("b").unwrap().as_str(), Some("x\"é😀"));
+        assert_eq!(value.get("c"), Some(&Value::Object(Vec::new())));
+        assert_eq!(parse("18446744073709551615").unwrap().as_u64(), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for invalid in [
+            "", "{", "[1,]", "{\"a\":1,}", "01", "1.", "-", "1e", "\"\\x\"", "\"\u{01}\"", "tru",
+            "\"\\u+abc\"", "\"\\u-abc\"", "\"\\ud83d\"", "\"\\ude00\"", "{\"a\":1,\"a\":2}", "[] []", "{a:1}",
+        ] {
+            assert!(parse(invalid).is_err(), "{invalid:?} is accepted");
+        }
+        let deep = "[".repeat(MAX_DEPTH + 2) + &"]".repeat(MAX_DEPTH + 2);
+        assert!(parse(&deep).is_err());
+        assert_eq!(parse("[1 2]").unwrap_err().position, Some(3));
+    }
+
+    #[test]
+    fn test_write() {
+        let value = Value::object([
+            ("s", Value::from("a\"b\\c\n\u{1}é")),
+            ("n", Value::from(42u64)),
+            ("a", Value::from(vec![Value::Null, Value::from(false)])),
+        ]);
+        let text = value.to_string();
+        assert_eq!(text, r#"{"s":"a\"b\\c\n\u0001é","n":42,"a":[null,false]}"#);
+        assert_eq!(parse(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_fields() {
+        let value = parse(r#"{"url": "u", "slug": null, "n": 1, "f": -1}"#).unwrap();
+        assert_eq!(value.str_field("url"), Ok("u"));
+        assert_eq!(value.opt_str_field("slug"), Ok(None));
+        assert_eq!(value.opt_str_field("missing"), Ok(None));
+        assert_eq!(value.u64_field("n"), Ok(1));
+        assert!(value.u64_field("f").is_err());
+        assert!(value.str_field("n").is_err());
+        assert!(value.str_field("missing").is_err());
+    }
+}
@@ -36,14 +36,41 @@
 //! - The service must be built using CQRS and Event Sourcing approaches.
 //! - The service must be possible to run in Rust Playground (so no database like
 //!   Postgres is allowed)
-//! - Public API already written for this task must not be changed (any change to
-//!   the public API items must be considered as breaking change).
+//! - Any change to the public API items written for this task is a breaking
+//!   change, see [Breaking changes](#breaking-changes).
+//!
+//! ## Breaking changes
+//!
+//! The public API of the task is changed on purpose since version 0.2:
+//!
+//! - [`ShortenerError::StorageError`] reports the failures of the storage,
+//!   which used to panic
+//! - [`Stats::redirect_config`] keeps the redirect settings of the link
+//! - [`ShortenerEvent::RedirectConfigured`] records the changes of the
+//!   settings
+//! - [`cqrs::DomainEvent`] requires `PartialEq`, the stores compare the
+//!   committed events with the stored ones
+//! - [`UrlShortenerService::new`] and [`sync::SyncUrlShortenerService::new`]
+//!   take the storage of the [`clicks::Clicks`] streams, so the redirects are
+//!   as durable as the links
+//!
+//! Exhaustive matches of the enums, struct literals of [`Stats`] and the
+//! calls of the service constructors need updating.
 
 extern crate url as url_parser;
 
-mod cqrs;
-mod gen;
+pub mod clicks;
+pub mod cqrs;
+pub mod gen;
+pub mod http;
+pub mod json;
+pub mod redirect;
+pub mod sync;
+pub mod async_service;
 mod base64;
+mod radix;
+mod normalize;
+mod suggest;
 mod string_based_type;
 mod owned_borrowed_pair;
 
@@ -66,6 +93,10 @@ pub enum ShortenerError {
     /// This error occurs when the provided [`Slug`] does not map to any existing
     /// short link.
     SlugNotFound,
+
+    /// This error occurs when the storage of the events fails, e.g. on I/O
+    /// errors. Holds the message of the [`EventStoreError`](cqrs::store::EventStoreError).
+    StorageError(String),
 }
 
 /// A unique string (or alias) that represents the shortened version of the
@@ -96,11 +127,17 @@ pub struct Stats {
 
     /// Count of redirects of the [`ShortLink`].
     pub redirects: u64,
+
+    /// Redirect settings of the [`ShortLink`], kept in the state so they
+    /// survive the compaction of the stream.
+    pub redirect_config: redirect::RedirectConfig,
 }
 
 /// Commands for CQRS.
 pub mod commands {
     use super::{ShortLink, ShortenerError, Slug, Url};
+    use super::cqrs::future::BoxFuture;
+    use super::redirect::{Redirect, RedirectConfig};
 
     /// Trait for command handlers.
     pub trait CommandHandler {
@@ -124,11 +161,75 @@ pub mod commands {
             slug: Slug,
         ) -> Result<ShortLink, ShortenerError>;
     }
+
+    /// Trait for handlers of the per-link redirect settings.
+    pub trait RedirectCommandHandler {
+        /// Replaces the [`RedirectConfig`] of the link.
+        fn handle_configure_redirect(
+            &mut self,
+            slug: Slug,
+            config: RedirectConfig,
+        ) -> Result<(), ShortenerError>;
+
+        /// Same as [`CommandHandler::handle_redirect`], but returns the
+        /// [`RedirectConfig`] of the link along with it.
+        fn handle_redirect_with_config(
+            &mut self,
+            slug: Slug,
+        ) -> Result<Redirect, ShortenerError>;
+    }
+
+    /// Command handlers which may be shared between threads: same as
+    /// [`CommandHandler`] and [`RedirectCommandHandler`], but with `&self`.
+    pub trait SyncCommandHandler {
+        fn handle_create_short_link(
+            &self,
+            url: Url,
+            slug: Option<Slug>,
+        ) -> Result<ShortLink, ShortenerError>;
+
+        fn handle_redirect(
+            &self,
+            slug: Slug,
+        ) -> Result<ShortLink, ShortenerError>;
+
+        fn handle_configure_redirect(
+            &self,
+            slug: Slug,
+            config: RedirectConfig,
+        ) -> Result<(), ShortenerError>;
+
+        fn handle_redirect_with_config(
+            &self,
+            slug: Slug,
+        ) -> Result<Redirect, ShortenerError>;
+    }
+
+    /// Async counterpart of [`CommandHandler`]. Commands take `&self`, as
+    /// async servers share the service between tasks.
+    pub trait AsyncCommandHandler {
+        /// See [`CommandHandler::handle_create_short_link`].
+        fn handle_create_short_link(
+            &self,
+            url: Url,
+            slug: Option<Slug>,
+        ) -> BoxFuture<'_, Result<ShortLink, ShortenerError>>;
+
+        /// See [`CommandHandler::handle_redirect`].
+        fn handle_redirect(
+            &self,
+            slug: Slug,
+        ) -> BoxFuture<'_, Result<ShortLink, ShortenerError>>;
+    }
 }
 
 /// Queries for CQRS
 pub mod queries {
     use super::{ShortenerError, Slug, Stats};
+    use super::cqrs::future::BoxFuture;
+    use super::cqrs::history::AsOf;
+    use super::cqrs::store::EventStoreError;
+    use super::redirect::RedirectConfig;
 
     /// Trait for query handlers.
     pub trait QueryHandler {
@@ -138,22 +239,97 @@ pub mod queries {
         /// [`ShortLink`]: super::ShortLink
         fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError>;
     }
+
+    /// Trait for slug suggestion queries.
+    pub trait SlugSuggestionHandler {
+        /// Returns up to `limit` available near-variants of the desired
+        /// [`Slug`] (e.g. when it is already in use), the closest first.
+        fn suggest_slugs(&self, slug: Slug, limit: usize) -> Result<Vec<Slug>, ShortenerError>;
+    }
+
+    /// Trait for queries of the per-link redirect settings.
+    pub trait RedirectConfigQueryHandler {
+        /// Returns the [`RedirectConfig`] of the link.
+        fn get_redirect_config(&self, slug: Slug) -> Result<RedirectConfig, ShortenerError>;
+    }
+
+    /// Trait for audits of the past, see [`cqrs::history`](super::cqrs::history).
+    pub trait HistoryQueryHandler {
+        /// Returns the [`Stats`] of every link as of the moment, sorted by
+        /// slugs. A [`AsOf::Position`] is a position in the log of the links
+        /// storage, it fails unless the clicks storage shares its backend, see
+        /// [`EventStore::log_id`](super::cqrs::store::EventStore::log_id).
+        fn get_stats_as_of(&self, as_of: AsOf) -> Result<Vec<Stats>, EventStoreError>;
+    }
+
+    /// Async counterpart of [`QueryHandler`].
+    pub trait AsyncQueryHandler {
+        /// See [`QueryHandler::get_stats`].
+        fn get_stats(&self, slug: Slug) -> BoxFuture<'_, Result<Stats, ShortenerError>>;
+    }
 }
 
 /// CQRS and Event Sourcing-based service implementation
 pub struct UrlShortenerService {
     // dynamic dispatch allows us to change implementations with a configuration (file)
     storage: Box<dyn cqrs::store::EventStore<Stats>>,
+    clicks: Box<dyn cqrs::store::EventStore<clicks::Clicks>>,
     slug_generator: Box<dyn gen::SlugGenerator>,
+    redirect_buffer: Option<clicks::RedirectBuffer>,
 }
 
 impl UrlShortenerService {
-    /// Creates a new instance of the service
+    /// Creates a new instance of the service, the [`clicks::Clicks`] streams
+    /// are kept in `clicks`, e.g. a store of the backend of `storage`, see
+    /// [`cqrs::file_store::FileBackend`]
     pub fn new(
         storage: Box<dyn cqrs::store::EventStore<Stats>>,
+        clicks: Box<dyn cqrs::store::EventStore<clicks::Clicks>>,
         generator: Box<dyn gen::SlugGenerator>,
     ) -> Self {
-        Self { storage, slug_generator: generator }
+        Self {
+            storage,
+            clicks,
+            slug_generator: generator,
+            redirect_buffer: None,
+        }
+    }
+
+    /// Counts the redirects in memory and commits them in batches, see
+    /// [`clicks::RedirectBuffering`]
+    pub fn with_redirect_buffering(mut self, config: clicks::RedirectBuffering) -> Self {
+        self.redirect_buffer = Some(clicks::RedirectBuffer::new(config));
+        self
+    }
+
+    /// Commits all the buffered redirects
+    pub fn flush_redirects(&self) -> Result<(), cqrs::store::EventStoreError> {
+        self.shortener().flush_redirects(false)
+    }
+
+    /// Commits the buffered redirects which have waited the `max_delay`, for
+    /// the links without further redirects. Expected to be called periodically.
+    pub fn flush_due_redirects(&self) -> Result<(), cqrs::store::EventStoreError> {
+        self.shortener().flush_redirects(true)
+    }
+
+    fn shortener(&self) -> Shortener<'_> {
+        // `&mut self` of the commands already makes them exclusive
+        Shortener {
+            storage: &*self.storage,
+            clicks: &*self.clicks,
+            slug_generator: &*self.slug_generator,
+            locks: None,
+            redirect_buffer: self.redirect_buffer.as_ref(),
+        }
+    }
+}
+
+/// Buffered redirects are committed on drop, errors are ignored, see
+/// [`UrlShortenerService::flush_redirects`] to handle them
+impl Drop for UrlShortenerService {
+    fn drop(&mut self) {
+        let _ = self.flush_redirects();
     }
 }
 
@@ -163,85 +339,272 @@ impl commands::CommandHandler for UrlShortenerService {
         url: Url,
         slug: Option<Slug>,
     ) -> Result<ShortLink, ShortenerError> {
+        self.shortener().create_short_link(url, slug)
+    }
 
-        if let Err(_) = url_parser::Url::parse(url.as_ref()) {
-            return Err(ShortenerError::InvalidUrl)
-        }
-        
-        let slug = match slug {
-            Some(slug) => {
-                let is_exist = self.storage
-                    .is_exist(&slug)
-                    .map_err(map_fetch_err_to_shortener_err)?;
-                if is_exist {
-                    return Err(ShortenerError::SlugAlreadyInUse)
-                }
-                slug
-            }
+    fn handle_redirect(
+        &mut self,
+        slug: Slug,
+    ) -> Result<ShortLink, ShortenerError> {
+        self.shortener().redirect(slug)
+    }
+}
+
+impl commands::RedirectCommandHandler for UrlShortenerService {
+    fn handle_configure_redirect(
+        &mut self,
+        slug: Slug,
+        config: redirect::RedirectConfig,
+    ) -> Result<(), ShortenerError> {
+        self.shortener().configure_redirect(slug, config)
+    }
+
+    fn handle_redirect_with_config(
+        &mut self,
+        slug: Slug,
+    ) -> Result<redirect::Redirect, ShortenerError> {
+        self.shortener().redirect_with_config(slug)
+    }
+}
+
+impl queries::QueryHandler for UrlShortenerService {
+    fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+        self.shortener().stats(slug)
+    }
+}
+
+impl queries::RedirectConfigQueryHandler for UrlShortenerService {
+    fn get_redirect_config(&self, slug: Slug) -> Result<redirect::RedirectConfig, ShortenerError> {
+        self.shortener().redirect_config(slug)
+    }
+}
+
+impl queries::HistoryQueryHandler for UrlShortenerService {
+    fn get_stats_as_of(&self, as_of: cqrs::history::AsOf) -> Result<Vec<Stats>, cqrs::store::EventStoreError> {
+        self.shortener().stats_as_of(as_of)
+    }
+}
+
+impl queries::SlugSuggestionHandler for UrlShortenerService {
+    fn suggest_slugs(&self, slug: Slug, limit: usize) -> Result<Vec<Slug>, ShortenerError> {
+        self.shortener().suggest_slugs(slug, limit)
+    }
+}
+
+/// Commands and queries of the services over borrowed parts of them. With
+/// `locks` the commands hold the lock of the stream from fetching to
+/// committing. Links are defined by the [`Stats`] streams of `storage`, their
+/// redirects are counted by the [`clicks::Clicks`] streams of `clicks`,
+/// through the `redirect_buffer` if it's set.
+pub(crate) struct Shortener<'a> {
+    pub(crate) storage: &'a dyn cqrs::store::EventStore<Stats>,
+    pub(crate) clicks: &'a dyn cqrs::store::EventStore<clicks::Clicks>,
+    pub(crate) slug_generator: &'a dyn gen::SlugGenerator,
+    pub(crate) locks: Option<&'a sync::SlugLocks>,
+    pub(crate) redirect_buffer: Option<&'a clicks::RedirectBuffer>,
+}
+
+impl<'a> Shortener<'a> {
+    fn lock<A: cqrs::Aggregate<IdRef = SlugRef>>(&self, slug: &SlugRef) -> Option<std::sync::MutexGuard<'a, ()>> {
+        self.locks.map(|locks| locks.lock(A::aggregate_type().as_ref(), slug))
+    }
+
+    pub(crate) fn create_short_link(&self, url: Url, slug: Option<Slug>) -> Result<ShortLink, ShortenerError> {
+        // the url is validated by the aggregate, see `Stats::decide`
+        let event_list = match slug {
+            Some(slug) => self.execute(self.storage, &slug, ShortenerCommand::Create(slug.clone(), url))?,
             None => {
                 let mut bump: u16 = 0;
                 loop {
                     let generated_slug = self.slug_generator.generate(url.as_ref(), bump);
-                    let is_exist = self.storage
-                        .is_exist(&generated_slug)
-                        .map_err(map_fetch_err_to_shortener_err)?;
-                    if !is_exist {
-                        break generated_slug
+                    let command = ShortenerCommand::Create(generated_slug.clone(), url.clone());
+                    match self.execute(self.storage, &generated_slug, command) {
+                        Err(ShortenerError::SlugAlreadyInUse) => {}
+                        result => break result?,
                     }
                     bump += 1;
-                    if bump >= u16::MAX {
+                    if bump == u16::MAX {
                         unreachable!("somehow bump reaches it's maximum");
                     }
                 }
             }
         };
+        Ok(event_list.snapshot().into_aggregate().link)
+    }
+
+    pub(crate) fn redirect(&self, slug: Slug) -> Result<ShortLink, ShortenerError> {
+        Ok(self.redirect_with_config(slug)?.link)
+    }
+
+    /// Reads the link and counts the redirect in its clicks stream, the link
+    /// stream is never written
+    pub(crate) fn redirect_with_config(&self, slug: Slug) -> Result<redirect::Redirect, ShortenerError> {
+        let event_list = self.storage
+            .fetch(slug.as_ref())
+            .map_err(map_fetch_err_to_shortener_err)?;
+        let Stats { link, redirect_config: config, .. } = event_list.snapshot().into_aggregate();
+        match self.redirect_buffer {
+            Some(buffer) => {
+                for (slug, count) in buffer.add(&link.slug) {
+                    // the redirect is counted anyway, the failed counts stay
+                    // in the buffer and the flushes report them
+                    let _ = self.count_redirects(buffer, slug, count);
+                }
+            }
+            None => {
+                self.execute(self.clicks, &link.slug, clicks::ClickCommand::Redirect(link.slug.clone()))?;
+            }
+        }
+        Ok(redirect::Redirect { link, config })
+    }
 
-        // unwrap: i promise, this is a correct event list
-        let event_list = StoredEventList::new(&[ShortenerEvent::Create(slug, url)]).unwrap();
-        let snapshot = event_list.snapshot();
-        // unwrap: there is not type error to handle storage event
-        self.storage.commit(event_list).unwrap();
+    /// Commits the buffered redirects, only the ones which have waited if `due_only`
+    pub(crate) fn flush_redirects(&self, due_only: bool) -> Result<(), cqrs::store::EventStoreError> {
+        let Some(buffer) = self.redirect_buffer else {
+            return Ok(());
+        };
+        let counts = match due_only {
+            true => buffer.take_due(),
+            false => buffer.take_all(),
+        };
+        let mut result = Ok(());
+        for (slug, count) in counts {
+            // the rest are committed anyway, the failed ones stay in the buffer
+            result = result.and(self.count_redirects(buffer, slug, count));
+        }
+        result
+    }
 
-        Ok(snapshot.into_aggregate().link)
+    /// Commits the count taken from the buffer, returns it back on failure
+    fn count_redirects(&self, buffer: &clicks::RedirectBuffer, slug: Slug, count: u64) -> Result<(), cqrs::store::EventStoreError> {
+        let _guard = self.lock::<clicks::Clicks>(&slug);
+        let command = clicks::ClickCommand::CountRedirects(slug.clone(), count);
+        match cqrs::bus::CommandBus::new(self.clicks).execute(&slug, command, ()) {
+            Ok(_) => Ok(()),
+            Err(cqrs::bus::CommandBusError::Command(e)) => unreachable!("counting of redirects is never rejected: {e}"),
+            Err(cqrs::bus::CommandBusError::Store(e)) => {
+                buffer.restore(slug, count);
+                Err(e)
+            }
+        }
     }
 
-    fn handle_redirect(
-        &mut self,
-        slug: Slug,
-    ) -> Result<ShortLink, ShortenerError> {
-        let event_list = self
-            .storage.fetch(&slug)
-            .map_err(map_fetch_err_to_shortener_err)?
-            .append_all(&[ShortenerEvent::ShortLinkStatEvent(slug, ShortLinkStatEvent::Redirect)]);
-        let snapshot = event_list.snapshot();
-        // unwrap: there is not type error to handle storage event
-        self.storage.commit(event_list).unwrap();
-        Ok(snapshot.into_aggregate().link)
+    pub(crate) fn configure_redirect(&self, slug: Slug, config: redirect::RedirectConfig) -> Result<(), ShortenerError> {
+        self.execute(self.storage, &slug, ShortenerCommand::ConfigureRedirect(config))?;
+        Ok(())
     }
-}
 
-impl queries::QueryHandler for UrlShortenerService {
-    fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
-        Ok(self.storage
+    /// Runs the command through the [`cqrs::bus::CommandBus`], returns the committed stream
+    fn execute<A>(
+        &self,
+        storage: &dyn cqrs::store::EventStore<A>,
+        slug: &SlugRef,
+        command: <A as cqrs::CommandHandler<A>>::Command,
+    ) -> Result<StoredEventList<A>, ShortenerError>
+    where
+        A: cqrs::Aggregate<IdRef = SlugRef> + cqrs::CommandHandler<A, Error = ShortenerError, Services = ()>,
+        <A as cqrs::CommandHandler<A>>::Command: Clone,
+    {
+        let _guard = self.lock::<A>(slug);
+        match cqrs::bus::CommandBus::new(storage).execute(slug, command, ()) {
+            Ok(Some(event_list)) => Ok(event_list),
+            // every command of an absent link is rejected or creates it
+            Ok(None) => Err(ShortenerError::SlugNotFound),
+            Err(cqrs::bus::CommandBusError::Command(e)) => Err(e),
+            Err(cqrs::bus::CommandBusError::Store(e)) => Err(map_fetch_err_to_shortener_err(e)),
+        }
+    }
+
+    /// Stats of the link with the redirects of its clicks stream, the ones
+    /// in the link stream are of the time before the streams were split. The
+    /// pending redirects are included if configured, except the ones being
+    /// committed at the moment.
+    pub(crate) fn stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+        let mut stats = self.storage
             .fetch(slug.as_ref())
             .map_err(map_fetch_err_to_shortener_err)?
             .snapshot()
-            .into_aggregate())
+            .into_aggregate();
+        match self.clicks.fetch(&stats.link.slug) {
+            Ok(event_list) => stats.redirects += event_list.snapshot().aggregate().redirects,
+            Err(cqrs::store::EventStoreError::AggregateIsNotExist) => {}
+            Err(e) => return Err(map_fetch_err_to_shortener_err(e)),
+        }
+        if let Some(buffer) = self.redirect_buffer.filter(|buffer| buffer.config().pending_in_stats) {
+            stats.redirects += buffer.pending(&stats.link.slug);
+        }
+        Ok(stats)
+    }
+
+    /// Stats of all the links as of the moment, the buffered redirects are
+    /// never included. Positions of the log need the clicks in the same log.
+    pub(crate) fn stats_as_of(&self, as_of: cqrs::history::AsOf) -> Result<Vec<Stats>, cqrs::store::EventStoreError> {
+        let share_log = self.storage.log_id().is_some_and(|log_id| self.clicks.log_id() == Some(log_id));
+        if matches!(as_of, cqrs::history::AsOf::Position(_)) && !share_log {
+            return Err(cqrs::store::EventStoreError::StorageError(
+                "positions need the clicks storage sharing the log of the links".into(),
+            ));
+        }
+        let clicks: std::collections::HashMap<Slug, u64> = cqrs::history::states_as_of(self.clicks, as_of)?
+            .into_iter()
+            .map(|clicks: clicks::Clicks| (clicks.slug, clicks.redirects))
+            .collect();
+        let mut links: Vec<Stats> = cqrs::history::states_as_of(self.storage, as_of)?;
+        for stats in &mut links {
+            stats.redirects += clicks.get(&stats.link.slug).copied().unwrap_or(0);
+        }
+        Ok(links)
+    }
+
+    pub(crate) fn redirect_config(&self, slug: Slug) -> Result<redirect::RedirectConfig, ShortenerError> {
+        let event_list = self.storage
+            .fetch(slug.as_ref())
+            .map_err(map_fetch_err_to_shortener_err)?;
+        Ok(event_list.snapshot().into_aggregate().redirect_config)
+    }
+
+    pub(crate) fn suggest_slugs(&self, slug: Slug, limit: usize) -> Result<Vec<Slug>, ShortenerError> {
+        let mut suggestions: Vec<Slug> = Vec::with_capacity(limit);
+        // random tails may repeat each other or the fixed candidates
+        let mut seen = std::collections::HashSet::new();
+        for candidate in suggest::candidates(slug.as_str(), limit.saturating_mul(2)) {
+            if suggestions.len() >= limit {
+                break
+            }
+            if !seen.insert(candidate.clone()) {
+                continue
+            }
+            let candidate = Slug::from(candidate);
+            let is_exist = self.storage
+                .is_exist(&candidate)
+                .map_err(map_fetch_err_to_shortener_err)?;
+            if !is_exist {
+                suggestions.push(candidate);
+            }
+        }
+        Ok(suggestions)
     }
 }
 
 /////////////////////////////////////////////////////////////////
 
-string_based_type!(Slug exists, SlugRef);
+string_based_type!(Slug exists, SlugRef, normalized);
 string_based_type!(Url exists, UrlRef);
 
 impl cqrs::AggregateIdContract for Slug { type BorrowedAggregateId = SlugRef; }
-impl cqrs::AggregateIdRefContract for SlugRef { type OwnedAggregateId = Slug; }
+impl cqrs::AggregateIdRefContract for SlugRef {
+    type OwnedAggregateId = Slug;
+    fn normalized_key(&self) -> std::borrow::Cow<'_, str> {
+        std::borrow::Cow::Owned(self.skeleton())
+    }
+}
 
 fn map_fetch_err_to_shortener_err(e: cqrs::store::EventStoreError) -> ShortenerError {
     match e {
         cqrs::store::EventStoreError::AggregateIsNotExist => ShortenerError::SlugNotFound,
-        _ => Err(e).unwrap(), // any otther error from .snapshot method is unexpected i.e. panic
+        // the creation lost to another one more times than the bus retries
+        cqrs::store::EventStoreError::AggregateAlreadyExists => ShortenerError::SlugAlreadyInUse,
+        e => ShortenerError::StorageError(e.to_string()),
     }
 }
 
@@ -260,6 +623,7 @@ impl cqrs::Aggregate for Stats {
             ShortenerEvent::Create(slug, url) => {
                 self.link = ShortLink { slug: slug.clone(), url: url.clone() };
                 self.redirects = 0;
+                self.redirect_config = redirect::RedirectConfig::default();
             }
             ShortenerEvent::ShortLinkStatEvent(slug, stat_event) => {
                 if slug.as_str() == self.aggregate_id().as_str() {
@@ -268,18 +632,50 @@ impl cqrs::Aggregate for Stats {
                     }
                 }
             }
+            ShortenerEvent::RedirectConfigured(_, config) => self.redirect_config = config,
         }
     }
 }
 
-/// Events aggregated by SLUG
+/// Commands of the [`Stats`] aggregate, see [`cqrs::bus::CommandBus`].
+/// Redirects are commands of [`clicks::Clicks`].
 #[derive(Clone, Debug)]
+pub enum ShortenerCommand {
+    Create(Slug, Url),
+    ConfigureRedirect(redirect::RedirectConfig),
+}
+
+impl cqrs::Decider for Stats {
+    type Command = ShortenerCommand;
+    type Error = ShortenerError;
+
+    /// Slugs are unique per stream: creation is rejected if the stream exists
+    fn decide(&self, command: ShortenerCommand) -> Result<Vec<ShortenerEvent>, ShortenerError> {
+        use cqrs::Aggregate;
+        let is_exist = !self.aggregate_id().is_empty();
+        let slug = self.link.slug.clone();
+        match command {
+            ShortenerCommand::Create(_, url) if url_parser::Url::parse(url.as_ref()).is_err() => Err(ShortenerError::InvalidUrl),
+            ShortenerCommand::Create(_, _) if is_exist => Err(ShortenerError::SlugAlreadyInUse),
+            ShortenerCommand::Create(slug, url) => Ok(vec![ShortenerEvent::Create(slug, url)]),
+            _ if !is_exist => Err(ShortenerError::SlugNotFound),
+            ShortenerCommand::ConfigureRedirect(config) => Ok(vec![ShortenerEvent::RedirectConfigured(slug, config)]),
+        }
+    }
+}
+
+/// Events aggregated by SLUG
+#[derive(Clone, Debug, PartialEq)]
 pub enum ShortenerEvent {
     Create(Slug, Url),
+    /// Redirects recorded in the link stream before they got their own
+    /// [`clicks::Clicks`] streams, still counted by [`Stats`]
     ShortLinkStatEvent(Slug, ShortLinkStatEvent),
+    /// Replaces the redirect settings of the link, see [`Stats::redirect_config`]
+    RedirectConfigured(Slug, redirect::RedirectConfig),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ShortLinkStatEvent {
     Redirect
 }
@@ -290,6 +686,7 @@ impl cqrs::DomainEvent for ShortenerEvent {
         match self {
             ShortenerEvent::Create(_, _) => "Create",
             ShortenerEvent::ShortLinkStatEvent(_, _) => "ShortLinkStatEvent",
+            ShortenerEvent::RedirectConfigured(_, _) => "RedirectConfigured",
         }
     }
 }
@@ -303,6 +700,53 @@ impl cqrs::DomainEvent for ShortLinkStatEvent {
     }
 }
 
+/// Persisted shape of the events: changes of it must bump
+/// `ShortenerEvent::EVENT_VERSION` and register the upcasters of the old shape
+impl json::ToJson for ShortenerEvent {
+    fn to_json(&self) -> json::Value {
+        use cqrs::DomainEvent;
+        let name = ("name", json::Value::from(self.event_name()));
+        match self {
+            ShortenerEvent::Create(slug, url) => json::Value::object([
+                name,
+                ("slug", json::Value::from(slug.as_str())),
+                ("url", json::Value::from(url.as_str())),
+            ]),
+            ShortenerEvent::ShortLinkStatEvent(slug, stat_event) => json::Value::object([
+                name,
+                ("slug", json::Value::from(slug.as_str())),
+                ("stat", json::Value::from(stat_event.event_name())),
+            ]),
+            ShortenerEvent::RedirectConfigured(slug, config) => json::Value::object([
+                name,
+                ("slug", json::Value::from(slug.as_str())),
+                ("config", config.to_json()),
+            ]),
+        }
+    }
+}
+
+impl json::FromJson for ShortenerEvent {
+    fn from_json(value: &json::Value) -> Result<Self, json::Error> {
+        let slug = Slug::from(value.str_field("slug")?);
+        match value.str_field("name")? {
+            "Create" => Ok(ShortenerEvent::Create(slug, Url::from(value.str_field("url")?))),
+            "ShortLinkStatEvent" => {
+                let stat_event = match value.str_field("stat")? {
+                    "Redirect" => ShortLinkStatEvent::Redirect,
+                    stat => return Err(json::Error::new(format!("unknown stat event `{stat}`"))),
+                };
+                Ok(ShortenerEvent::ShortLinkStatEvent(slug, stat_event))
+            }
+            "RedirectConfigured" => {
+                let config = redirect::RedirectConfig::from_json(value.field("config")?)?;
+                Ok(ShortenerEvent::RedirectConfigured(slug, config))
+            }
+            name => Err(json::Error::new(format!("unknown event `{name}`"))),
+        }
+    }
+}
+
 impl Default for Stats {
     fn default() -> Self {
         Stats {
@@ -311,6 +755,7 @@ impl Default for Stats {
                 url: Url(String::new()),
             },
             redirects: 0,
+            redirect_config: redirect::RedirectConfig::default(),
         }
     }
 }
@@ -324,6 +769,7 @@ impl core::fmt::Display for ShortenerError {
             ShortenerError::InvalidUrl => write!(f, "invalid url"),
             ShortenerError::SlugAlreadyInUse => write!(f, "slug already in use"),
             ShortenerError::SlugNotFound => write!(f, "slug not found"),
+            ShortenerError::StorageError(e) => write!(f, "storage error: {e}"),
         }
     }
 }
@@ -0,0 +1,107 @@
+// Case folding and a confusable "skeleton" for identifiers such as slugs.
+// It is a small hand-written subset of the Unicode confusables data (UTS #39),
+// the full tables are not available without external crates.
+
+/// Lower cases the string (unicode aware).
+pub fn fold_case(s: &str) -> String {
+    s.to_lowercase()
+}
+
+/// Returns the case folded and confusable-normalized form of the string.
+/// Two strings with equal skeletons would look (almost) the same to a human,
+/// e.g. `Promo`/`promo`, `l0go`/`logo` or `logo` written with a cyrillic `о`.
+///
+/// Case folding goes first, so `I` always matches `i` (not `l`). Only single
+/// chars are mapped: sequences such as `rn` for `m` would merge ordinary
+/// words, e.g. `modern` and `modem`.
+pub fn skeleton(s: &str) -> String {
+    fold_case(s).chars().map(prototype).collect()
+}
+
+/// Returns `true` if both strings have the same [`skeleton`].
+pub fn is_confusable(a: &str, b: &str) -> bool {
+    skeleton(a) == skeleton(b)
+}
+
+fn prototype(c: char) -> char {
+    match c {
+        // ascii look-alikes
+        '0' => 'o',
+        '1' | '|' => 'l',
+
+        // fullwidth forms
+        '\u{FF01}'..='\u{FF5E}' => prototype(char::from_u32(c as u32 - 0xFEE0).unwrap_or(c)),
+
+        // dashes
+        '\u{2010}'..='\u{2015}' | '\u{2212}' => '-',
+
+        // cyrillic
+        'а' => 'a',
+        'в' => 'b',
+        'с' => 'c',
+        'ԁ' => 'd',
+        'е' | 'ё' => 'e',
+        'һ' => 'h',
+        'і' | 'ї' => 'i',
+        'ј' => 'j',
+        'к' => 'k',
+        'ӏ' => 'l',
+        'м' => 'm',
+        'н' => 'h',
+        'о' => 'o',
+        'р' => 'p',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'т' => 't',
+        'у' => 'y',
+        'ѵ' => 'v',
+        'ԝ' => 'w',
+        'х' => 'x',
+
+        // greek
+        'α' => 'a',
+        'β' => 'b',
+        'ε' => 'e',
+        'ι' => 'i',
+        'κ' => 'k',
+        'ν' => 'v',
+        'ο' => 'o',
+        'ρ' => 'p',
+        'τ' => 't',
+        'υ' => 'u',
+        'χ' => 'x',
+
+        // latin extensions
+        'ı' => 'i',
+        'ɡ' => 'g',
+        'ɑ' => 'a',
+
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_skeleton() {
+        assert_eq!(skeleton("Promo"), "promo");
+        assert_eq!(skeleton("l0go"), "logo");
+        assert_eq!(skeleton("l\u{043E}go"), "logo");
+        assert_eq!(skeleton("ＬＯＧＯ"), "logo");
+        assert_eq!(skeleton("modern"), "modern");
+        assert_eq!(skeleton("a\u{2013}b"), "a-b");
+    }
+
+    #[test]
+    fn test_is_confusable() {
+        assert!(is_confusable("Promo", "promo"));
+        assert!(is_confusable("l0go", "lоgо"));
+        assert!(is_confusable("Ivan", "ivan"));
+        assert!(!is_confusable("promo", "promo2"));
+        assert!(!is_confusable("Ivan", "lvan"));
+        assert!(!is_confusable("modern", "modem"));
+        assert!(!is_confusable("savvy", "sawy"));
+    }
+}
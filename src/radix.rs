@@ -0,0 +1,257 @@
+// Encoding of binary data as a big number in an arbitrary base (radix).
+// Unlike base64 the encoded length is not a multiple of the data length, so
+// the result is left-padded with "zero" symbols up to a fixed width: the same
+// number of bytes always gives the same number of symbols.
+
+use crate::base64;
+
+pub trait Radix {
+    /// Symbols by their value, ascii only
+    const ALPHABET: &'static [u8];
+
+    fn get_index_for_char(character: char) -> Option<u8> {
+        Self::ALPHABET
+            .iter()
+            .position(|&symbol| character.is_ascii() && symbol == character as u8)
+            .map(|index| index as u8)
+    }
+
+    /// Symbols which are skipped on decoding
+    fn is_separator(_character: char) -> bool {
+        false
+    }
+}
+
+/// `0-9A-Za-z`, no symbols which need escaping or look like separators
+pub struct Base62;
+
+/// Bitcoin alphabet, without `0OIl` which are easy to confuse with each other
+pub struct Base58;
+
+/// Douglas Crockford's base32: upper case on encoding, case insensitive and
+/// typo-tolerant on decoding (`O` is read as `0`, `I` and `L` as `1`), `-` is
+/// ignored
+pub struct Crockford32;
+
+impl Radix for Base62 {
+    const ALPHABET: &'static [u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+}
+
+impl Radix for Base58 {
+    const ALPHABET: &'static [u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+}
+
+impl Radix for Crockford32 {
+    const ALPHABET: &'static [u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+    fn get_index_for_char(character: char) -> Option<u8> {
+        match character.to_ascii_uppercase() {
+            'O' => Some(0),
+            'I' | 'L' => Some(1),
+            'U' => None,
+            c => Self::ALPHABET
+                .iter()
+                .position(|&symbol| c.is_ascii() && symbol == c as u8)
+                .map(|index| index as u8),
+        }
+    }
+
+    fn is_separator(character: char) -> bool {
+        character == '-'
+    }
+}
+
+/// Errors of the radix decoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The symbol at the position (in chars) is not a part of the alphabet
+    InvalidSymbol { position: usize, symbol: char },
+    /// No data length is encoded with this number of symbols
+    InvalidLength,
+    /// The encoded number doesn't fit into the data length
+    Overflow,
+    /// Error of the [`Encoding::Base64Url`] decoding
+    Base64(base64::DecodeError),
+}
+
+/// Length of the encoded data in symbols
+pub fn encoded_len<R: Radix + ?Sized>(data_len: usize) -> usize {
+    let bits_per_symbol = (R::ALPHABET.len() as f64).log2();
+    ((data_len * 8) as f64 / bits_per_symbol).ceil() as usize
+}
+
+pub fn encode<R: Radix + ?Sized>(data: &[u8]) -> String {
+    let base = R::ALPHABET.len() as u32;
+    // little-endian digits of the number
+    let mut digits = vec![0u8; encoded_len::<R>(data.len())];
+    for &byte in data {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % base) as u8;
+            carry /= base;
+        }
+        debug_assert_eq!(carry, 0, "encoded_len is too short");
+    }
+    digits.iter().rev().map(|&digit| R::ALPHABET[digit as usize] as char).collect()
+}
+
+pub fn decode<R: Radix + ?Sized>(data: &str) -> Result<Vec<u8>, DecodeError> {
+    let base = R::ALPHABET.len() as u32;
+    let mut digits = Vec::with_capacity(data.len());
+    for (position, symbol) in data.chars().enumerate() {
+        if R::is_separator(symbol) {
+            continue;
+        }
+        match R::get_index_for_char(symbol) {
+            Some(digit) => digits.push(digit),
+            None => return Err(DecodeError::InvalidSymbol { position, symbol }),
+        }
+    }
+
+    let data_len = (0..=digits.len())
+        .find(|&len| encoded_len::<R>(len) == digits.len())
+        .ok_or(DecodeError::InvalidLength)?;
+
+    // little-endian bytes of the number
+    let mut bytes = vec![0u8; data_len];
+    for digit in digits {
+        let mut carry = digit as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * base;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        if carry != 0 {
+            return Err(DecodeError::Overflow);
+        }
+    }
+    bytes.reverse();
+    Ok(bytes)
+}
+
+/// Encodings a slug could be generated in
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// url-safe base64 without padding
+    #[default]
+    Base64Url,
+    Base62,
+    Base58,
+    Crockford32,
+}
+
+#[allow(dead_code)]
+impl Encoding {
+    pub fn encode(self, data: &[u8]) -> String {
+        match self {
+            Self::Base64Url => base64::Url::encode_unpadded(data),
+            Self::Base62 => encode::<Base62>(data),
+            Self::Base58 => encode::<Base58>(data),
+            Self::Crockford32 => encode::<Crockford32>(data),
+        }
+    }
+
+    pub fn decode(self, data: &str) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            Self::Base64Url => base64::Url::decode(data).map_err(DecodeError::Base64),
+            Self::Base62 => decode::<Base62>(data),
+            Self::Base58 => decode::<Base58>(data),
+            Self::Crockford32 => decode::<Crockford32>(data),
+        }
+    }
+
+    pub fn encoded_len(self, data_len: usize) -> usize {
+        match self {
+            Self::Base64Url => base64::encoded_len(data_len, base64::Padding::Unpadded),
+            Self::Base62 => encoded_len::<Base62>(data_len),
+            Self::Base58 => encoded_len::<Base58>(data_len),
+            Self::Crockford32 => encoded_len::<Crockford32>(data_len),
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSymbol { position, symbol } => write!(f, "invalid symbol {symbol:?} at position {position}"),
+            Self::InvalidLength => write!(f, "invalid length"),
+            Self::Overflow => write!(f, "encoded number is too big"),
+            Self::Base64(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ENCODINGS: [Encoding; 4] = [Encoding::Base64Url, Encoding::Base62, Encoding::Base58, Encoding::Crockford32];
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode::<Base62>(&[0, 0, 0, 0, 0, 61]), "00000000z");
+        assert_eq!(encode::<Base62>(&[0, 0, 0, 0, 0, 62]), "000000010");
+        assert_eq!(encode::<Base58>(&[0x00, 0x01]), "112");
+        assert_eq!(encode::<Base58>(&[0xff; 6]), "3CUsUpv9t");
+        assert_eq!(encode::<Crockford32>(&[0xff; 5]), "ZZZZZZZZ");
+        assert_eq!(encode::<Crockford32>(&[0x00, 0x00, 0x00, 0x01, 0x00]), "00000080");
+        assert_eq!(encode::<Base62>(&[]), "");
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        assert_eq!(encoded_len::<Base62>(6), 9);
+        assert_eq!(encoded_len::<Base58>(6), 9);
+        assert_eq!(encoded_len::<Crockford32>(6), 10);
+        for encoding in ENCODINGS {
+            assert_eq!(encoding.encode(&[0; 6]).len(), encoding.encoded_len(6));
+            assert_eq!(encoding.encode(&[0xff; 6]).len(), encoding.encoded_len(6));
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let data = (0..=255).rev().collect::<Vec<u8>>();
+        for encoding in ENCODINGS {
+            for len in 0..24 {
+                for offset in [0, 100, 232] {
+                    let data = &data[offset..offset + len];
+                    assert_eq!(encoding.decode(&encoding.encode(data)).unwrap(), data, "{encoding:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_base58_alphabet() {
+        let encoded = encode::<Base58>(&(0..=255).collect::<Vec<u8>>());
+        assert!(!encoded.contains(['0', 'O', 'I', 'l']));
+        assert_eq!(decode::<Base58>("0"), Err(DecodeError::InvalidSymbol { position: 0, symbol: '0' }));
+    }
+
+    #[test]
+    fn test_crockford_typos() {
+        let data = [0x12, 0x34, 0x56, 0x78, 0x9a];
+        let encoded = encode::<Crockford32>(&data);
+        assert_eq!(encoded, "28T5CY4T");
+        assert_eq!(decode::<Crockford32>("28t5cy4t").unwrap(), data);
+        assert_eq!(decode::<Crockford32>("28T5-CY4T").unwrap(), data);
+        assert_eq!(decode::<Crockford32>("0o1IiLl0").unwrap(), decode::<Crockford32>("00111110").unwrap());
+        assert_eq!(decode::<Crockford32>("28T5CY4U"), Err(DecodeError::InvalidSymbol { position: 7, symbol: 'U' }));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(decode::<Base62>("0000"), Err(DecodeError::InvalidLength));
+        assert_eq!(decode::<Base62>("zzzzzzzzz"), Err(DecodeError::Overflow));
+        assert_eq!(decode::<Crockford32>("ZZZZZZZZZZ"), Err(DecodeError::Overflow));
+        assert_eq!(decode::<Base62>("0000-0000"), Err(DecodeError::InvalidSymbol { position: 4, symbol: '-' }));
+        assert_eq!(
+            Encoding::Base64Url.decode("AAAAA"),
+            Err(DecodeError::Base64(base64::DecodeError::InvalidLength)),
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Per-link redirect settings. They are stored as
+//! [`RedirectConfigured`](crate::ShortenerEvent::RedirectConfigured) events
+//! of the link and folded into [`Stats::redirect_config`](crate::Stats::redirect_config),
+//! the last event wins.
+
+use crate::json::{self, FromJson, ToJson, Value};
+use crate::ShortLink;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedirectKind {
+    Permanent,
+    #[default]
+    Temporary,
+}
+
+/// How clients are redirected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RedirectType {
+    pub kind: RedirectKind,
+    /// Whether the client must repeat the request with the same method and
+    /// body (307/308) or is allowed to switch to `GET` (301/302)
+    pub preserve_method: bool,
+}
+
+impl RedirectType {
+    pub fn status_code(self) -> u16 {
+        match (self.kind, self.preserve_method) {
+            (RedirectKind::Permanent, false) => 301,
+            (RedirectKind::Temporary, false) => 302,
+            (RedirectKind::Temporary, true) => 307,
+            (RedirectKind::Permanent, true) => 308,
+        }
+    }
+}
+
+/// Caching hint of the redirect responses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheControl {
+    NoStore,
+    Private { max_age: u64 },
+    Public { max_age: u64 },
+}
+
+impl CacheControl {
+    /// Value of the `Cache-Control` header
+    pub fn header_value(self) -> String {
+        match self {
+            CacheControl::NoStore => "no-store".into(),
+            CacheControl::Private { max_age } => format!("private, max-age={max_age}"),
+            CacheControl::Public { max_age } => format!("public, max-age={max_age}"),
+        }
+    }
+}
+
+/// Redirect settings of a link, absent ones are up to the frontend
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RedirectConfig {
+    pub redirect_type: Option<RedirectType>,
+    pub cache_control: Option<CacheControl>,
+    /// Show an interstitial page with the target url instead of redirecting
+    pub preview: bool,
+}
+
+/// Result of a redirect: the link and how to redirect to it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirect {
+    pub link: ShortLink,
+    pub config: RedirectConfig,
+}
+
+impl ToJson for RedirectConfig {
+    fn to_json(&self) -> Value {
+        let redirect_type = self.redirect_type.map(|redirect_type| Value::object([
+            ("permanent", Value::from(redirect_type.kind == RedirectKind::Permanent)),
+            ("preserve_method", Value::from(redirect_type.preserve_method)),
+        ]));
+        let cache_control = self.cache_control.map(|cache_control| match cache_control {
+            CacheControl::NoStore => Value::object([("mode", Value::from("no-store"))]),
+            CacheControl::Private { max_age } => Value::object([
+                ("mode", Value::from("private")),
+                ("max_age", Value::from(max_age)),
+            ]),
+            CacheControl::Public { max_age } => Value::object([
+                ("mode", Value::from("public")),
+                ("max_age", Value::from(max_age)),
+            ]),
+        });
+        Value::object([
+            ("redirect_type", Value::from(redirect_type)),
+            ("cache_control", Value::from(cache_control)),
+            ("preview", Value::from(self.preview)),
+        ])
+    }
+}
+
+impl FromJson for RedirectConfig {
+    fn from_json(value: &Value) -> Result<Self, json::Error> {
+        let redirect_type = match value.opt_field("redirect_type") {
+            Some(redirect_type) => Some(RedirectType {
+                kind: match redirect_type.bool_field("permanent")? {
+                    true => RedirectKind::Permanent,
+                    false => RedirectKind::Temporary,
+                },
+                preserve_method: redirect_type.bool_field("preserve_method")?,
+            }),
+            None => None,
+        };
+        let cache_control = match value.opt_field("cache_control") {
+            Some(cache_control) => Some(match cache_control.str_field("mode")? {
+                "no-store" => CacheControl::NoStore,
+                "private" => CacheControl::Private { max_age: cache_control.u64_field("max_age")? },
+                "public" => CacheControl::Public { max_age: cache_control.u64_field("max_age")? },
+                mode => return Err(json::Error::new(format!("unknown cache control mode `{mode}`"))),
+            }),
+            None => None,
+        };
+        let preview = match value.opt_field("preview") {
+            Some(_) => value.bool_field("preview")?,
+            None => false,
+        };
+        Ok(RedirectConfig { redirect_type, cache_control, preview })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_codes() {
+        let status = |kind, preserve_method| RedirectType { kind, preserve_method }.status_code();
+        assert_eq!(status(RedirectKind::Permanent, false), 301);
+        assert_eq!(status(RedirectKind::Temporary, false), 302);
+        assert_eq!(status(RedirectKind::Temporary, true), 307);
+        assert_eq!(status(RedirectKind::Permanent, true), 308);
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let configs = [
+            RedirectConfig::default(),
+            RedirectConfig {
+                redirect_type: Some(RedirectType { kind: RedirectKind::Permanent, preserve_method: true }),
+                cache_control: Some(CacheControl::Public { max_age: 3600 }),
+                preview: true,
+            },
+            RedirectConfig { cache_control: Some(CacheControl::NoStore), ..Default::default() },
+        ];
+        for config in configs {
+            let text = config.to_json().to_string();
+            assert_eq!(RedirectConfig::from_json(&json::parse(&text).unwrap()).unwrap(), config, "{text}");
+        }
+        assert_eq!(RedirectConfig::from_json(&json::parse("{}").unwrap()).unwrap(), RedirectConfig::default());
+        assert!(RedirectConfig::from_json(&json::parse(r#"{"cache_control":{"mode":"private"}}"#).unwrap()).is_err());
+    }
+}
@@ -20,6 +20,7 @@ macro_rules! string_based_type {
             pub fn len(&self) -> usize { self.0.len() }
             pub fn is_empty(&self) -> bool { self.0.is_empty() }
             pub fn as_str(&self) -> &str { &self.0 }
+            #[allow(clippy::should_implement_trait)]
             pub fn borrow(&self) -> &$ref_type { $ref_type::from_str(self.0.as_str()) }
         }
         impl From<String> for $owned_type {
@@ -32,11 +33,11 @@ macro_rules! string_based_type {
                 s.0
             }
         }
-        impl ToString for $owned_type {
-            fn to_string(&self) -> String { self.0.to_string() }
+        impl core::fmt::Display for $owned_type {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { f.write_str(&self.0) }
         }
-        impl ToString for $ref_type {
-            fn to_string(&self) -> String { self.0.to_string() }
+        impl core::fmt::Display for $ref_type {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { f.write_str(&self.0) }
         }
         impl core::convert::AsRef<str> for $ref_type {
             fn as_ref(&self) -> &str { &self.0 }
@@ -83,6 +84,18 @@ macro_rules! string_based_type {
         }
     };
 
+    (@normalized, $owned_type:ident, $ref_type:ident) => {
+        impl $ref_type {
+            /// Case folded and confusable-normalized form of the value. Two values
+            /// with the same skeleton are considered the same for uniqueness checks,
+            /// while the value itself keeps the original casing for display.
+            pub fn skeleton(&self) -> String { $crate::normalize::skeleton(&self.0) }
+            pub fn is_confusable_with<S: core::convert::AsRef<str> + ?Sized>(&self, other: &S) -> bool {
+                $crate::normalize::is_confusable(&self.0, other.as_ref())
+            }
+        }
+    };
+
     ($owned_type:ident, $ref_type:ident) => {
         #[derive(Clone, Debug, PartialEq, Eq, Hash)]
         pub struct $owned_type(String);
@@ -96,4 +109,9 @@ macro_rules! string_based_type {
         pub struct $ref_type(str);
         string_based_type!(@inner, $owned_type, $ref_type);
     };
+
+    ($owned_type:ident exists, $ref_type:ident, normalized) => {
+        string_based_type!($owned_type exists, $ref_type);
+        string_based_type!(@normalized, $owned_type, $ref_type);
+    };
 }
@@ -0,0 +1,98 @@
+// Near-variants of a desired slug, offered when the slug is already taken.
+// Candidates are produced in the order of their rank: the closer a candidate
+// to the desired slug, the earlier it goes.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Words used to build the hyphenated variants, e.g. `promo-go` or `get-promo`
+const SUFFIX_WORDS: [&str; 6] = ["go", "link", "now", "app", "info", "hq"];
+const PREFIX_WORDS: [&str; 3] = ["get", "my", "the"];
+
+const TAIL_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789"; // without confusable 0/o and 1/l
+const TAIL_LEN: usize = 3;
+
+/// Returns ranked candidates for the `desired` slug:
+/// 1. number suffixes: `promo2`, ..., `promo9`
+/// 2. hyphenated number suffixes: `promo-2`, ..., `promo-9`
+/// 3. hyphenated words: `promo-go`, ..., `get-promo`, ...
+/// 4. `random_tails` short random tails: `promo-x7k`, ...
+///
+/// The desired slug itself is never returned.
+pub fn candidates(desired: &str, random_tails: usize) -> impl Iterator<Item = String> + '_ {
+    let stem = desired.trim_end_matches('-');
+    let numbered = (2..=9).map(move |n| format!("{stem}{n}"));
+    let hyphen_numbered = (2..=9).map(move |n| join(stem, &n.to_string()));
+    let suffixed = SUFFIX_WORDS.iter().map(move |word| join(stem, word));
+    let prefixed = PREFIX_WORDS.iter().map(move |word| join(word, stem));
+    let mut rng = XorShift::seeded(desired);
+    let tails = (0..random_tails).map(move |_| join(stem, &rng.tail()));
+
+    numbered
+        .chain(hyphen_numbered)
+        .chain(suffixed)
+        .chain(prefixed)
+        .chain(tails)
+        .filter(move |candidate| candidate != desired)
+}
+
+fn join(head: &str, tail: &str) -> String {
+    match (head.is_empty(), tail.is_empty()) {
+        (true, _) => tail.to_owned(),
+        (_, true) => head.to_owned(),
+        _ => format!("{}-{}", head.trim_end_matches('-'), tail.trim_start_matches('-')),
+    }
+}
+
+// pseudo-random without using 'rand' crate
+struct XorShift(u64);
+
+impl XorShift {
+    fn seeded(salt: &str) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let seed = salt
+            .bytes()
+            .fold(nanos, |acc, b| acc.rotate_left(5) ^ b as u64);
+        // xorshift must not be seeded with zero
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn tail(&mut self) -> String {
+        (0..TAIL_LEN)
+            .map(|_| TAIL_ALPHABET[(self.next() % TAIL_ALPHABET.len() as u64) as usize] as char)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_candidates_ranking() {
+        let candidates = candidates("promo", 2).collect::<Vec<_>>();
+        assert_eq!(&candidates[..3], ["promo2", "promo3", "promo4"]);
+        assert!(candidates.iter().position(|c| c == "promo-2") < candidates.iter().position(|c| c == "promo-go"));
+        assert!(candidates.iter().position(|c| c == "promo-go") < candidates.iter().position(|c| c == "get-promo"));
+        assert_eq!(candidates.len(), 8 + 8 + SUFFIX_WORDS.len() + PREFIX_WORDS.len() + 2);
+
+        let tail = candidates.last().unwrap();
+        assert!(tail.starts_with("promo-"));
+        assert_eq!(tail.len(), "promo-".len() + TAIL_LEN);
+    }
+
+    #[test]
+    fn test_candidates_hyphens() {
+        assert!(candidates("promo-", 0).all(|c| !c.contains("--") && c != "promo-"));
+        assert!(candidates("", 1).all(|c| !c.starts_with('-') && !c.is_empty()));
+    }
+}
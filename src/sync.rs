@@ -0,0 +1,160 @@
+//! Service which may be shared between threads, e.g. in `Arc`
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::commands::{CommandHandler, RedirectCommandHandler, SyncCommandHandler};
+use crate::clicks::{Clicks, RedirectBuffer, RedirectBuffering};
+use crate::cqrs::store::{EventStore, EventStoreError};
+use crate::gen::SlugGenerator;
+use crate::cqrs::history::AsOf;
+use crate::queries::{HistoryQueryHandler, QueryHandler, RedirectConfigQueryHandler, SlugSuggestionHandler};
+use crate::redirect::{Redirect, RedirectConfig};
+use crate::{Shortener, ShortLink, ShortenerError, Slug, SlugRef, Stats, Url};
+
+const LOCK_STRIPES: usize = 64;
+
+/// Same as [`UrlShortenerService`](crate::UrlShortenerService), but `Send +
+/// Sync` with `&self` commands. Commands of the same slug are serialized, the
+/// other ones run in parallel as far as the storage allows.
+pub struct SyncUrlShortenerService {
+    storage: Box<dyn EventStore<Stats> + Send + Sync>,
+    clicks: Box<dyn EventStore<Clicks> + Send + Sync>,
+    slug_generator: Box<dyn SlugGenerator + Send + Sync>,
+    locks: SlugLocks,
+    redirect_buffer: Option<RedirectBuffer>,
+}
+
+impl SyncUrlShortenerService {
+    /// See [`UrlShortenerService::new`](crate::UrlShortenerService::new)
+    pub fn new(
+        storage: Box<dyn EventStore<Stats> + Send + Sync>,
+        clicks: Box<dyn EventStore<Clicks> + Send + Sync>,
+        generator: Box<dyn SlugGenerator + Send + Sync>,
+    ) -> Self {
+        Self {
+            storage,
+            clicks,
+            slug_generator: generator,
+            locks: SlugLocks::new(LOCK_STRIPES),
+            redirect_buffer: None,
+        }
+    }
+
+    /// See [`UrlShortenerService::with_redirect_buffering`](crate::UrlShortenerService::with_redirect_buffering)
+    pub fn with_redirect_buffering(mut self, config: RedirectBuffering) -> Self {
+        self.redirect_buffer = Some(RedirectBuffer::new(config));
+        self
+    }
+
+    /// Commits all the buffered redirects
+    pub fn flush_redirects(&self) -> Result<(), EventStoreError> {
+        self.shortener().flush_redirects(false)
+    }
+
+    /// Commits the buffered redirects which have waited the `max_delay`
+    pub fn flush_due_redirects(&self) -> Result<(), EventStoreError> {
+        self.shortener().flush_redirects(true)
+    }
+
+    fn shortener(&self) -> Shortener<'_> {
+        Shortener {
+            storage: &*self.storage,
+            clicks: &*self.clicks,
+            slug_generator: &*self.slug_generator,
+            locks: Some(&self.locks),
+            redirect_buffer: self.redirect_buffer.as_ref(),
+        }
+    }
+}
+
+/// Buffered redirects are committed on drop, errors are ignored
+impl Drop for SyncUrlShortenerService {
+    fn drop(&mut self) {
+        let _ = self.flush_redirects();
+    }
+}
+
+/// Striped locks of the streams keyed by slugs. Slugs are hashed by their
+/// skeleton, so the slugs of the same stream share the lock whatever the key
+/// mode of the storage is. Streams of different aggregate types have separate
+/// locks, e.g. redirects don't wait for the changes of the link.
+pub(crate) struct SlugLocks(Vec<Mutex<()>>);
+
+impl SlugLocks {
+    fn new(stripes: usize) -> Self {
+        Self((0..stripes.max(1)).map(|_| Mutex::new(())).collect())
+    }
+
+    pub(crate) fn lock(&self, aggregate_type: &str, slug: &SlugRef) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        aggregate_type.hash(&mut hasher);
+        slug.skeleton().hash(&mut hasher);
+        let stripe = &self.0[(hasher.finish() % self.0.len() as u64) as usize];
+        // the lock guards no data, so a panic while holding it can't leave anything broken
+        stripe.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SyncCommandHandler for SyncUrlShortenerService {
+    fn handle_create_short_link(&self, url: Url, slug: Option<Slug>) -> Result<ShortLink, ShortenerError> {
+        self.shortener().create_short_link(url, slug)
+    }
+
+    fn handle_redirect(&self, slug: Slug) -> Result<ShortLink, ShortenerError> {
+        self.shortener().redirect(slug)
+    }
+
+    fn handle_configure_redirect(&self, slug: Slug, config: RedirectConfig) -> Result<(), ShortenerError> {
+        self.shortener().configure_redirect(slug, config)
+    }
+
+    fn handle_redirect_with_config(&self, slug: Slug) -> Result<Redirect, ShortenerError> {
+        self.shortener().redirect_with_config(slug)
+    }
+}
+
+impl CommandHandler for SyncUrlShortenerService {
+    fn handle_create_short_link(&mut self, url: Url, slug: Option<Slug>) -> Result<ShortLink, ShortenerError> {
+        SyncCommandHandler::handle_create_short_link(self, url, slug)
+    }
+
+    fn handle_redirect(&mut self, slug: Slug) -> Result<ShortLink, ShortenerError> {
+        SyncCommandHandler::handle_redirect(self, slug)
+    }
+}
+
+impl RedirectCommandHandler for SyncUrlShortenerService {
+    fn handle_configure_redirect(&mut self, slug: Slug, config: RedirectConfig) -> Result<(), ShortenerError> {
+        SyncCommandHandler::handle_configure_redirect(self, slug, config)
+    }
+
+    fn handle_redirect_with_config(&mut self, slug: Slug) -> Result<Redirect, ShortenerError> {
+        SyncCommandHandler::handle_redirect_with_config(self, slug)
+    }
+}
+
+impl QueryHandler for SyncUrlShortenerService {
+    fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+        self.shortener().stats(slug)
+    }
+}
+
+impl RedirectConfigQueryHandler for SyncUrlShortenerService {
+    fn get_redirect_config(&self, slug: Slug) -> Result<RedirectConfig, ShortenerError> {
+        self.shortener().redirect_config(slug)
+    }
+}
+
+impl HistoryQueryHandler for SyncUrlShortenerService {
+    fn get_stats_as_of(&self, as_of: AsOf) -> Result<Vec<Stats>, EventStoreError> {
+        self.shortener().stats_as_of(as_of)
+    }
+}
+
+impl SlugSuggestionHandler for SyncUrlShortenerService {
+    fn suggest_slugs(&self, slug: Slug, limit: usize) -> Result<Vec<Slug>, ShortenerError> {
+        self.shortener().suggest_slugs(slug, limit)
+    }
+}
@@ -1,16 +1,20 @@
 #![cfg(test)]
 
-use crate::{commands::CommandHandler, cqrs::mem_store, gen, queries::QueryHandler, ShortenerError, UrlRef, UrlShortenerService};
+use crate::{commands::{self, CommandHandler, RedirectCommandHandler}, cqrs::mem_store, gen, queries::{QueryHandler, RedirectConfigQueryHandler, SlugSuggestionHandler}, redirect, sync, ShortenerError, Slug, UrlRef, UrlShortenerService};
 
 
 fn create_service() -> UrlShortenerService {
+    create_service_with_clicks(Box::new(mem_store::MemEventStore::<crate::clicks::Clicks>::new()))
+}
+
+fn create_service_with_clicks(clicks: Box<dyn crate::cqrs::store::EventStore<crate::clicks::Clicks>>) -> UrlShortenerService {
     let storage = Box::new(mem_store::MemEventStore::<super::Stats>::new());
     let shortener = Box::new(gen::SimplestSlugGenerator);
-    UrlShortenerService::new(storage, shortener)
+    UrlShortenerService::new(storage, clicks, shortener)
 }
 
-const INVALID_URL: &'static UrlRef = UrlRef::from_str("http://[:::1]");
-const VALID_URL: &'static UrlRef = UrlRef::from_str("https://github.com/rust-lang/rust/issues?labels=E-easy&state=open");
+const INVALID_URL: &UrlRef = UrlRef::from_str("http://[:::1]");
+const VALID_URL: &UrlRef = UrlRef::from_str("https://github.com/rust-lang/rust/issues?labels=E-easy&state=open");
 
 macro_rules! test_url {
     ($x:ident) => { format!("https://github.com/rust-lang/rust/issues?labels=E-easy&state=open&x={}", $x) };
@@ -43,7 +47,7 @@ fn service_handle_redirect() {
     assert_eq!(link.url.borrow(), VALID_URL);
     assert_eq!(link.slug.len(), 8);
 
-    for _ in [0..10] {
+    for _ in 0..10 {
         service.handle_redirect(link.slug.clone()).unwrap();
     }
 }
@@ -72,3 +76,567 @@ fn service_handle_many_links() {
         assert_eq!(stats.redirects, REDIRECTS);
     }
 }
+
+fn create_normalized_service() -> UrlShortenerService {
+    let storage = Box::new(mem_store::MemEventStore::<super::Stats>::with_key_mode(mem_store::KeyMode::Normalized));
+    let clicks = Box::new(mem_store::MemEventStore::<crate::clicks::Clicks>::new());
+    let shortener = Box::new(gen::SimplestSlugGenerator);
+    UrlShortenerService::new(storage, clicks, shortener)
+}
+
+#[test]
+fn service_normalized_slug_uniqueness() {
+    let mut service = create_normalized_service();
+    let link = service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("Promo"))).unwrap();
+    assert_eq!(link.slug.as_str(), "Promo");
+
+    for taken in ["promo", "PROMO", "Prоmо", "pr0mo"] {
+        let result = service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from(taken)));
+        assert_eq!(result, Err(ShortenerError::SlugAlreadyInUse), "slug {taken:?} is not detected as taken");
+    }
+
+    // the original casing is preserved for display
+    service.handle_redirect(Slug::from("promo")).unwrap();
+    let stats = service.get_stats(Slug::from("PROMO")).unwrap();
+    assert_eq!(stats.link.slug.as_str(), "Promo");
+    assert_eq!(stats.redirects, 1);
+}
+
+#[test]
+fn service_exact_slug_uniqueness() {
+    let mut service = create_service();
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("Promo"))).unwrap();
+    let link = service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("promo"))).unwrap();
+    assert_eq!(link.slug.as_str(), "promo");
+}
+
+#[test]
+fn service_suggest_slugs() {
+    let mut service = create_service();
+    for slug in ["promo", "promo2", "promo-go"] {
+        service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from(slug))).unwrap();
+    }
+
+    let suggestions = service.suggest_slugs(Slug::from("promo"), 20).unwrap();
+    assert_eq!(suggestions.len(), 20);
+    assert_eq!(suggestions[0].as_str(), "promo3");
+    assert!(!suggestions.iter().any(|s| s.as_str() == "promo2" || s.as_str() == "promo-go"));
+
+    // every suggestion is accepted as a custom slug
+    for slug in suggestions {
+        service.handle_create_short_link(VALID_URL.to_owned(), Some(slug)).unwrap();
+    }
+}
+
+#[test]
+fn service_suggest_slugs_unique() {
+    let mut service = create_service();
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("promo"))).unwrap();
+    // mostly random tails, which repeat in a few hundreds of them
+    let suggestions = service.suggest_slugs(Slug::from("promo"), 300).unwrap();
+    let unique: std::collections::HashSet<&str> = suggestions.iter().map(|s| s.as_str()).collect();
+    assert_eq!(unique.len(), suggestions.len());
+}
+
+#[test]
+fn service_suggest_slugs_normalized() {
+    let mut service = create_normalized_service();
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("Promo2"))).unwrap();
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("PROMO4"))).unwrap();
+    let suggestions = service.suggest_slugs(Slug::from("promo"), 2).unwrap();
+    assert_eq!(suggestions, [Slug::from("promo3"), Slug::from("promo5")]);
+}
+
+#[test]
+fn service_redirect_config() {
+    let mut service = create_service();
+    let link = service.handle_create_short_link(VALID_URL.to_owned(), None).unwrap();
+    assert_eq!(service.get_redirect_config(link.slug.clone()).unwrap(), redirect::RedirectConfig::default());
+
+    let config = redirect::RedirectConfig {
+        redirect_type: Some(redirect::RedirectType { kind: redirect::RedirectKind::Permanent, preserve_method: false }),
+        cache_control: Some(redirect::CacheControl::Private { max_age: 600 }),
+        preview: false,
+    };
+    service.handle_configure_redirect(link.slug.clone(), config).unwrap();
+    let redirect = service.handle_redirect_with_config(link.slug.clone()).unwrap();
+    assert_eq!(redirect, redirect::Redirect { link: link.clone(), config });
+    service.handle_redirect(link.slug.clone()).unwrap();
+
+    // configuring doesn't change the stats
+    let stats = service.get_stats(link.slug.clone()).unwrap();
+    assert_eq!((stats.link, stats.redirects), (link, 2));
+
+    assert_eq!(service.handle_configure_redirect(Slug::from("missing"), config), Err(ShortenerError::SlugNotFound));
+    assert_eq!(service.handle_redirect_with_config(Slug::from("missing")), Err(ShortenerError::SlugNotFound));
+}
+
+#[test]
+fn service_redirects_dont_touch_link_stream() {
+    use crate::cqrs::store::EventStore;
+    use std::sync::Arc;
+
+    let links = Arc::new(mem_store::MemEventStore::<crate::Stats>::with_key_mode(mem_store::KeyMode::Normalized));
+    let clicks = Arc::new(mem_store::MemEventStore::<crate::clicks::Clicks>::new());
+    let mut service = UrlShortenerService::new(Box::new(Arc::clone(&links)), Box::new(Arc::clone(&clicks)), Box::new(gen::SimplestSlugGenerator));
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("Promo"))).unwrap();
+
+    for slug in ["Promo", "promo", "PROMO"] {
+        service.handle_redirect(Slug::from(slug)).unwrap();
+    }
+    assert_eq!(links.fetch(&Slug::from("Promo")).unwrap().len(), 1);
+    // the clicks are keyed by the slug of the link as it was created
+    assert_eq!(clicks.fetch(&Slug::from("Promo")).unwrap().snapshot().aggregate().redirects, 3);
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, 3);
+    assert_eq!(service.handle_redirect(Slug::from("absent")), Err(ShortenerError::SlugNotFound));
+}
+
+#[test]
+fn service_storage_errors() {
+    use crate::cqrs::store::{EventStore, EventStoreError, StoredEventList};
+    use crate::{SlugRef, Stats};
+
+    /// Storage whose disk is gone
+    struct BrokenStore;
+
+    fn broken<T>() -> Result<T, EventStoreError> {
+        Err(EventStoreError::StorageError(Box::new(std::io::Error::other("disk is gone"))))
+    }
+
+    impl EventStore<Stats> for BrokenStore {
+        fn fetch(&self, _: &SlugRef) -> Result<StoredEventList<Stats>, EventStoreError> { broken() }
+        fn is_exist(&self, _: &SlugRef) -> Result<bool, EventStoreError> { broken() }
+        fn commit(&self, _: StoredEventList<Stats>) -> Result<(), EventStoreError> { broken() }
+        fn remove(&self, _: &SlugRef) -> Result<StoredEventList<Stats>, EventStoreError> { broken() }
+    }
+
+    let mut service = UrlShortenerService::new(Box::new(BrokenStore), Box::new(mem_store::MemEventStore::new()), Box::new(gen::SimplestSlugGenerator));
+    let error = ShortenerError::StorageError("event storage error: disk is gone".into());
+    assert_eq!(service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("promo"))), Err(error));
+    assert!(matches!(service.handle_redirect(Slug::from("promo")), Err(ShortenerError::StorageError(_))));
+    assert!(matches!(service.get_stats(Slug::from("promo")), Err(ShortenerError::StorageError(_))));
+}
+
+#[test]
+fn service_stats_include_legacy_redirects() {
+    use crate::cqrs::store::{EventStore, StoredEventList};
+    use crate::{ShortLinkStatEvent, ShortenerEvent, Url};
+
+    let links = mem_store::MemEventStore::<crate::Stats>::new();
+    let legacy = ShortenerEvent::ShortLinkStatEvent(Slug::from("promo"), ShortLinkStatEvent::Redirect);
+    links.commit(StoredEventList::new(&[
+        ShortenerEvent::Create(Slug::from("promo"), Url::from("https://example.com")),
+        legacy.clone(),
+        legacy,
+    ]).unwrap()).unwrap();
+    let mut service = UrlShortenerService::new(Box::new(links), Box::new(mem_store::MemEventStore::new()), Box::new(gen::SimplestSlugGenerator));
+
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, 2);
+    service.handle_redirect(Slug::from("promo")).unwrap();
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, 3);
+}
+
+#[test]
+fn service_stats_as_of() {
+    use crate::cqrs::history::AsOf;
+    use crate::queries::HistoryQueryHandler;
+
+    let backend = mem_store::MemBackend::new();
+    let links = mem_store::MemEventStore::<crate::Stats>::with_backend(backend.clone(), mem_store::KeyMode::Exact);
+    let clicks = mem_store::MemEventStore::<crate::clicks::Clicks>::with_backend(backend, mem_store::KeyMode::Exact);
+    let mut service = UrlShortenerService::new(Box::new(links), Box::new(clicks), Box::new(gen::SimplestSlugGenerator));
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("b"))).unwrap();
+    service.handle_redirect(Slug::from("b")).unwrap();
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("a"))).unwrap();
+    service.handle_redirect(Slug::from("b")).unwrap();
+
+    let stats_as_of = |as_of| service.get_stats_as_of(as_of).unwrap()
+        .into_iter()
+        .map(|stats| (stats.link.slug.to_string(), stats.redirects))
+        .collect::<Vec<_>>();
+    assert_eq!(stats_as_of(AsOf::Position(0)), []);
+    assert_eq!(stats_as_of(AsOf::Position(2)), [("b".to_string(), 1)]);
+    assert_eq!(stats_as_of(AsOf::Position(3)), [("a".to_string(), 0), ("b".to_string(), 1)]);
+    assert_eq!(stats_as_of(AsOf::Time(u64::MAX)), [("a".to_string(), 0), ("b".to_string(), 2)]);
+    assert_eq!(stats_as_of(AsOf::Time(0)), []);
+}
+
+#[test]
+fn service_stats_as_of_position_needs_shared_log() {
+    use crate::cqrs::history::AsOf;
+    use crate::cqrs::store::EventStoreError;
+    use crate::queries::HistoryQueryHandler;
+
+    let mut service = create_service();
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("a"))).unwrap();
+    service.handle_redirect(Slug::from("a")).unwrap();
+    // the clicks storage of another backend has a log of its own
+    assert!(matches!(service.get_stats_as_of(AsOf::Position(1)), Err(EventStoreError::StorageError(_))));
+    let stats = service.get_stats_as_of(AsOf::Time(u64::MAX)).unwrap();
+    assert_eq!(stats.iter().map(|stats| stats.redirects).collect::<Vec<_>>(), [1]);
+
+    let service = sync::SyncUrlShortenerService::new(
+        Box::new(mem_store::MemEventStore::<super::Stats>::new()),
+        Box::new(mem_store::MemEventStore::new()),
+        Box::new(gen::SimplestSlugGenerator),
+    );
+    assert!(matches!(service.get_stats_as_of(AsOf::Position(0)), Err(EventStoreError::StorageError(_))));
+
+    // the same for the buffered redirects
+    let (mut service, _) = create_buffered_service(false);
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("a"))).unwrap();
+    assert!(matches!(service.get_stats_as_of(AsOf::Position(0)), Err(EventStoreError::StorageError(_))));
+}
+
+fn create_buffered_service(pending_in_stats: bool) -> (UrlShortenerService, std::sync::Arc<mem_store::MemEventStore<crate::clicks::Clicks>>) {
+    let clicks = std::sync::Arc::new(mem_store::MemEventStore::<crate::clicks::Clicks>::new());
+    let buffering = crate::clicks::RedirectBuffering {
+        max_pending: 3,
+        max_delay: std::time::Duration::from_secs(3600),
+        pending_in_stats,
+    };
+    let service = create_service_with_clicks(Box::new(std::sync::Arc::clone(&clicks))).with_redirect_buffering(buffering);
+    (service, clicks)
+}
+
+#[test]
+fn service_buffered_redirects() {
+    use crate::clicks::ClickEvent;
+    use crate::cqrs::store::EventStore;
+
+    let (mut service, clicks) = create_buffered_service(false);
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("promo"))).unwrap();
+    for _ in 0..5 {
+        service.handle_redirect(Slug::from("promo")).unwrap();
+    }
+    let events = clicks.fetch(&Slug::from("promo")).unwrap();
+    assert_eq!(events.events().iter().map(|e| e.event().clone()).collect::<Vec<_>>(), [ClickEvent::RedirectsCounted(Slug::from("promo"), 3)]);
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, 3);
+
+    service.flush_redirects().unwrap();
+    assert_eq!(clicks.fetch(&Slug::from("promo")).unwrap().len(), 2);
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, 5);
+    assert_eq!(service.handle_redirect(Slug::from("absent")), Err(ShortenerError::SlugNotFound));
+}
+
+#[test]
+fn service_buffered_redirects_survive_failed_commits() {
+    use crate::clicks::Clicks;
+    use crate::cqrs::store::{EventStore, EventStoreError, StoredEventList};
+    use crate::SlugRef;
+    use std::sync::{Arc, Mutex};
+
+    /// Fails the commits of the `broken` stream
+    struct FailingClicks {
+        inner: mem_store::MemEventStore<Clicks>,
+        broken: Mutex<Option<Slug>>,
+    }
+
+    impl EventStore<Clicks> for FailingClicks {
+        fn fetch(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Clicks>, EventStoreError> {
+            self.inner.fetch(aggregate_id)
+        }
+        fn is_exist(&self, aggregate_id: &SlugRef) -> Result<bool, EventStoreError> {
+            self.inner.is_exist(aggregate_id)
+        }
+        fn commit(&self, state: StoredEventList<Clicks>) -> Result<(), EventStoreError> {
+            if self.broken.lock().unwrap().as_deref() == Some(state.aggregate_id()) {
+                return Err(EventStoreError::StorageError("disk is gone".into()));
+            }
+            self.inner.commit(state)
+        }
+        fn remove(&self, aggregate_id: &SlugRef) -> Result<StoredEventList<Clicks>, EventStoreError> {
+            self.inner.remove(aggregate_id)
+        }
+    }
+
+    let clicks = Arc::new(FailingClicks { inner: mem_store::MemEventStore::new(), broken: Mutex::new(Some(Slug::from("broken"))) });
+    // every redirect takes all the pending counts
+    let buffering = crate::clicks::RedirectBuffering { max_pending: 100, max_delay: std::time::Duration::ZERO, pending_in_stats: false };
+    let mut service = create_service_with_clicks(Box::new(Arc::clone(&clicks))).with_redirect_buffering(buffering);
+    for slug in ["broken", "promo"] {
+        service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from(slug))).unwrap();
+    }
+
+    service.handle_redirect(Slug::from("broken")).unwrap();
+    service.handle_redirect(Slug::from("promo")).unwrap();
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, 1);
+    assert_eq!(service.get_stats(Slug::from("broken")).unwrap().redirects, 0);
+    assert!(service.flush_redirects().is_err());
+
+    *clicks.broken.lock().unwrap() = None;
+    service.flush_redirects().unwrap();
+    assert_eq!(service.get_stats(Slug::from("broken")).unwrap().redirects, 1);
+}
+
+#[test]
+fn service_buffered_redirects_in_stats() {
+    let (mut service, _clicks) = create_buffered_service(true);
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("promo"))).unwrap();
+    for _ in 0..5 {
+        service.handle_redirect(Slug::from("promo")).unwrap();
+    }
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, 5);
+}
+
+#[test]
+fn service_buffered_redirects_are_flushed_on_drop() {
+    use crate::cqrs::store::EventStore;
+
+    let (mut service, clicks) = create_buffered_service(false);
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("promo"))).unwrap();
+    service.handle_redirect(Slug::from("promo")).unwrap();
+    assert!(!clicks.is_exist(&Slug::from("promo")).unwrap());
+    drop(service);
+    assert_eq!(clicks.fetch(&Slug::from("promo")).unwrap().snapshot().aggregate().redirects, 1);
+}
+
+fn create_sync_service(key_mode: mem_store::KeyMode, generator: Box<dyn gen::SlugGenerator + Send + Sync>) -> std::sync::Arc<sync::SyncUrlShortenerService> {
+    let storage = Box::new(mem_store::MemEventStore::<super::Stats>::with_key_mode(key_mode));
+    let clicks = Box::new(mem_store::MemEventStore::<crate::clicks::Clicks>::new());
+    std::sync::Arc::new(sync::SyncUrlShortenerService::new(storage, clicks, generator))
+}
+
+/// Runs `f(thread_index)` in `threads` threads at once
+fn run_in_threads<F: Fn(usize) + Send + Sync + 'static>(threads: usize, f: F) {
+    let f = std::sync::Arc::new(f);
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(threads));
+    let handles = (0..threads)
+        .map(|i| {
+            let (f, barrier) = (f.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                barrier.wait();
+                f(i)
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn sync_service_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<sync::SyncUrlShortenerService>();
+}
+
+#[test]
+fn sync_service_concurrent_redirects_are_exact() {
+    use commands::SyncCommandHandler;
+    const THREADS: usize = 8;
+    const REDIRECTS: usize = 500;
+    let slugs = ["a", "b", "c"];
+    let service = create_sync_service(mem_store::KeyMode::Exact, Box::new(gen::SimplestSlugGenerator));
+    for slug in slugs {
+        service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from(slug))).unwrap();
+    }
+
+    let shared = service.clone();
+    run_in_threads(THREADS, move |i| {
+        for n in 0..REDIRECTS {
+            let slug = slugs[(i + n) % slugs.len()];
+            shared.handle_redirect(Slug::from(slug)).unwrap();
+        }
+    });
+
+    let total = slugs
+        .iter()
+        .map(|slug| service.get_stats(Slug::from(*slug)).unwrap().redirects)
+        .sum::<u64>();
+    assert_eq!(total, (THREADS * REDIRECTS) as u64);
+    for slug in slugs {
+        let redirects = service.get_stats(Slug::from(slug)).unwrap().redirects;
+        assert!(redirects >= ((THREADS * REDIRECTS) / slugs.len() - THREADS) as u64, "{slug}: {redirects}");
+    }
+}
+
+#[test]
+fn sync_service_concurrent_normalized_redirects_are_exact() {
+    use commands::SyncCommandHandler;
+    const THREADS: usize = 6;
+    const REDIRECTS: u64 = 300;
+    let service = create_sync_service(mem_store::KeyMode::Normalized, Box::new(gen::SimplestSlugGenerator));
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("Promo"))).unwrap();
+
+    let shared = service.clone();
+    run_in_threads(THREADS, move |i| {
+        let slug = ["promo", "PROMO", "Promo"][i % 3];
+        for _ in 0..REDIRECTS {
+            shared.handle_redirect(Slug::from(slug)).unwrap();
+        }
+    });
+    assert_eq!(service.get_stats(Slug::from("pRoMo")).unwrap().redirects, THREADS as u64 * REDIRECTS);
+}
+
+#[test]
+fn sync_service_concurrent_buffered_redirects_are_exact() {
+    use commands::SyncCommandHandler;
+    const THREADS: usize = 8;
+    const REDIRECTS: u64 = 250;
+    let buffering = crate::clicks::RedirectBuffering { max_pending: 7, ..Default::default() };
+    let service = sync::SyncUrlShortenerService::new(
+        Box::new(mem_store::MemEventStore::<super::Stats>::new()),
+        Box::new(mem_store::MemEventStore::new()),
+        Box::new(gen::SimplestSlugGenerator),
+    ).with_redirect_buffering(buffering);
+    let service = std::sync::Arc::new(service);
+    service.handle_create_short_link(VALID_URL.to_owned(), Some(Slug::from("promo"))).unwrap();
+
+    let shared = service.clone();
+    run_in_threads(THREADS, move |_| {
+        for _ in 0..REDIRECTS {
+            shared.handle_redirect(Slug::from("promo")).unwrap();
+        }
+    });
+    service.flush_redirects().unwrap();
+    assert_eq!(service.get_stats(Slug::from("promo")).unwrap().redirects, THREADS as u64 * REDIRECTS);
+}
+
+#[test]
+fn sync_service_concurrent_creation_of_the_same_slug() {
+    use commands::SyncCommandHandler;
+    const THREADS: usize = 8;
+    const SLUGS: usize = 50;
+    let service = create_sync_service(mem_store::KeyMode::Exact, Box::new(gen::SimplestSlugGenerator));
+    let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    let (shared, counter) = (service.clone(), created.clone());
+    run_in_threads(THREADS, move |i| {
+        for n in 0..SLUGS {
+            let url = crate::Url(test_url!(i));
+            match shared.handle_create_short_link(url, Some(Slug(format!("slug{n}")))) {
+                Ok(_) => { counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst); }
+                Err(ShortenerError::SlugAlreadyInUse) => {}
+                Err(e) => panic!("{e:?}"),
+            }
+        }
+    });
+
+    assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), SLUGS);
+    for n in 0..SLUGS {
+        // the stream has the only create event
+        assert_eq!(service.get_stats(Slug(format!("slug{n}"))).unwrap().redirects, 0);
+    }
+}
+
+#[test]
+fn service_concurrent_creation_without_locks() {
+    use crate::cqrs::store::EventStore;
+    // services of their own over the shared store, the store decides the winner
+    const THREADS: usize = 8;
+    let storage = std::sync::Arc::new(mem_store::MemEventStore::<super::Stats>::new());
+    let winners = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let (shared, collected) = (storage.clone(), winners.clone());
+    run_in_threads(THREADS, move |i| {
+        let mut service = UrlShortenerService::new(Box::new(shared.clone()), Box::new(mem_store::MemEventStore::new()), Box::new(gen::SimplestSlugGenerator));
+        match service.handle_create_short_link(crate::Url(test_url!(i)), Some(Slug::from("promo"))) {
+            Ok(link) => collected.lock().unwrap().push(link),
+            Err(ShortenerError::SlugAlreadyInUse) => {}
+            Err(e) => panic!("{e:?}"),
+        }
+    });
+
+    let winners = winners.lock().unwrap();
+    assert_eq!(winners.len(), 1);
+    let events = storage.fetch(&Slug::from("promo")).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events.snapshot().aggregate().link.url, winners[0].url);
+}
+
+/// Generates the same slugs for any input, so concurrent creations collide
+struct BumpSlugGenerator;
+
+impl gen::SlugGenerator for BumpSlugGenerator {
+    fn generate(&self, _input: &str, bump: u16) -> Slug {
+        Slug(format!("bump{bump}"))
+    }
+}
+
+#[test]
+fn sync_service_concurrent_generated_slugs_are_unique() {
+    use commands::SyncCommandHandler;
+    const THREADS: usize = 8;
+    const LINKS: usize = 25;
+    let service = create_sync_service(mem_store::KeyMode::Exact, Box::new(BumpSlugGenerator));
+    let links = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let (shared, collected) = (service.clone(), links.clone());
+    run_in_threads(THREADS, move |i| {
+        for n in 0..LINKS {
+            let link = shared.handle_create_short_link(crate::Url(test_url!(i)), None).unwrap();
+            collected.lock().unwrap().push((link, i, n));
+        }
+    });
+
+    let links = links.lock().unwrap();
+    let mut slugs = links.iter().map(|(link, _, _)| link.slug.0.clone()).collect::<Vec<_>>();
+    slugs.sort();
+    slugs.dedup();
+    assert_eq!(slugs.len(), THREADS * LINKS);
+    for (link, i, _) in links.iter() {
+        assert_eq!(service.get_stats(link.slug.clone()).unwrap().link.url.0, test_url!(i));
+    }
+}
+
+mod decide {
+    use crate::cqrs::fixture::Fixture;
+    use crate::redirect::RedirectConfig;
+    use crate::{ShortLinkStatEvent, ShortenerCommand, ShortenerError, ShortenerEvent, Slug, Stats, Url};
+
+    fn created() -> ShortenerEvent {
+        ShortenerEvent::Create(Slug::from("promo"), Url::from("https://example.com"))
+    }
+
+    fn redirected() -> ShortenerEvent {
+        ShortenerEvent::ShortLinkStatEvent(Slug::from("promo"), ShortLinkStatEvent::Redirect)
+    }
+
+    #[test]
+    fn create() {
+        Fixture::<Stats>::given_no_previous_events()
+            .when(ShortenerCommand::Create(Slug::from("promo"), Url::from("https://example.com")))
+            .then_expect_events([created()]);
+    }
+
+    #[test]
+    fn create_existing() {
+        Fixture::<Stats>::given([created(), redirected()])
+            .when(ShortenerCommand::Create(Slug::from("promo"), Url::from("https://example.org")))
+            .then_expect_error(ShortenerError::SlugAlreadyInUse);
+    }
+
+    #[test]
+    fn create_with_invalid_url() {
+        let command = ShortenerCommand::Create(Slug::from("promo"), Url::from("http://[:::1]"));
+        Fixture::<Stats>::given_no_previous_events()
+            .when(command.clone())
+            .then_expect_error(ShortenerError::InvalidUrl);
+        // the url is checked first, as it's the error of the request itself
+        Fixture::<Stats>::given([created()]).when(command).then_expect_error(ShortenerError::InvalidUrl);
+    }
+
+    #[test]
+    fn legacy_redirects() {
+        let state = Fixture::<Stats>::given([created(), redirected(), redirected()])
+            .when(ShortenerCommand::ConfigureRedirect(RedirectConfig::default()))
+            .then_expect_events([ShortenerEvent::RedirectConfigured(Slug::from("promo"), RedirectConfig::default())]);
+        assert_eq!(state.redirects, 2);
+    }
+
+    #[test]
+    fn commands_of_absent_link() {
+        Fixture::<Stats>::given_no_previous_events()
+            .when(ShortenerCommand::ConfigureRedirect(RedirectConfig::default()))
+            .then_expect_error(ShortenerError::SlugNotFound);
+    }
+
+    #[test]
+    fn configure_redirect() {
+        let config = RedirectConfig { preview: true, ..Default::default() };
+        Fixture::<Stats>::given([created(), redirected()])
+            .when(ShortenerCommand::ConfigureRedirect(config))
+            .then_expect_events([ShortenerEvent::RedirectConfigured(Slug::from("promo"), config)]);
+    }
+}